@@ -272,17 +272,24 @@ pub async fn run_build(
     } else {
         tracing::info!("Running tests");
 
-        test::run_test(
+        let test_result = test::run_test(
             &result,
             &TestConfiguration {
                 test_prefix: test_dir.clone(),
                 target_platform: Some(output.build_configuration.target_platform),
                 keep_test_prefix: tool_configuration.no_clean,
                 channels,
+                multi_progress: Some(tool_configuration.multi_progress_indicator.clone()),
+                ..Default::default()
             },
         )
         .await
         .into_diagnostic()?;
+
+        if !test_result.all_passed() {
+            tracing::error!("{}", test_result.summary());
+            return Err(miette::miette!("Tests failed for {:?}", result));
+        }
     }
 
     if !tool_configuration.no_clean {
@@ -27,15 +27,31 @@ fn package_record_from_index_json<T: Read>(
     let md5_result = rattler_digest::compute_file_digest::<rattler_digest::Md5>(file)?;
     let size = std::fs::metadata(file)?.len();
 
-    let package_record = PackageRecord {
+    Ok(package_record_from_index(
+        index,
+        Some(md5_result),
+        Some(sha256_result),
+        Some(size),
+    ))
+}
+
+/// Create the repodata record of a package from its `info/index.json` and the hashes and size of
+/// the package file (if known)
+pub fn package_record_from_index(
+    index: IndexJson,
+    md5: Option<rattler_digest::Md5Hash>,
+    sha256: Option<rattler_digest::Sha256Hash>,
+    size: Option<u64>,
+) -> PackageRecord {
+    PackageRecord {
         name: index.name,
         version: index.version,
         build: index.build,
         build_number: index.build_number,
         subdir: index.subdir.unwrap_or_else(|| "unknown".to_string()),
-        md5: Some(md5_result),
-        sha256: Some(sha256_result),
-        size: Some(size),
+        md5,
+        sha256,
+        size,
         arch: index.arch,
         platform: index.platform,
         depends: index.depends,
@@ -49,8 +65,7 @@ fn package_record_from_index_json<T: Read>(
         legacy_bz2_md5: None,
         legacy_bz2_size: None,
         purls: Default::default(),
-    };
-    Ok(package_record)
+    }
 }
 
 fn package_record_from_tar_bz2(file: &Path) -> Result<PackageRecord, std::io::Error> {
@@ -71,7 +86,8 @@ fn package_record_from_tar_bz2(file: &Path) -> Result<PackageRecord, std::io::Er
 
 fn package_record_from_conda(file: &Path) -> Result<PackageRecord, std::io::Error> {
     let reader = std::fs::File::open(file)?;
-    let mut archive = seek::stream_conda_info(reader).expect("Could not open conda file");
+    let mut archive = seek::stream_conda_info(reader)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
     for entry in archive.entries()?.flatten() {
         let mut entry = entry;
@@ -169,12 +169,12 @@ async fn main() -> miette::Result<()> {
 
     match args.subcommand {
         SubCommands::Build(args) => run_build_from_args(args, multi_progress).await,
-        SubCommands::Test(args) => run_test_from_args(args).await,
+        SubCommands::Test(args) => run_test_from_args(args, multi_progress).await,
         SubCommands::Rebuild(args) => rebuild_from_args(args).await,
     }
 }
 
-async fn run_test_from_args(args: TestOpts) -> miette::Result<()> {
+async fn run_test_from_args(args: TestOpts, multi_progress: MultiProgress) -> miette::Result<()> {
     let package_file = canonicalize(args.package_file).into_diagnostic()?;
     let test_prefix = PathBuf::from("test-prefix");
     fs::create_dir_all(&test_prefix).into_diagnostic()?;
@@ -184,11 +184,18 @@ async fn run_test_from_args(args: TestOpts) -> miette::Result<()> {
         target_platform: Some(Platform::current()),
         keep_test_prefix: false,
         channels: vec!["conda-forge".to_string(), "./output".to_string()],
+        multi_progress: Some(multi_progress),
+        ..Default::default()
     };
 
-    test::run_test(&package_file, &test_options)
+    let test_result = test::run_test(&package_file, &test_options)
         .await
         .into_diagnostic()?;
+    tracing::info!("{}", test_result.summary());
+
+    if !test_result.all_passed() {
+        return Err(miette::miette!("Tests failed for {:?}", package_file));
+    }
 
     Ok(())
 }
@@ -296,6 +303,9 @@ async fn run_build_from_args(args: BuildOpts, multi_progress: MultiProgress) ->
         no_test: args.no_test,
         use_zstd: args.common.use_zstd,
         use_bz2: args.common.use_bz2,
+        cache_dir: None,
+        offline: false,
+        repodata_patches: None,
     };
 
     let mut subpackages = BTreeMap::new();
@@ -424,6 +434,9 @@ async fn rebuild_from_args(args: RebuildOpts) -> miette::Result<()> {
         no_test: args.no_test,
         use_zstd: args.common.use_zstd,
         use_bz2: args.common.use_bz2,
+        cache_dir: None,
+        offline: false,
+        repodata_patches: None,
     };
 
     output
@@ -27,6 +27,7 @@ use rattler_package_streaming::write::{
 
 use crate::macos;
 use crate::metadata::Output;
+use crate::recipe::parser::Test;
 use crate::{linux, post};
 
 #[derive(Debug, thiserror::Error)]
@@ -641,99 +642,111 @@ fn filter_pyc(path: &Path, new_files: &HashSet<PathBuf>) -> bool {
 }
 
 fn write_test_files(output: &Output, tmp_dir_path: &Path) -> Result<Vec<PathBuf>, PackagingError> {
-    let mut test_files = Vec::new();
     let test = output.recipe.test();
-    if !test.is_empty() {
-        let test_folder = tmp_dir_path.join("info/test/");
-        fs::create_dir_all(&test_folder)?;
-
-        if !test.imports().is_empty() {
-            let test_file = test_folder.join("run_test.py");
-            let mut file = File::create(&test_file)?;
-            for el in test.imports() {
-                writeln!(file, "import {}\n", el)?;
-            }
-            test_files.push(test_file);
+    if test.is_empty() {
+        return Ok(Vec::new());
+    }
+    write_recipe_tests(
+        test,
+        &output.build_configuration.target_platform,
+        &output.build_configuration.directories.recipe_dir,
+        &output.build_configuration.directories.work_dir,
+        &tmp_dir_path.join("info/test/"),
+    )
+}
+
+/// Write the test files of the `test` section of a recipe into the test folder (the `info/test`
+/// folder of a package). The `files` are copied from the recipe directory and the
+/// `source_files` from the work directory.
+pub(crate) fn write_recipe_tests(
+    test: &Test,
+    target_platform: &Platform,
+    recipe_dir: &Path,
+    work_dir: &Path,
+    test_folder: &Path,
+) -> Result<Vec<PathBuf>, PackagingError> {
+    let mut test_files = Vec::new();
+    fs::create_dir_all(test_folder)?;
+
+    if !test.imports().is_empty() {
+        let test_file = test_folder.join("run_test.py");
+        let mut file = File::create(&test_file)?;
+        for el in test.imports() {
+            writeln!(file, "import {}\n", el)?;
         }
+        test_files.push(test_file);
+    }
 
-        if !test.commands().is_empty() {
-            let mut command_files = vec![];
-            let target_platform = &output.build_configuration.target_platform;
-            if target_platform.is_windows() || target_platform == &Platform::NoArch {
-                command_files.push(test_folder.join("run_test.bat"));
-            }
-            if target_platform.is_unix() || target_platform == &Platform::NoArch {
-                command_files.push(test_folder.join("run_test.sh"));
-            }
+    if !test.commands().is_empty() {
+        let mut command_files = vec![];
+        if target_platform.is_windows() || target_platform == &Platform::NoArch {
+            command_files.push(test_folder.join("run_test.bat"));
+        }
+        if target_platform.is_unix() || target_platform == &Platform::NoArch {
+            command_files.push(test_folder.join("run_test.sh"));
+        }
 
-            for cf in command_files {
-                let mut file = File::create(&cf)?;
-                for el in test.commands() {
-                    writeln!(file, "{}\n", el)?;
-                }
-                test_files.push(cf);
+        for cf in command_files {
+            let mut file = File::create(&cf)?;
+            for el in test.commands() {
+                writeln!(file, "{}\n", el)?;
             }
+            test_files.push(cf);
         }
+    }
 
-        if !test.requires().is_empty() {
-            let test_dependencies = test.requires();
-            let test_file = test_folder.join("test_time_dependencies.json");
-            let mut file = File::create(&test_file)?;
-            file.write_all(serde_json::to_string(test_dependencies)?.as_bytes())?;
-            test_files.push(test_file);
-        }
+    if !test.requires().is_empty() {
+        let test_dependencies = test.requires();
+        let test_file = test_folder.join("test_time_dependencies.json");
+        let mut file = File::create(&test_file)?;
+        file.write_all(serde_json::to_string(test_dependencies)?.as_bytes())?;
+        test_files.push(test_file);
+    }
+
+    if !test.files().is_empty() {
+        let globs = test.files();
+        let include_globs = globs
+            .iter()
+            .filter(|glob| !glob.trim_start().starts_with('~'))
+            .map(AsRef::as_ref)
+            .collect::<Vec<&str>>();
+
+        let exclude_globs = globs
+            .iter()
+            .filter(|glob| glob.trim_start().starts_with('~'))
+            .map(AsRef::as_ref)
+            .collect::<Vec<&str>>();
 
-        if !test.files().is_empty() {
-            let globs = test.files();
-            let include_globs = globs
-                .iter()
-                .filter(|glob| !glob.trim_start().starts_with('~'))
-                .map(AsRef::as_ref)
-                .collect::<Vec<&str>>();
-
-            let exclude_globs = globs
-                .iter()
-                .filter(|glob| glob.trim_start().starts_with('~'))
-                .map(AsRef::as_ref)
-                .collect::<Vec<&str>>();
-
-            let copy_dir = crate::source::copy_dir::CopyDir::new(
-                &output.build_configuration.directories.recipe_dir,
-                &test_folder,
-            )
+        let copy_dir = crate::source::copy_dir::CopyDir::new(recipe_dir, test_folder)
             .with_include_globs(include_globs)
             .with_exclude_globs(exclude_globs)
             .use_gitignore(true)
             .run()?;
 
-            test_files.extend(copy_dir.copied_pathes().iter().cloned());
-        }
+        test_files.extend(copy_dir.copied_pathes().iter().cloned());
+    }
+
+    if !test.source_files().is_empty() {
+        let globs = test.source_files();
+        let include_globs = globs
+            .iter()
+            .filter(|glob| !glob.trim_start().starts_with('~'))
+            .map(AsRef::as_ref)
+            .collect::<Vec<&str>>();
 
-        if !test.source_files().is_empty() {
-            let globs = test.source_files();
-            let include_globs = globs
-                .iter()
-                .filter(|glob| !glob.trim_start().starts_with('~'))
-                .map(AsRef::as_ref)
-                .collect::<Vec<&str>>();
-
-            let exclude_globs = globs
-                .iter()
-                .filter(|glob| glob.trim_start().starts_with('~'))
-                .map(AsRef::as_ref)
-                .collect::<Vec<&str>>();
-
-            let copy_dir = crate::source::copy_dir::CopyDir::new(
-                &output.build_configuration.directories.work_dir,
-                &test_folder,
-            )
+        let exclude_globs = globs
+            .iter()
+            .filter(|glob| glob.trim_start().starts_with('~'))
+            .map(AsRef::as_ref)
+            .collect::<Vec<&str>>();
+
+        let copy_dir = crate::source::copy_dir::CopyDir::new(work_dir, test_folder)
             .with_include_globs(include_globs)
             .with_exclude_globs(exclude_globs)
             .use_gitignore(true)
             .run()?;
 
-            test_files.extend(copy_dir.copied_pathes().iter().cloned());
-        }
+        test_files.extend(copy_dir.copied_pathes().iter().cloned());
     }
 
     Ok(test_files)
@@ -2,5 +2,6 @@
 //! Render the dependencies to a final recipe
 
 pub mod pin;
+pub mod repodata_patches;
 pub mod resolved_dependencies;
 pub mod solver;
@@ -0,0 +1,133 @@
+//! Apply repodata patches (in the format of the `patch_instructions.json` of conda-forge) to
+//! the records that are loaded for a solve
+//!
+//! ```json
+//! {
+//!   "patch_instructions_version": 1,
+//!   "packages": {"foo-1.0-0.tar.bz2": {"depends": ["bar <2"]}},
+//!   "packages.conda": {"foo-1.0-0.conda": {"depends": ["bar <2"]}},
+//!   "remove": ["foo-0.9-0.tar.bz2"],
+//!   "revoke": ["foo-0.9-0.conda"]
+//! }
+//! ```
+//!
+//! The fields of a patch replace the fields of the record (a `null` value removes the field).
+//! Removed and revoked packages are not available to the solver.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use rattler_conda_types::{PackageRecord, RepoDataRecord};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// The patch instructions of a channel
+#[derive(Debug, Default, Deserialize)]
+pub struct RepodataPatches {
+    /// The patches of `.tar.bz2` packages, keyed by file name
+    #[serde(default)]
+    packages: HashMap<String, Map<String, Value>>,
+    /// The patches of `.conda` packages, keyed by file name
+    #[serde(default, rename = "packages.conda")]
+    conda_packages: HashMap<String, Map<String, Value>>,
+    /// File names of packages that are removed
+    #[serde(default)]
+    remove: HashSet<String>,
+    /// File names of packages that are revoked
+    #[serde(default)]
+    revoke: HashSet<String>,
+}
+
+impl RepodataPatches {
+    /// Read the patch instructions from a JSON file
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs_err::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("failed to parse the repodata patches {:?}: {}", path, e))
+    }
+
+    /// Patch a single record
+    fn patch_record(patch: &Map<String, Value>, record: &mut PackageRecord) -> anyhow::Result<()> {
+        let Value::Object(mut fields) = serde_json::to_value(&*record)? else {
+            unreachable!("a package record is serialized as an object");
+        };
+        for (key, value) in patch {
+            match value {
+                Value::Null => fields.remove(key),
+                value => fields.insert(key.clone(), value.clone()),
+            };
+        }
+        *record = serde_json::from_value(Value::Object(fields))?;
+        Ok(())
+    }
+
+    /// Apply the patches to the records (grouped by channel and subdirectory)
+    pub fn apply(&self, repodatas: &mut [Vec<RepoDataRecord>]) -> anyhow::Result<()> {
+        for records in repodatas {
+            records.retain(|record| {
+                !self.remove.contains(&record.file_name) && !self.revoke.contains(&record.file_name)
+            });
+            for record in records {
+                let patches = if record.file_name.ends_with(".conda") {
+                    &self.conda_packages
+                } else {
+                    &self.packages
+                };
+                if let Some(patch) = patches.get(&record.file_name) {
+                    Self::patch_record(patch, &mut record.package_record).map_err(|e| {
+                        anyhow::anyhow!("failed to patch {}: {}", record.file_name, e)
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(file_name: &str) -> RepoDataRecord {
+        let package_record = serde_json::from_str(
+            r#"{"name": "foo", "version": "1.0", "build": "0", "build_number": 0, "subdir": "linux-64", "depends": ["bar"], "license": "MIT"}"#,
+        )
+        .unwrap();
+        RepoDataRecord {
+            package_record,
+            file_name: file_name.to_string(),
+            url: url::Url::parse(&format!("https://example.com/linux-64/{}", file_name)).unwrap(),
+            channel: "https://example.com/".to_string(),
+        }
+    }
+
+    #[test]
+    fn patches_are_applied() {
+        let patches: RepodataPatches = serde_json::from_str(
+            r#"{
+                "packages": {"foo-1.0-0.tar.bz2": {"depends": ["bar <2"], "license": null}},
+                "packages.conda": {"foo-1.0-0.conda": {"depends": ["bar <3"]}},
+                "remove": ["foo-0.9-0.tar.bz2"],
+                "revoke": ["foo-0.9-0.conda"]
+            }"#,
+        )
+        .unwrap();
+
+        let mut repodatas = vec![vec![
+            record("foo-1.0-0.tar.bz2"),
+            record("foo-1.0-0.conda"),
+            record("foo-0.9-0.tar.bz2"),
+            record("foo-0.9-0.conda"),
+        ]];
+        patches.apply(&mut repodatas).unwrap();
+
+        let records = &repodatas[0];
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].package_record.depends, ["bar <2"]);
+        assert_eq!(records[0].package_record.license, None);
+        assert_eq!(records[1].package_record.depends, ["bar <3"]);
+        assert_eq!(records[1].package_record.license.as_deref(), Some("MIT"));
+    }
+}
@@ -20,7 +20,10 @@ use rattler_conda_types::{
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use super::{pin::PinError, solver::create_environment};
+use super::{
+    pin::PinError,
+    solver::{create_environment, ChannelPriority, SolverBackend},
+};
 use crate::recipe::parser::Dependency;
 use crate::render::solver::install_packages;
 use serde_with::{serde_as, DisplayFromStr};
@@ -387,7 +390,9 @@ pub async fn install_environments(
     output: &Output,
     tool_configuration: tool_configuration::Configuration,
 ) -> Result<(), ResolveError> {
-    let cache_dir = rattler::default_cache_dir().expect("Could not get default cache dir");
+    let cache_dir = tool_configuration
+        .cache_dir()
+        .expect("Could not get the cache dir");
 
     let dependencies = output.finalized_dependencies.as_ref().unwrap();
 
@@ -430,7 +435,9 @@ pub async fn resolve_dependencies(
     channels: &[String],
     tool_configuration: tool_configuration::Configuration,
 ) -> Result<FinalizedDependencies, ResolveError> {
-    let cache_dir = rattler::default_cache_dir().expect("Could not get default cache dir");
+    let cache_dir = tool_configuration
+        .cache_dir()
+        .expect("Could not get the cache dir");
     let pkgs_dir = cache_dir.join("pkgs");
 
     let reqs = &output.recipe.requirements();
@@ -445,6 +452,9 @@ pub async fn resolve_dependencies(
             &output.build_configuration.build_platform,
             &output.build_configuration.directories.build_prefix,
             channels,
+            &[],
+            ChannelPriority::default(),
+            SolverBackend::default(),
             &tool_configuration,
         )
         .await
@@ -510,6 +520,9 @@ pub async fn resolve_dependencies(
             &output.build_configuration.host_platform,
             &output.build_configuration.directories.host_prefix,
             channels,
+            &[],
+            ChannelPriority::default(),
+            SolverBackend::default(),
             &tool_configuration,
         )
         .await
@@ -5,21 +5,22 @@ use futures::{stream, stream::FuturesUnordered, FutureExt, StreamExt, TryFutureE
 use indicatif::{HumanBytes, ProgressBar, ProgressState, ProgressStyle};
 use rattler::{
     install::{link_package, InstallDriver, InstallOptions, Transaction, TransactionOperation},
-    package_cache::PackageCache,
+    package_cache::{CacheKey, PackageCache},
 };
 use rattler_conda_types::{
-    Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, Platform, PrefixRecord,
-    RepoDataRecord,
+    package::ArchiveIdentifier, Channel, ChannelConfig, GenericVirtualPackage, MatchSpec, Platform,
+    PrefixRecord, RepoDataRecord,
 };
 use rattler_networking::AuthenticatedClient;
 use rattler_repodata_gateway::fetch::{
-    CacheResult, DownloadProgress, FetchRepoDataError, FetchRepoDataOptions,
+    CacheAction, CacheResult, DownloadProgress, FetchRepoDataError, FetchRepoDataOptions,
 };
 use rattler_repodata_gateway::sparse::SparseRepoData;
-use rattler_solve::{resolvo::Solver, SolverImpl, SolverTask};
+use rattler_solve::{SolverImpl, SolverTask};
 
 use std::{
     borrow::Cow,
+    collections::HashMap,
     fmt::Write,
     future::ready,
     io::ErrorKind,
@@ -27,8 +28,12 @@ use std::{
     time::Duration,
 };
 use tokio::task::JoinHandle;
+use tracing::Instrument;
 
-use crate::tool_configuration;
+use crate::{
+    render::repodata_patches::RepodataPatches, tool_configuration,
+    windows::path::extended_length_path,
+};
 
 fn print_as_table(packages: &Vec<RepoDataRecord>) {
     let mut table = Table::new();
@@ -63,26 +68,314 @@ fn print_as_table(packages: &Vec<RepoDataRecord>) {
     tracing::info!("\n{table}");
 }
 
+/// How the channels are prioritized when solving an environment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ChannelPriority {
+    /// Packages can come from any of the channels, the best matching package is used
+    #[default]
+    Disabled,
+    /// A package is only taken from the first channel (in the order in which the channels are
+    /// given) that contains a package with the same name
+    Strict,
+}
+
+/// The solver that is used to solve an environment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SolverBackend {
+    /// The Rust-native solver `resolvo`
+    #[default]
+    Resolvo,
+    /// `libsolv` (only available if rattler-build is built with the `libsolv` feature)
+    Libsolv,
+}
+
+impl SolverBackend {
+    /// Solve the task with this solver. Fails if the solver is not compiled in, instead of falling
+    /// back to another solver.
+    fn solve(
+        self,
+        task: SolverTask<&[Vec<RepoDataRecord>]>,
+    ) -> anyhow::Result<Vec<RepoDataRecord>> {
+        match self {
+            SolverBackend::Resolvo => Ok(rattler_solve::resolvo::Solver.solve(task)?),
+            #[cfg(feature = "libsolv")]
+            SolverBackend::Libsolv => Ok(rattler_solve::libsolv_c::Solver.solve(task)?),
+            #[cfg(not(feature = "libsolv"))]
+            SolverBackend::Libsolv => anyhow::bail!(
+                "the libsolv solver is not available, rattler-build was built without the `libsolv` feature"
+            ),
+        }
+    }
+}
+
+/// Remove the records that are not allowed by the channel priority. The repodatas have to be
+/// ordered like the channels.
+fn apply_channel_priority(
+    repodatas: Vec<Vec<RepoDataRecord>>,
+    channel_priority: ChannelPriority,
+) -> Vec<Vec<RepoDataRecord>> {
+    if channel_priority == ChannelPriority::Disabled {
+        return repodatas;
+    }
+
+    let mut first_channel = HashMap::new();
+    repodatas
+        .into_iter()
+        .map(|records| {
+            records
+                .into_iter()
+                .filter(|record| {
+                    let channel = first_channel
+                        .entry(record.package_record.name.clone())
+                        .or_insert_with(|| record.channel.clone());
+                    *channel == record.channel
+                })
+                .collect()
+        })
+        .collect()
+}
+
 pub async fn create_environment(
     specs: &[MatchSpec],
     target_platform: &Platform,
     target_prefix: &Path,
     channels: &[String],
+    virtual_packages: &[GenericVirtualPackage],
+    channel_priority: ChannelPriority,
+    solver: SolverBackend,
     tool_configuration: &tool_configuration::Configuration,
 ) -> anyhow::Result<Vec<RepoDataRecord>> {
-    let channel_config = ChannelConfig::default();
-    // Parse the specs from the command line. We do this explicitly instead of allow clap to deal
-    // with this because we need to parse the `channel_config` when parsing matchspecs.
-
-    // Find the default cache directory. Create it if it doesn't exist yet.
-    let cache_dir = rattler::default_cache_dir()?;
-
     tracing::info!("\nResolving for environment specs:");
     for spec in specs {
         tracing::info!(" - {}", spec);
     }
     tracing::info!("\n");
 
+    // Determine the packages that are currently installed in the environment.
+    let installed_packages = find_installed_packages(target_prefix, 100)
+        .await
+        .context("failed to determine currently installed packages")?;
+
+    let required_packages = solve_environment(
+        specs,
+        installed_packages
+            .iter()
+            .map(|record| record.repodata_record.clone())
+            .collect(),
+        channels,
+        *target_platform,
+        virtual_packages,
+        channel_priority,
+        solver,
+        tool_configuration,
+    )
+    .instrument(tracing::info_span!("solve", specs = specs.len()))
+    .await?;
+
+    install_environment(
+        &required_packages,
+        target_platform,
+        target_prefix,
+        tool_configuration,
+    )
+    .await?;
+
+    Ok(required_packages)
+}
+
+/// Install the given packages (e.g. the packages of a solved environment or of a lockfile) into
+/// the prefix, without solving
+pub async fn install_environment(
+    packages: &Vec<RepoDataRecord>,
+    target_platform: &Platform,
+    target_prefix: &Path,
+    tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<()> {
+    let cache_dir = tool_configuration.cache_dir()?;
+    if tool_configuration.offline {
+        ensure_available_offline(packages, &cache_dir)?;
+    }
+
+    install_packages(
+        packages,
+        target_platform,
+        target_prefix,
+        &cache_dir,
+        tool_configuration,
+    )
+    .instrument(tracing::info_span!(
+        "install_packages",
+        packages = packages.len()
+    ))
+    .await
+}
+
+/// Remove the package with the given name from the prefix, by executing the transaction from
+/// the installed packages to the installed packages without it. Packages that depend on it are
+/// not removed.
+pub async fn remove_package(
+    name: &str,
+    target_platform: &Platform,
+    target_prefix: &Path,
+    tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<()> {
+    let installed = find_installed_packages(target_prefix, 100).await?;
+    let desired = installed
+        .iter()
+        .filter(|record| {
+            !record
+                .repodata_record
+                .package_record
+                .name
+                .as_normalized()
+                .eq_ignore_ascii_case(name)
+        })
+        .map(|record| record.repodata_record.clone())
+        .collect::<Vec<_>>();
+    if desired.len() == installed.len() {
+        anyhow::bail!("{} is not installed in {:?}", name, target_prefix);
+    }
+
+    let transaction = Transaction::from_current_and_desired(installed, desired, *target_platform)?;
+    execute_transaction(
+        transaction,
+        target_prefix,
+        &tool_configuration.cache_dir()?,
+        tool_configuration.client.clone(),
+        tool_configuration.multi_progress_indicator.clone(),
+    )
+    .instrument(tracing::info_span!("remove_package", package = name))
+    .await
+}
+
+/// Install the packages into a prefix that already contains some of them (e.g. a cloned
+/// environment), by executing the transaction from the installed packages to the given packages.
+/// Only the packages that are not installed yet are linked.
+pub async fn install_missing_packages(
+    packages: &[RepoDataRecord],
+    target_platform: &Platform,
+    target_prefix: &Path,
+    tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<()> {
+    let installed = find_installed_packages(target_prefix, 100).await?;
+    let transaction =
+        Transaction::from_current_and_desired(installed, packages.to_vec(), *target_platform)?;
+    if transaction.operations.is_empty() {
+        return Ok(());
+    }
+    execute_transaction(
+        transaction,
+        target_prefix,
+        &tool_configuration.cache_dir()?,
+        tool_configuration.client.clone(),
+        tool_configuration.multi_progress_indicator.clone(),
+    )
+    .instrument(tracing::info_span!(
+        "install_packages",
+        packages = packages.len()
+    ))
+    .await
+}
+
+/// Solve the specs with the packages of the channels for the target platform, without installing
+/// anything. The locked packages (e.g. the packages that are already installed) are preferred by
+/// the solver.
+pub async fn solve_environment(
+    specs: &[MatchSpec],
+    locked_packages: Vec<RepoDataRecord>,
+    channels: &[String],
+    target_platform: Platform,
+    virtual_packages: &[GenericVirtualPackage],
+    channel_priority: ChannelPriority,
+    solver: SolverBackend,
+    tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<Vec<RepoDataRecord>> {
+    let repodatas = apply_channel_priority(
+        load_repodatas(specs, channels, target_platform, tool_configuration).await?,
+        channel_priority,
+    );
+
+    // Determine virtual packages of the system. These packages define the capabilities of the
+    // system. Some packages depend on these virtual packages to indicate compatibility with the
+    // hardware of the system.
+    let mut detected_virtual_packages =
+        wrap_in_progress("determining virtual packages", move || {
+            rattler_virtual_packages::VirtualPackage::current().map(|vpkgs| {
+                vpkgs
+                    .iter()
+                    .map(|vpkg| GenericVirtualPackage::from(vpkg.clone()))
+                    .collect::<Vec<_>>()
+            })
+        })?;
+
+    // The given virtual packages override the detected ones with the same name
+    detected_virtual_packages.retain(|detected| {
+        !virtual_packages
+            .iter()
+            .any(|vpkg| vpkg.name == detected.name)
+    });
+    let virtual_packages = detected_virtual_packages
+        .into_iter()
+        .chain(virtual_packages.iter().cloned())
+        .collect::<Vec<_>>();
+
+    // Now that we parsed and downloaded all information, construct the packaging problem that we
+    // need to solve. We do this by constructing a `SolverProblem`. This encapsulates all the
+    // information required to be able to solve the problem.
+    let solver_task = SolverTask {
+        available_packages: repodatas.as_slice(),
+        locked_packages,
+        virtual_packages,
+        specs: specs.to_vec(),
+        pinned_packages: Vec::new(),
+    };
+
+    // Next, use a solver to solve this specific problem. This provides us with all the operations
+    // we need to apply to our environment to bring it up to date.
+    wrap_in_progress("solving", move || solver.solve(solver_task))
+}
+
+/// Make sure that all packages can be installed without network access, i.e. that they are
+/// already in the package cache or come from a local channel
+fn ensure_available_offline(packages: &[RepoDataRecord], cache_dir: &Path) -> anyhow::Result<()> {
+    let missing = packages
+        .iter()
+        .filter(|record| record.url.scheme() != "file")
+        .filter(|record| {
+            let Some(identifier) = ArchiveIdentifier::try_from_filename(&record.file_name) else {
+                return true;
+            };
+            !cache_dir
+                .join("pkgs")
+                .join(CacheKey::from(identifier).to_string())
+                .exists()
+        })
+        .map(|record| record.file_name.clone())
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "the following packages are not available offline (they are not in the package cache): {}",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Download (or load from the cache) the repodata of the channels and return all records that
+/// are (transitively) required by the specs, grouped by channel and subdirectory (in the order of
+/// the channels). The subdirectories of the target platform and `noarch` are loaded, unless a
+/// channel specifies its subdirectories.
+pub async fn load_repodatas(
+    specs: &[MatchSpec],
+    channels: &[String],
+    target_platform: Platform,
+    tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<Vec<Vec<RepoDataRecord>>> {
+    let channel_config = ChannelConfig::default();
+
+    // Find the cache directory. Create it if it doesn't exist yet.
+    let cache_dir = tool_configuration.cache_dir()?;
     std::fs::create_dir_all(&cache_dir)
         .map_err(|e| anyhow::anyhow!("could not create cache directory: {}", e))?;
 
@@ -94,23 +387,22 @@ pub async fn create_environment(
         .collect::<Result<Vec<_>, _>>()?;
 
     // Each channel contains multiple subdirectories. Users can specify the subdirectories they want
-    // to use when specifying their channels. If the user didn't specify the default subdirectories
-    // we use defaults based on the current platform.
+    // to use when specifying their channels. If the user didn't specify the subdirectories we use
+    // the subdirectories of the target platform and `noarch`.
+    let mut default_platforms = vec![target_platform, Platform::NoArch];
+    default_platforms.dedup();
     let channel_urls = channels
         .iter()
         .flat_map(|channel| {
             channel
-                .platforms_or_default()
+                .platforms
+                .as_deref()
+                .unwrap_or(default_platforms.as_slice())
                 .iter()
                 .map(move |platform| (channel.clone(), *platform))
         })
         .collect::<Vec<_>>();
 
-    // Determine the packages that are currently installed in the environment.
-    let installed_packages = find_installed_packages(target_prefix, 100)
-        .await
-        .context("failed to determine currently installed packages")?;
-
     // For each channel/subdirectory combination, download and cache the `repodata.json` that should
     // be available from the corresponding Url. The code below also displays a nice CLI progress-bar
     // to give users some more information about what is going on.
@@ -122,6 +414,13 @@ pub async fn create_environment(
         .map(move |(channel, platform)| {
             let repodata_cache = repodata_cache_path.clone();
             let download_client = repodata_download_client.clone();
+            // local channels can always be read, other channels only from the cache when offline
+            let cache_action = if tool_configuration.offline && channel.base_url.scheme() != "file"
+            {
+                CacheAction::ForceCacheOnly
+            } else {
+                CacheAction::CacheOrFetch
+            };
             async move {
                 fetch_repo_data_records_with_progress(
                     channel,
@@ -130,11 +429,12 @@ pub async fn create_environment(
                     download_client.clone(),
                     tool_configuration.multi_progress_indicator.clone(),
                     platform != Platform::NoArch,
+                    cache_action,
                 )
                 .await
             }
         })
-        .buffer_unordered(channel_and_platform_len)
+        .buffered(channel_and_platform_len)
         .collect::<Vec<_>>()
         .await
         // Collect into another iterator where we extract the first erroneous result
@@ -144,50 +444,16 @@ pub async fn create_environment(
 
     // Get the package names from the matchspecs so we can only load the package records that we need.
     let package_names = specs.iter().filter_map(|spec| spec.name.clone());
-    let repodatas = wrap_in_progress("parsing repodata", move || {
+    let mut repodatas = wrap_in_progress("parsing repodata", move || {
         SparseRepoData::load_records_recursive(&sparse_repo_datas, package_names, None)
     })?;
 
-    // Determine virtual packages of the system. These packages define the capabilities of the
-    // system. Some packages depend on these virtual packages to indicate compatibility with the
-    // hardware of the system.
-    let virtual_packages = wrap_in_progress("determining virtual packages", move || {
-        rattler_virtual_packages::VirtualPackage::current().map(|vpkgs| {
-            vpkgs
-                .iter()
-                .map(|vpkg| GenericVirtualPackage::from(vpkg.clone()))
-                .collect::<Vec<_>>()
-        })
-    })?;
-
-    // Now that we parsed and downloaded all information, construct the packaging problem that we
-    // need to solve. We do this by constructing a `SolverProblem`. This encapsulates all the
-    // information required to be able to solve the problem.
-    let solver_task = SolverTask {
-        available_packages: &repodatas,
-        locked_packages: installed_packages
-            .iter()
-            .map(|record| record.repodata_record.clone())
-            .collect(),
-        virtual_packages,
-        specs: specs.to_vec(),
-        pinned_packages: Vec::new(),
-    };
-
-    // Next, use a solver to solve this specific problem. This provides us with all the operations
-    // we need to apply to our environment to bring it up to date.
-    let required_packages = wrap_in_progress("solving", move || Solver.solve(solver_task))?;
-
-    install_packages(
-        &required_packages,
-        target_platform,
-        target_prefix,
-        &cache_dir,
-        tool_configuration,
-    )
-    .await?;
+    // the dependencies are loaded before patching, a patch cannot pull in additional packages
+    if let Some(path) = &tool_configuration.repodata_patches {
+        RepodataPatches::from_path(path)?.apply(&mut repodatas)?;
+    }
 
-    Ok(required_packages)
+    Ok(repodatas)
 }
 
 pub async fn install_packages(
@@ -239,18 +505,22 @@ async fn execute_transaction(
     download_client: AuthenticatedClient,
     multi_progress: indicatif::MultiProgress,
 ) -> anyhow::Result<()> {
-    // Open the package cache
-    let package_cache = PackageCache::new(cache_dir.join("pkgs"));
+    // Open the package cache (the packages are extracted into it)
+    let package_cache = PackageCache::new(extended_length_path(&cache_dir.join("pkgs")));
 
     // Create an install driver which helps limit the number of concurrent filesystem operations
     let install_driver = InstallDriver::default();
 
-    // Define default installation options.
+    // Define default installation options. On Windows the files are linked through an
+    // extended-length path, as the paths of a package can exceed MAX_PATH in a deeply nested
+    // prefix. The prefix placeholders are still replaced with the prefix itself.
     let install_options = InstallOptions {
+        target_prefix: Some(target_prefix.to_path_buf()),
         python_info: transaction.python_info.clone(),
         platform: Some(transaction.platform),
         ..Default::default()
     };
+    let target_prefix = &extended_length_path(target_prefix);
 
     // Create a progress bars for downloads.
     let total_packages_to_download = transaction
@@ -509,6 +779,7 @@ async fn fetch_repo_data_records_with_progress(
     client: AuthenticatedClient,
     multi_progress: indicatif::MultiProgress,
     allow_not_found: bool,
+    cache_action: CacheAction,
 ) -> anyhow::Result<Option<SparseRepoData>> {
     // Create a progress bar
     let progress_bar = multi_progress.add(
@@ -526,6 +797,7 @@ async fn fetch_repo_data_records_with_progress(
         client,
         repodata_cache.to_path_buf(),
         FetchRepoDataOptions {
+            cache_action,
             ..Default::default()
         },
         Some(Box::new(move |DownloadProgress { total, bytes }| {
@@ -538,6 +810,14 @@ async fn fetch_repo_data_records_with_progress(
     // Error out if an error occurred, but also update the progress bar
     let result = match result {
         Err(e) => {
+            if matches!(e, FetchRepoDataError::NoCacheAvailable) {
+                progress_bar.set_style(errored_progress_style());
+                progress_bar.finish_with_message("Not cached");
+                return Err(anyhow::anyhow!(
+                    "the repodata of {} is not cached and cannot be downloaded offline",
+                    channel.platform_url(platform)
+                ));
+            }
             if matches!(e, FetchRepoDataError::NotFound(_)) && allow_not_found {
                 progress_bar.set_style(errored_progress_style());
                 progress_bar.finish_with_message("Not Found");
@@ -659,7 +939,7 @@ fn long_running_progress_style() -> indicatif::ProgressStyle {
 
 /// Scans the conda-meta directory of an environment and returns all the [`PrefixRecord`]s found in
 /// there.
-async fn find_installed_packages(
+pub async fn find_installed_packages(
     target_prefix: &Path,
     concurrency_limit: usize,
 ) -> Result<Vec<PrefixRecord>, std::io::Error> {
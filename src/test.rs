@@ -8,32 +8,89 @@
 //! * `files` - check if a list of files exist
 
 use std::{
+    collections::{BTreeSet, HashMap, HashSet},
     fs::{self},
     io::{Read, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use dunce::canonicalize;
-use indicatif::MultiProgress;
+use indicatif::{MultiProgress, ProgressBar};
 use rattler::package_cache::CacheKey;
 use rattler_conda_types::{
-    package::{ArchiveIdentifier, ArchiveType},
-    MatchSpec, Platform,
+    package::{
+        AboutJson, ArchiveIdentifier, ArchiveType, EntryPoint, FileMode, IndexJson, LinkJson,
+        NoArchLinks, PackageFile, PathType, PathsJson, RunExportsJson,
+    },
+    Arch, ChannelInfo, GenericVirtualPackage, MatchSpec, Platform, RepoData, RepoDataRecord,
+    Version,
 };
 use rattler_networking::AuthenticatedClient;
 use rattler_shell::{
-    activation::{ActivationError, ActivationVariables, Activator},
-    shell::{Shell, ShellEnum, ShellScript},
+    activation::{ActivationError, ActivationVariables, Activator, PathModificationBehavior},
+    shell::{Bash, CmdExe, Shell, ShellEnum, ShellScript},
 };
+use tracing::Instrument;
 
-use crate::{env_vars, index, render::solver::create_environment, tool_configuration};
+use crate::{
+    env_vars, index, packaging,
+    recipe::parser::Test,
+    render::solver::{
+        create_environment, find_installed_packages, install_environment, load_repodatas,
+        remove_package, solve_environment, ChannelPriority, SolverBackend,
+    },
+    tool_configuration,
+    windows::path::{extended_length_path, reserved_name, MAX_PATH},
+};
+
+mod builder;
+mod checkpoint;
+mod docker;
+mod environment_cache;
+mod interrupt;
+mod linkage;
+mod lockfile;
+mod manifest;
+mod menuinst;
+mod read_only;
+mod side_effects;
+mod signature;
+pub mod testresult;
+pub use builder::TestConfigurationBuilder;
+use checkpoint::Checkpoint;
+pub use checkpoint::CheckpointConfig;
+pub use linkage::LinkageProblem;
+use manifest::{OutputExpectation, TestManifest};
+use read_only::ReadOnlyPrefix;
+use side_effects::DirectorySnapshot;
+pub use signature::PublicKey;
+pub use testresult::{SkipReason, TestCaseOutcome, TestLog, TestResult, TestStatus};
 
 #[allow(missing_docs)]
 #[derive(thiserror::Error, Debug)]
 pub enum TestError {
-    #[error("failed to run test")]
-    TestFailed,
+    #[error(
+        "test '{}' failed ({}):\n{}{}",
+        .test,
+        format_exit_code(*.exit_code),
+        .command.trim_end(),
+        format_output_tail(.output_tail)
+    )]
+    TestFailed {
+        test: String,
+        command: String,
+        exit_code: Option<i32>,
+        output_tail: String,
+    },
+
+    #[error("Could not parse the package name, version and build string from {0:?}")]
+    InvalidPackageFileName(PathBuf),
+
+    #[error("The test {test} cannot run on {platform}")]
+    UnrunnableTest { test: String, platform: Platform },
 
     #[error("Failed to read package: {0}")]
     PackageRead(#[from] std::io::Error),
@@ -47,9 +104,24 @@ pub enum TestError {
     #[error("Failed to setup test environment: {0}")]
     TestEnvironementActivation(#[from] ActivationError),
 
+    #[error("Failed to start {} to run the test: {source}", .executable.display())]
+    ShellSpawnFailed {
+        executable: PathBuf,
+        source: std::io::Error,
+    },
+
     #[error("Failed to parse JSON from test files: {0}")]
     TestJSONParseError(#[from] serde_json::Error),
 
+    #[error("Failed to parse the test dependencies in {}: {source}", .file.display())]
+    TestDependenciesParseError {
+        file: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("Failed to parse the test manifest: {0}")]
+    TestManifestParseError(#[from] serde_yaml::Error),
+
     #[error("Failed to parse MatchSpec from test files: {0}")]
     TestMatchSpecParseError(#[from] rattler_conda_types::ParseMatchSpecError),
 
@@ -58,320 +130,7795 @@ pub enum TestError {
 
     #[error("Archive type not supported")]
     ArchiveTypeNotSupported,
+
+    #[error("Failed to extract package: {0}")]
+    PackageExtraction(#[from] rattler_package_streaming::ExtractError),
+
+    #[error("test '{0}' tried to write into the read-only test prefix")]
+    PrefixWriteViolation(String),
+
+    #[error(
+        "test '{test}' left unexpected changes behind:\n{}",
+        format_paths(paths)
+    )]
+    UnexpectedSideEffects { test: String, paths: Vec<PathBuf> },
+
+    #[error("Invalid glob pattern: {0}")]
+    GlobError(#[from] globset::Error),
+
+    #[error("Invalid regular expression: {0}")]
+    RegexError(#[from] regex::Error),
+
+    #[error("test '{test}' did not produce the expected output:\n{diff}")]
+    UnexpectedOutput { test: String, diff: String },
+
+    #[error("{} test(s) failed:\n{}", .0.len(), format_failures(.0))]
+    TestsFailed(Vec<(String, TestError)>),
+
+    #[error("{0} (recorded in a previous run)")]
+    PreviousRun(String),
+
+    #[error("No file in the test environment matches the pattern '{0}'")]
+    MissingFile(String),
+
+    #[error("The command timed out after {elapsed:?}:\n{command}")]
+    Timeout { command: String, elapsed: Duration },
+
+    #[error("Failed to index the local channel {path:?} for {platform}: {source}")]
+    IndexingFailed {
+        path: PathBuf,
+        platform: Platform,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("The tests did not finish within the total time limit of {limit:?} (aborted while running '{test}')")]
+    TotalTimeout { limit: Duration, test: String },
+
+    #[error("The tests were interrupted")]
+    Interrupted,
+
+    #[error(
+        "The dependencies of {package} cannot be satisfied with the configured channels:\n{}\n{}",
+        .specs.iter().map(|spec| format!(" - {}", spec)).collect::<Vec<_>>().join("\n"),
+        .problems.join("\n")
+    )]
+    UnsatisfiableDependencies {
+        package: String,
+        specs: Vec<String>,
+        problems: Vec<String>,
+    },
+
+    #[error(
+        "The test environment cannot be solved:\n{}\nRequested specs:\n{}\nChannels:\n{}",
+        .explanation,
+        .specs.iter().map(|spec| format!(" - {}", spec)).collect::<Vec<_>>().join("\n"),
+        .channels.iter().map(|channel| format!(" - {}", channel)).collect::<Vec<_>>().join("\n")
+    )]
+    EnvironmentSolveFailed {
+        specs: Vec<String>,
+        channels: Vec<String>,
+        explanation: String,
+    },
+
+    #[error("test '{test}' wrote to stderr:\n{stderr_tail}")]
+    UnexpectedStderr { test: String, stderr_tail: String },
+
+    #[error("The lockfile {path:?} cannot be used: {reason}")]
+    InvalidLockfile { path: PathBuf, reason: String },
+
+    #[error("the variable ${{{name}}} in the test command '{command}' is not defined")]
+    UndefinedVariable { name: String, command: String },
+
+    #[error(
+        "no test named {name} found; available: {}",
+        if .available.is_empty() { "none".to_string() } else { .available.join(", ") }
+    )]
+    NoMatchingTest {
+        name: String,
+        available: Vec<String>,
+    },
+
+    #[error("Invalid test configuration: {0}")]
+    InvalidConfiguration(String),
+
+    #[error("No package matching '{0}' was found in the channels")]
+    PackageNotFound(String),
+
+    #[error(
+        "The spec '{spec}' matches multiple packages, please be more specific:\n{}",
+        .matches.join("\n")
+    )]
+    AmbiguousSpec { spec: String, matches: Vec<String> },
+
+    #[error("Failed to download the package: {0}")]
+    PackageDownload(#[from] reqwest::Error),
+
+    #[error("The shell {0:?} is not supported for running tests")]
+    UnsupportedShell(ShellEnum),
+
+    #[error("The test environment does not provide {name} (add it to the test requirements)")]
+    MissingInterpreter { name: String },
+
+    #[error(
+        "The existing prefix {prefix:?} does not satisfy the test requirements: {}",
+        missing.join(", ")
+    )]
+    PrefixNotSatisfied {
+        prefix: PathBuf,
+        missing: Vec<String>,
+    },
+
+    #[error("pip check reported broken requirements:\n{}", .0.join("\n"))]
+    PipCheckFailed(Vec<String>),
+
+    #[error(
+        "the following modules could not be imported:\n{}",
+        .0.iter().map(|(module, error)| format!(" - {}: {}", module, error)).collect::<Vec<_>>().join("\n")
+    )]
+    ImportsFailed(Vec<(String, String)>),
+
+    #[error(
+        "the following entry points do not work:\n{}",
+        .0.iter().map(|(command, error)| format!(" - {}: {}", command, error)).collect::<Vec<_>>().join("\n")
+    )]
+    EntryPointsFailed(Vec<(String, String)>),
+
+    #[error(
+        "the following menu entries are broken:\n{}",
+        .0.iter().map(|(entry, problem)| format!(" - {}: {}", entry, problem)).collect::<Vec<_>>().join("\n")
+    )]
+    MenuInstFailed(Vec<(String, String)>),
+
+    #[error(
+        "Files of the package are missing or empty in the test environment:\n{}",
+        .0.iter().map(|path| format!(" - {}", path.display())).collect::<Vec<_>>().join("\n")
+    )]
+    MissingInstalledFile(Vec<PathBuf>),
+
+    #[error(
+        "Files of the package still contain the prefix placeholder after the installation:\n{}",
+        .0.iter().map(|path| format!(" - {}", path.display())).collect::<Vec<_>>().join("\n")
+    )]
+    PrefixNotReplaced(Vec<PathBuf>),
+
+    #[error("The metadata of {package} is inconsistent: {reason}")]
+    InconsistentMetadata { package: String, reason: String },
+
+    #[error(
+        "Files of the package are left in the test environment after the package was removed:\n{}",
+        .0.iter().map(|path| format!(" - {}", path.display())).collect::<Vec<_>>().join("\n")
+    )]
+    IncompleteUninstall(Vec<PathBuf>),
+
+    #[error(
+        "Libraries of the package have unresolved dependencies:\n{}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+    )]
+    LinkageProblems(Vec<LinkageProblem>),
+
+    #[error("The signature of {package} could not be verified: {reason}")]
+    SignatureVerificationFailed { package: String, reason: String },
+
+    #[error("Checksum mismatch for {file}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
 }
 
-#[derive(Debug)]
-enum Tests {
-    Commands(PathBuf),
-    Python(PathBuf),
+fn format_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!(" - {}", path.display()))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
-fn run_in_environment(
-    shell: ShellEnum,
-    cmd: String,
-    cwd: &Path,
-    environment: &Path,
-) -> Result<(), TestError> {
-    let current_path = std::env::var("PATH")
-        .ok()
-        .map(|p| std::env::split_paths(&p).collect::<Vec<_>>());
+fn format_exit_code(exit_code: Option<i32>) -> String {
+    match exit_code {
+        Some(code) => format!("exit code {}", code),
+        None => "no exit code (terminated by a signal)".to_string(),
+    }
+}
 
-    // if we are in a conda environment, we need to deactivate it before activating the host / build prefix
-    let conda_prefix = std::env::var("CONDA_PREFIX").ok().map(|p| p.into());
+fn format_output_tail(output_tail: &str) -> String {
+    if output_tail.is_empty() {
+        String::new()
+    } else {
+        format!(", the last lines of its output were:\n{}", output_tail)
+    }
+}
 
-    let av = ActivationVariables {
-        conda_prefix,
-        path: current_path,
-        path_modification_behaviour: Default::default(),
-    };
+fn format_failures(failures: &[(String, TestError)]) -> String {
+    failures
+        .iter()
+        .map(|(name, error)| format!(" - {}: {}", name, error))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    let activator = Activator::from_path(environment, shell.clone(), Platform::current())?;
-    let script = activator.activation(av)?;
+/// Returns true if the test output is colored: only if it goes to an interactive terminal
+/// (the log and the summary are written to stderr) and `NO_COLOR` is not set
+fn colors_enabled() -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && console::colors_enabled_stderr()
+}
 
-    let mut tmpfile = tempfile::Builder::new()
-        .prefix("rattler-test-")
-        .suffix(&format!(".{}", shell.extension()))
-        .tempfile()?;
+/// Like [`console::style`], but only emits colors if [`colors_enabled`]
+pub(crate) fn style<D>(value: D) -> console::StyledObject<D> {
+    console::style(value).force_styling(colors_enabled())
+}
 
-    let mut additional_script = ShellScript::new(shell.clone(), Platform::current());
+/// A custom test that is run in addition to the tests of a package, e.g. to verify a property
+/// that is specific to a package format. Register it with
+/// [`TestConfiguration::register_runner`].
+pub trait TestRunner: std::fmt::Debug + Send + Sync {
+    /// The name of the test, used for reporting and e.g. for the options in the test manifest
+    fn name(&self) -> String;
 
-    let os_vars = env_vars::os_vars(environment, &Platform::current());
-    for (key, val) in os_vars {
-        if key == "PATH" {
-            continue;
-        }
-        additional_script.set_env_var(&key, &val);
-    }
+    /// Run the test in the test environment (the prefix), with the test folder of the package
+    /// as the working directory
+    fn run(&self, environment: &Path, cwd: &Path) -> Result<(), TestError>;
+}
 
-    additional_script.set_env_var("PREFIX", environment.to_string_lossy().as_ref());
+/// Observes the progress of the tests, e.g. to drive the UI of an application that embeds them
+/// or to collect metrics (see [`TestConfiguration::observer`]). All methods do nothing by
+/// default.
+pub trait TestObserver: std::fmt::Debug + Send + Sync {
+    /// Called before a test of the package is run
+    fn on_test_start(&self, _test: &TestDescription) {}
 
-    writeln!(tmpfile, "{}", additional_script.contents)?;
-    writeln!(tmpfile, "{}", script.script)?;
-    writeln!(tmpfile, "{}", cmd)?;
+    /// Called with the outcome of every test, including skipped tests
+    fn on_test_finish(&self, _outcome: &TestCaseOutcome) {}
 
-    let tmpfile_path = tmpfile.into_temp_path();
-    let executable = shell.executable();
-    let status = match shell {
-        ShellEnum::Bash(_) => std::process::Command::new(executable)
-            .arg(&tmpfile_path)
-            .current_dir(cwd)
-            .status()?,
-        ShellEnum::CmdExe(_) => std::process::Command::new(executable)
-            .arg("/d")
-            .arg("/c")
-            .arg(&tmpfile_path)
-            .current_dir(cwd)
-            .status()?,
-        _ => todo!("No shells implemented beyond cmd.exe and bash"),
+    /// Called with the result of a package after all of its tests were run
+    fn on_package_finish(&self, _result: &TestResult) {}
+}
+
+/// Log the progress of a single test: at `info` level, or at `debug` level if the configuration
+/// is `quiet`
+macro_rules! test_progress {
+    ($config:expr, $($arg:tt)+) => {
+        if $config.quiet {
+            tracing::debug!($($arg)+)
+        } else {
+            tracing::info!($($arg)+)
+        }
     };
+}
 
-    if !status.success() {
-        return Err(TestError::TestFailed);
-    }
+#[derive(Debug, Clone)]
+enum Tests {
+    Commands(PathBuf),
+    /// Run the commands of a command test (the script) again with the minimal activation
+    MinimalActivation(PathBuf),
+    Python(PathBuf),
+    Files(PathBuf),
+    R(PathBuf),
+    Interpreted {
+        path: PathBuf,
+        interpreter: &'static str,
+    },
+    Custom(Arc<dyn TestRunner>),
+    PipCheck,
+    /// Check the linkage of the libraries of the package (in the given package folder)
+    LinkageCheck(PathBuf),
+    /// Check that the files of the package (in the given package folder) are installed
+    InstalledFiles(PathBuf),
+    /// Check that the prefix placeholders of the files of the package (in the given package
+    /// folder) were replaced when the package was installed
+    PrefixCheck(PathBuf),
+    /// Check that the `info/index.json` of the package (in the given package folder) agrees
+    /// with the name, version and build string from the file name of the package
+    MetadataCheck {
+        package_folder: PathBuf,
+        pkg: ArchiveIdentifier,
+    },
+    /// Run the Python entry points of the package (in the given package folder) with `--help`
+    EntryPoints(PathBuf),
+    /// Check the menuinst metadata (`Menu/*.json`) of the package (in the given package folder)
+    MenuInst(PathBuf),
+    /// Run the test suite that the package ships in its test folder (the directory) with the
+    /// runner of the suite
+    TestSuite {
+        runner: TestSuiteRunner,
+        dir: PathBuf,
+    },
+}
 
-    Ok(())
+/// The runner of a test suite in the test folder of a package
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TestSuiteRunner {
+    /// A pytest suite, detected by a `pytest.ini` or `conftest.py`
+    Pytest,
+    /// A CTest suite, detected by a `CTestTestfile.cmake`
+    Ctest,
 }
 
-impl Tests {
-    fn run(&self, environment: &Path, cwd: &Path) -> Result<(), TestError> {
-        let default_shell = ShellEnum::default();
+impl TestSuiteRunner {
+    /// The runner of the test suite that the file in the test folder configures
+    fn from_file_name(file_name: &str) -> Option<Self> {
+        match file_name {
+            "pytest.ini" | "conftest.py" => Some(TestSuiteRunner::Pytest),
+            "CTestTestfile.cmake" => Some(TestSuiteRunner::Ctest),
+            _ => None,
+        }
+    }
 
+    /// The executable of the runner, which is also the name of the test
+    fn executable(self) -> &'static str {
         match self {
-            Tests::Commands(path) => {
-                let contents = fs::read_to_string(path)?;
-                let ext = path.extension().unwrap().to_str().unwrap();
-                match (Platform::current().is_windows(), ext) {
-                    (true, "bat") => {
-                        tracing::info!("Testing commands:");
-                        run_in_environment(default_shell, contents, cwd, environment)
-                    }
-                    (false, "sh") => {
-                        tracing::info!("Testing commands:");
-                        run_in_environment(default_shell, contents, cwd, environment)
-                    }
-                    _ => Ok(()),
-                }
-            }
-            Tests::Python(path) => {
-                let imports = fs::read_to_string(path)?;
-                tracing::info!("Testing Python imports:\n{imports}");
-                run_in_environment(
-                    default_shell,
-                    format!("python {}", path.to_string_lossy()),
-                    cwd,
-                    environment,
-                )
-            }
+            TestSuiteRunner::Pytest => "pytest",
+            TestSuiteRunner::Ctest => "ctest",
+        }
+    }
+
+    /// The command that runs the suite. pytest does not write its cache into the test folder,
+    /// which is part of the package cache.
+    fn command(self) -> String {
+        match self {
+            TestSuiteRunner::Pytest => "pytest -p no:cacheprovider -ra".to_string(),
+            TestSuiteRunner::Ctest => "ctest --output-on-failure".to_string(),
+        }
+    }
+
+    /// The counts of passed and failed tests from the output of the runner, e.g. `3 passed, 1
+    /// failed` from pytest or `75% tests passed, 1 tests failed out of 4` from CTest
+    fn summary(self, output: &str) -> Option<String> {
+        let mut lines = output.lines().rev().map(str::trim);
+        match self {
+            TestSuiteRunner::Pytest => lines
+                .filter(|line| line.starts_with('=') && line.ends_with('='))
+                .map(|line| line.trim_matches(|c| c == '=' || c == ' '))
+                .find(|line| {
+                    ["passed", "failed", "error", "no tests ran"]
+                        .iter()
+                        .any(|word| line.contains(word))
+                })
+                .map(|line| match line.rsplit_once(" in ") {
+                    Some((counts, _)) => counts.to_string(),
+                    None => line.to_string(),
+                }),
+            TestSuiteRunner::Ctest => lines
+                .find(|line| line.contains("tests passed,"))
+                .map(str::to_string),
         }
     }
 }
 
-async fn tests_from_folder(pkg: &Path) -> Result<(PathBuf, Vec<Tests>), TestError> {
-    let mut tests = Vec::new();
+/// The name of the `pip check` test
+const PIP_CHECK_TEST_NAME: &str = "pip_check";
 
-    let test_folder = pkg.join("info").join("test");
+/// The name of the linkage check
+const LINKAGE_CHECK_TEST_NAME: &str = "linkage_check";
 
-    if !test_folder.exists() {
-        return Ok((test_folder, tests));
-    }
+/// The name of the check of the installed files
+const INSTALLED_FILES_TEST_NAME: &str = "installed_files";
 
-    let mut read_dir = tokio::fs::read_dir(&test_folder).await?;
+/// The name of the check of the prefix replacement
+const PREFIX_CHECK_TEST_NAME: &str = "prefix_check";
 
-    while let Some(entry) = read_dir.next_entry().await? {
-        let path = entry.path();
-        if path.is_dir() {
+/// The name of the check of the package metadata
+const METADATA_CHECK_TEST_NAME: &str = "metadata_check";
+
+/// Appended to the name of a command test for its run with the minimal activation
+const MINIMAL_ACTIVATION_SUFFIX: &str = " (minimal activation)";
+
+/// The name of the check that the package can be removed from the test environment
+const UNINSTALL_CHECK_TEST_NAME: &str = "uninstall_check";
+
+/// The name of the check of the Python entry points
+const ENTRY_POINTS_TEST_NAME: &str = "entry_points";
+
+/// The name of the check of the entry points against well-known system commands
+const ENTRY_POINT_SHADOWING_TEST_NAME: &str = "entry_point_shadowing";
+
+/// The name of the check of the menuinst metadata
+const MENUINST_TEST_NAME: &str = "menuinst";
+
+/// The problems reported by `pip check`, one per line
+fn pip_check_problems(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("No broken requirements found"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The modules of a Python test that only consists of `import` statements (like the tests that
+/// conda-build generates for `imports`). Returns `None` if the script contains anything else.
+fn import_statements(script: &str) -> Option<Vec<String>> {
+    let mut modules = Vec::new();
+    for line in script.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
             continue;
         }
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-        match file_name {
-            "run_test.sh" | "run_test.bat" => tests.push(Tests::Commands(path)),
-            "run_test.py" => tests.push(Tests::Python(path)),
-            _ => {}
+        for module in line.strip_prefix("import ")?.split(',').map(str::trim) {
+            let valid = !module.is_empty()
+                && module
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+            if !valid {
+                return None;
+            }
+            modules.push(module.to_string());
         }
     }
+    Some(modules)
+}
 
-    Ok((test_folder, tests))
+/// Interpreters for `run_test.<extension>` scripts, by extension
+const TEST_SCRIPT_INTERPRETERS: &[(&str, &str)] = &[("pl", "perl"), ("rb", "ruby")];
+
+/// How the test environment is prepared before a test command is executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ActivationMode {
+    /// Run the full activation of the prefix. This sources the `etc/conda/activate.d` scripts
+    /// of the installed packages (for the shell of the test), so that the variables they set
+    /// are available to the test command.
+    Full,
+    /// Only set `PREFIX` (and `CONDA_PREFIX`) and prepend the binary directories of the prefix
+    /// to `PATH`, similar to what `conda run` style tools do
+    Minimal,
 }
 
-fn file_from_tar_bz2(archive_path: &Path, find_path: &Path) -> Result<String, std::io::Error> {
-    let reader = std::fs::File::open(archive_path)?;
-    let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+/// The `PKG_*` variables that conda-build sets for test scripts. The build number is the number
+/// after the last underscore of the build string (e.g. `3` for `py311_3`).
+fn package_env_vars(pkg: &ArchiveIdentifier) -> HashMap<String, String> {
+    let build_number = pkg
+        .build_string
+        .rsplit('_')
+        .next()
+        .filter(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or("0");
 
-    for entry in archive.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?;
-        if path == find_path {
-            let mut contents = String::new();
-            entry.read_to_string(&mut contents)?;
-            return Ok(contents);
-        }
-    }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::NotFound,
-        format!("{:?} not found in {:?}", find_path, archive_path),
-    ))
+    HashMap::from([
+        ("PKG_NAME".to_string(), pkg.name.clone()),
+        ("PKG_VERSION".to_string(), pkg.version.clone()),
+        ("PKG_BUILDNUM".to_string(), build_number.to_string()),
+    ])
 }
 
-fn file_from_conda(archive_path: &Path, find_path: &Path) -> Result<String, std::io::Error> {
-    let reader = std::fs::File::open(archive_path)?;
+/// The additional environment variables for the test commands of a package. The variables of
+/// the configuration take precedence over the package variables.
+fn test_env_vars(pkg: &ArchiveIdentifier, config: &TestConfiguration) -> HashMap<String, String> {
+    let mut env_vars = package_env_vars(pkg);
+    env_vars.extend(config.extra_env.clone());
+    env_vars
+}
 
-    let mut archive = if find_path.starts_with("info") {
-        rattler_package_streaming::seek::stream_conda_info(reader)
-            .expect("Could not open conda file")
-    } else {
-        todo!("Not implemented yet");
-    };
+/// The platform for which the test environment is activated: the target platform, or the host
+/// platform for `noarch` packages
+fn activation_platform(target_platform: Option<Platform>) -> Platform {
+    match target_platform {
+        None | Some(Platform::NoArch) => Platform::current(),
+        Some(platform) => platform,
+    }
+}
 
-    for entry in archive.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?;
-        if path == find_path {
-            let mut contents = String::new();
-            entry.read_to_string(&mut contents)?;
-            return Ok(contents);
-        }
+/// Returns true if executables for the platform can (most likely) be run on this host
+fn can_run_on_host(platform: Platform) -> bool {
+    can_run_on(platform, Platform::current())
+}
+
+/// Returns true if executables for the platform can run on the host platform. The operating
+/// system and the architecture have to match, except for the emulations that come with the
+/// operating system: 32-bit x86 on 64-bit Windows, x86-64 on macOS with Rosetta 2 and on Windows
+/// on ARM.
+fn can_run_on(platform: Platform, host: Platform) -> bool {
+    if platform.is_windows() != host.is_windows()
+        || platform.is_osx() != host.is_osx()
+        || platform.is_linux() != host.is_linux()
+    {
+        return false;
+    }
+    match (platform.arch(), host.arch()) {
+        (Some(arch), Some(host_arch)) if arch == host_arch => true,
+        (Some(Arch::X86), Some(Arch::X86_64)) => platform.is_windows(),
+        (Some(Arch::X86_64), Some(Arch::Aarch64)) => platform.is_osx() || platform.is_windows(),
+        _ => false,
     }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::NotFound,
-        format!("{:?} not found in {:?}", find_path, archive_path),
-    ))
 }
 
-/// The configuration for a test
-#[derive(Default, Debug)]
-pub struct TestConfiguration {
-    /// The test prefix directory (will be created)
-    pub test_prefix: PathBuf,
-    /// The target platform
-    pub target_platform: Option<Platform>,
-    /// If true, the test prefix will not be deleted after the test is run
-    pub keep_test_prefix: bool,
-    /// The channels to use for the test – do not forget to add the local build outputs channel
-    /// if desired
-    pub channels: Vec<String>,
+/// The directories of a prefix that contain executables, in the order they should appear in `PATH`
+fn prefix_bin_dirs(prefix: &Path, platform: &Platform) -> Vec<PathBuf> {
+    if platform.is_windows() {
+        vec![
+            prefix.to_path_buf(),
+            prefix.join("Library/mingw-w64/bin"),
+            prefix.join("Library/usr/bin"),
+            prefix.join("Library/bin"),
+            prefix.join("Scripts"),
+            prefix.join("bin"),
+        ]
+    } else {
+        vec![prefix.join("bin")]
+    }
 }
 
-/// Run a test for a single package
-///
-/// This function creates a temporary directory, copies the package file into it, and then runs the
-/// indexing. It then creates a test environment that installs the package and any extra dependencies
-/// specified in the package test dependencies file.
-///
-/// With the activated test environment, the packaged test files are run:
-///
-/// * `info/test/run_test.sh` or `info/test/run_test.bat` on Windows
-/// * `info/test/run_test.py`
-///
-/// These test files are written at "package creation time" and are part of the package.
-///
-/// # Arguments
-///
-/// * `package_file` - The path to the package file
-/// * `config` - The test configuration
-///
-/// # Returns
-///
-/// * `Ok(())` if the test was successful
-/// * `Err(TestError::TestFailed)` if the test failed
-pub async fn run_test(package_file: &Path, config: &TestConfiguration) -> Result<(), TestError> {
-    let tmp_repo = tempfile::tempdir()?;
-    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+/// The home directory of the user (`HOME`, or `USERPROFILE` on Windows)
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
 
-    let subdir = tmp_repo.path().join(target_platform.to_string());
-    std::fs::create_dir_all(&subdir)?;
+/// The prefixes of the conda environments that are activated in the current process: the
+/// innermost one in `CONDA_PREFIX`, the ones of stacked activations in `CONDA_PREFIX_<n>`
+fn outer_conda_prefixes() -> Vec<PathBuf> {
+    std::env::vars_os()
+        .filter(|(key, _)| {
+            let key = key.to_string_lossy();
+            key == "CONDA_PREFIX"
+                || key
+                    .strip_prefix("CONDA_PREFIX_")
+                    .map_or(false, |n| n.parse::<u32>().is_ok())
+        })
+        .map(|(_, value)| PathBuf::from(value))
+        .filter(|prefix| !prefix.as_os_str().is_empty())
+        .collect()
+}
 
-    std::fs::copy(
-        package_file,
-        subdir.join(
-            package_file
-                .file_name()
-                .ok_or(TestError::MissingPackageFileName)?,
-        ),
-    )?;
+/// Remove the bin directories of the given conda prefixes from the entries of `PATH`
+fn strip_conda_paths(
+    path: Vec<PathBuf>,
+    prefixes: &[PathBuf],
+    platform: &Platform,
+) -> Vec<PathBuf> {
+    let conda_dirs = prefixes
+        .iter()
+        .flat_map(|prefix| prefix_bin_dirs(prefix, platform))
+        .collect::<Vec<_>>();
+    path.into_iter()
+        .filter(|entry| !conda_dirs.contains(entry))
+        .collect()
+}
 
-    let archive_type =
-        ArchiveType::try_from(package_file).ok_or(TestError::ArchiveTypeNotSupported)?;
-    let test_dep_json = PathBuf::from("info/test/test_time_dependencies.json");
-    let test_dependencies = match archive_type {
-        ArchiveType::TarBz2 => file_from_tar_bz2(package_file, &test_dep_json),
-        ArchiveType::Conda => file_from_conda(package_file, &test_dep_json),
-    };
+/// Find the first of the given executables in the bin directories of the prefix
+fn find_in_prefix<'a>(prefix: &Path, platform: &Platform, names: &[&'a str]) -> Option<&'a str> {
+    let dirs = prefix_bin_dirs(prefix, platform);
+    names.iter().copied().find(|name| {
+        let file_name = if platform.is_windows() {
+            format!("{}.exe", name)
+        } else {
+            name.to_string()
+        };
+        dirs.iter().any(|dir| dir.join(&file_name).is_file())
+    })
+}
 
-    let mut dependencies: Vec<MatchSpec> = match test_dependencies {
-        Ok(contents) => {
-            let test_deps: Vec<String> = serde_json::from_str(&contents)?;
-            test_deps
-                .iter()
-                .map(|s| MatchSpec::from_str(s))
-                .collect::<Result<Vec<_>, _>>()?
-        }
-        Err(error) => {
-            if error.kind() == std::io::ErrorKind::NotFound {
-                Vec::new()
-            } else {
-                return Err(TestError::TestFailed);
-            }
-        }
-    };
+/// The interpreter followed by its additional arguments (e.g. `python -X importtime`)
+fn interpreter_command(interpreter: &str, args: &[String]) -> String {
+    std::iter::once(interpreter)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
-    // index the temporary channel
-    index::index(tmp_repo.path(), Some(&target_platform))?;
+/// The captured output of a command that was run in a test environment
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    /// The command that was run
+    pub command: String,
+    /// The exit code of the command (`None` if it was terminated by a signal)
+    pub exit_code: Option<i32>,
+    /// The captured standard output
+    pub stdout: String,
+    /// The captured standard error
+    pub stderr: String,
+    /// The log file that the output was written to (if any)
+    pub log: Option<TestLog>,
+}
 
-    let cache_dir = rattler::default_cache_dir()?;
+/// The number of lines of captured output that are part of the error of a failed test command
+const OUTPUT_TAIL_LINES: usize = 50;
 
-    let pkg = ArchiveIdentifier::try_from_path(package_file).ok_or(TestError::TestFailed)?;
+impl CommandOutput {
+    /// Returns true if the command exited successfully
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
 
-    // if the package is already in the cache, remove it. TODO make this based on SHA256 instead!
-    let cache_key = CacheKey::from(pkg.clone());
-    let package_folder = cache_dir.join("pkgs").join(cache_key.to_string());
+    /// Returns true if the command exited successfully or with one of the allowed exit codes
+    pub fn accepted(&self, allowed_exit_codes: &[i32]) -> bool {
+        self.success()
+            || self
+                .exit_code
+                .is_some_and(|code| allowed_exit_codes.contains(&code))
+    }
 
-    if package_folder.exists() {
-        tracing::info!("Removing previously cached package {:?}", package_folder);
-        fs::remove_dir_all(package_folder)?;
+    /// The last `lines` lines of the captured output (stdout followed by stderr)
+    pub fn tail(&self, lines: usize) -> String {
+        let all_lines = self
+            .stdout
+            .lines()
+            .chain(self.stderr.lines())
+            .collect::<Vec<_>>();
+        all_lines[all_lines.len().saturating_sub(lines)..].join("\n")
     }
+}
 
-    let match_spec =
-        MatchSpec::from_str(format!("{}={}={}", pkg.name, pkg.version, pkg.build_string).as_str())
-            .map_err(|e| TestError::MatchSpecParse(e.to_string()))?;
-    dependencies.push(match_spec);
+/// The inputs of an activation script: the prefix, shell, platform and `PATH` modification
+/// behavior, and the `CONDA_PREFIX` and (stripped) `PATH` of this process that the activation
+/// starts from. The extra environment variables of the tests are set after the activation
+/// script, so they are not part of it.
+type ActivationKey = (
+    PathBuf,
+    String,
+    Platform,
+    String,
+    Option<PathBuf>,
+    Option<Vec<PathBuf>>,
+);
 
-    let prefix = canonicalize(&config.test_prefix)?;
+/// The activation scripts of the test environments. The script of a prefix is computed once and
+/// reused for all tests of a package.
+static ACTIVATION_SCRIPTS: once_cell::sync::Lazy<std::sync::Mutex<HashMap<ActivationKey, String>>> =
+    once_cell::sync::Lazy::new(Default::default);
 
-    let global_configuration = tool_configuration::Configuration {
-        client: AuthenticatedClient::default(),
-        multi_progress_indicator: MultiProgress::new(),
-        no_clean: config.keep_test_prefix,
-        ..Default::default()
-    };
+/// Forget the cached activation scripts of the prefix, e.g. because its contents changed
+fn invalidate_activation_scripts(prefix: &Path) {
+    ACTIVATION_SCRIPTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|(cached_prefix, ..), _| cached_prefix != prefix);
+}
 
-    tracing::info!("Creating test environment in {:?}", prefix);
+/// How the shell is invoked to run a test script
+#[derive(Debug, Clone, Copy, Default)]
+struct ShellInvocation<'a> {
+    /// The executable that is run instead of the executable of the shell (with the same
+    /// arguments)
+    executable: Option<&'a Path>,
+    /// Additional arguments that are passed to the shell before the script
+    args: &'a [String],
+    /// If true, the additional arguments replace the default options of the shell (e.g. `/d`
+    /// for `cmd.exe`)
+    replace_default_args: bool,
+    /// If true, `bash` and `zsh` are started as a login shell (with `-l`)
+    login: bool,
+}
 
-    create_environment(
-        &dependencies,
-        &Platform::current(),
-        &prefix,
-        &config.channels,
-        &global_configuration,
-    )
-    .await
-    .map_err(TestError::TestEnvironmentSetup)?;
+/// The environment variables that are set in the script of every test command (in this order),
+/// before the environment is activated
+fn script_env_vars(
+    environment: &Path,
+    platform: Platform,
+    extra_env: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut vars = env_vars::os_vars(environment, &platform)
+        .into_iter()
+        .filter(|(key, _)| key != "PATH")
+        .collect::<Vec<_>>();
+
+    let prefix = environment.to_string_lossy().to_string();
+    vars.push(("PREFIX".to_string(), prefix.clone()));
+    // like in an activated conda environment, also if the activation is minimal or skipped
+    vars.push(("CONDA_PREFIX".to_string(), prefix));
+    if let Some(name) = environment.file_name() {
+        vars.push((
+            "CONDA_DEFAULT_ENV".to_string(),
+            name.to_string_lossy().to_string(),
+        ));
+    }
+
+    // `PATH` is set up by the activation and cannot be overridden
+    let mut extra_env = extra_env
+        .iter()
+        .filter(|(key, _)| key.as_str() != "PATH")
+        .map(|(key, val)| (key.clone(), val.clone()))
+        .collect::<Vec<_>>();
+    extra_env.sort();
+    vars.extend(extra_env);
+    vars
+}
 
-    let cache_key = CacheKey::from(pkg);
-    let dir = cache_dir.join("pkgs").join(cache_key.to_string());
+/// Build a command that runs `cmd` in the (activated) environment. The returned temporary path
+/// is the script that is executed and must be kept alive until the command has finished.
+#[allow(clippy::too_many_arguments)]
+fn environment_command(
+    shell: ShellEnum,
+    invocation: ShellInvocation<'_>,
+    cmd: &str,
+    cwd: &Path,
+    environment: &Path,
+    platform: Platform,
+    mode: ActivationMode,
+    path_modification: PathModificationBehavior,
+    extra_env: &HashMap<String, String>,
+    temp_dir: Option<&Path>,
+) -> Result<(std::process::Command, tempfile::TempPath), TestError> {
+    // the bin directories of (stacked) outer conda environments could shadow the executables
+    // of the test environment
+    let outer_prefixes = outer_conda_prefixes();
+    let current_path = std::env::var("PATH").ok().map(|p| {
+        strip_conda_paths(
+            std::env::split_paths(&p).collect(),
+            &outer_prefixes,
+            &platform,
+        )
+    });
 
-    tracing::info!("Collecting tests from {:?}", dir);
-    let (test_folder, tests) = tests_from_folder(&dir).await?;
+    let activation_script = match mode {
+        ActivationMode::Full => {
+            // if we are in a conda environment, we need to deactivate it before activating the host / build prefix
+            let conda_prefix = std::env::var("CONDA_PREFIX").ok().map(PathBuf::from);
 
-    for test in tests {
-        test.run(&prefix, &test_folder)?;
+            let key = (
+                environment.to_path_buf(),
+                shell.executable().to_string(),
+                platform,
+                format!("{:?}", path_modification),
+                conda_prefix.clone(),
+                current_path.clone(),
+            );
+            let av = ActivationVariables {
+                conda_prefix,
+                path: current_path,
+                path_modification_behaviour: path_modification,
+            };
+            let cached = ACTIVATION_SCRIPTS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&key)
+                .cloned();
+            match cached {
+                Some(script) => script,
+                None => {
+                    let activator = Activator::from_path(environment, shell.clone(), platform)?;
+                    let script = activator.activation(av)?.script;
+                    ACTIVATION_SCRIPTS
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert(key, script.clone());
+                    script
+                }
+            }
+        }
+        ActivationMode::Minimal => {
+            let mut script = ShellScript::new(shell.clone(), platform);
+            let path = prefix_bin_dirs(environment, &platform)
+                .into_iter()
+                .chain(current_path.unwrap_or_default())
+                .collect::<Vec<_>>();
+            let path = std::env::join_paths(path)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            script.set_env_var("PATH", path.to_string_lossy().as_ref());
+            script.contents
+        }
+    };
+
+    let suffix = format!(".{}", shell.extension());
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("rattler-test-").suffix(&suffix);
+    let mut tmpfile = match temp_dir {
+        Some(temp_dir) => {
+            fs::create_dir_all(temp_dir)?;
+            builder.tempfile_in(temp_dir)?
+        }
+        None => builder.tempfile()?,
+    };
+
+    let mut additional_script = ShellScript::new(shell.clone(), platform);
+    for (key, val) in script_env_vars(environment, platform, extra_env) {
+        additional_script.set_env_var(&key, &val);
     }
 
-    tracing::info!(
-        "{} all tests passed!",
-        console::style(console::Emoji("✔", "")).green()
+    let script = format!(
+        "{}\n{}\n{}\n",
+        additional_script.contents, activation_script, cmd
     );
+    tmpfile.write_all(normalize_line_endings(&script, &shell).as_bytes())?;
 
-    fs::remove_dir_all(prefix)?;
+    let tmpfile_path = tmpfile.into_temp_path();
+    let mut command = match invocation.executable {
+        Some(executable) => std::process::Command::new(executable),
+        None => std::process::Command::new(shell.executable()),
+    };
+    // the default options, the additional arguments and then the arguments that run the script
+    let (default_args, script_args): (&[&str], &[&str]) = match shell {
+        ShellEnum::Bash(_) | ShellEnum::Zsh(_) | ShellEnum::Fish(_) => (&[], &[]),
+        ShellEnum::CmdExe(_) => (&["/d"], &["/c"]),
+        ShellEnum::PowerShell(_) => (&["-NoLogo", "-NoProfile"], &["-File"]),
+        _ => return Err(TestError::UnsupportedShell(shell)),
+    };
+    if !invocation.replace_default_args {
+        command.args(default_args);
+    }
+    command.args(invocation.args);
+    // after the additional arguments, bash only accepts long options (e.g. `--noprofile`) first
+    if invocation.login && matches!(shell, ShellEnum::Bash(_) | ShellEnum::Zsh(_)) {
+        command.arg("-l");
+    }
+    command.args(script_args).arg(&tmpfile_path);
+    command.current_dir(cwd);
 
-    Ok(())
+    Ok((command, tmpfile_path))
+}
+
+/// Run the command of [`environment_command`] with the backend: unchanged on the host, or in a
+/// container in which the prefix, the working directory and the script are mounted
+fn backend_command(
+    command: std::process::Command,
+    backend: &TestBackend,
+    environment: &Path,
+    cwd: &Path,
+    script: &Path,
+) -> std::process::Command {
+    match backend {
+        TestBackend::Local => command,
+        TestBackend::Docker { image } => {
+            docker::docker_command(&command, image, &[environment, cwd, script])
+        }
+    }
+}
+
+/// Convert the line endings of a script to the convention of the shell: `cmd.exe` scripts use
+/// CRLF, all other shells LF (a `\r` at the end of a line breaks e.g. `bash`)
+fn normalize_line_endings(script: &str, shell: &ShellEnum) -> String {
+    let script = script.replace("\r\n", "\n");
+    match shell {
+        ShellEnum::CmdExe(_) => script.replace('\n', "\r\n"),
+        _ => script,
+    }
+}
+
+/// Log the contents of the script of a test command (see [`TestConfiguration::print_scripts`])
+fn print_script(script: &Path) -> Result<(), TestError> {
+    tracing::info!(
+        "Test script {:?}:\n{}",
+        script,
+        fs::read_to_string(script)?.trim_end()
+    );
+    Ok(())
+}
+
+/// Run `cmd` in the environment and capture its output. The output is also forwarded to the
+/// terminal. A non-zero exit code is not an error, use [`CommandOutput::success`] to check it.
+#[allow(clippy::too_many_arguments)]
+fn run_in_environment(
+    shell: ShellEnum,
+    cmd: String,
+    cwd: &Path,
+    environment: &Path,
+    platform: Platform,
+    mode: ActivationMode,
+    extra_env: &HashMap<String, String>,
+    log_file: Option<&Path>,
+    config: &TestConfiguration,
+) -> Result<CommandOutput, TestError> {
+    let mode = if config.skip_activation {
+        ActivationMode::Minimal
+    } else {
+        mode
+    };
+    let (command, script) = environment_command(
+        shell,
+        config.shell_invocation(),
+        &cmd,
+        cwd,
+        environment,
+        platform,
+        mode,
+        config.path_modification.clone(),
+        extra_env,
+        config.temp_dir.as_deref(),
+    )?;
+    if config.print_scripts {
+        print_script(&script)?;
+    }
+    let command = backend_command(command, &config.backend, environment, cwd, &script);
+
+    let timeout = config.timeout;
+    let progress = config.multi_progress.as_ref().map(|multi_progress| {
+        let progress =
+            multi_progress.add(ProgressBar::new_spinner().with_message("Running test command"));
+        progress.enable_steady_tick(Duration::from_millis(100));
+        progress
+    });
+    let output = run_command(command, timeout, progress.as_ref());
+    if let Some(progress) = &progress {
+        progress.finish_and_clear();
+    }
+    let output = output?.ok_or_else(|| TestError::Timeout {
+        command: cmd.clone(),
+        elapsed: timeout.unwrap_or_default(),
+    })?;
+    let log = match log_file {
+        Some(log_file) => Some(write_log_file(
+            log_file,
+            &cmd,
+            &output,
+            config.compress_logs,
+        )?),
+        None => None,
+    };
+
+    Ok(CommandOutput {
+        command: cmd,
+        exit_code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        log,
+    })
+}
+
+/// The error for a command (the shell that runs a test script, or the container runtime) that
+/// could not be started, as opposed to a test command that fails
+fn spawn_failed(command: &std::process::Command, source: std::io::Error) -> TestError {
+    TestError::ShellSpawnFailed {
+        executable: PathBuf::from(command.get_program()),
+        source,
+    }
+}
+
+/// Run the command and capture its output like [`std::process::Command::output`]. Every line of
+/// output is forwarded to the stdout or stderr of this process as soon as it is read, logged at
+/// debug level and shown as the message of the progress bar (if any) while the command is
+/// running. If a timeout is given, the command (and on Unix all processes it
+/// started) is killed if it does not finish in time, and `None` is returned. It is killed the same
+/// way if the process is interrupted ([`TestError::Interrupted`]). If the command cannot be
+/// started, the error is [`TestError::ShellSpawnFailed`].
+fn run_command(
+    mut command: std::process::Command,
+    timeout: Option<Duration>,
+    progress: Option<&ProgressBar>,
+) -> Result<Option<std::process::Output>, TestError> {
+    // start the command in its own process group, so that the whole group can be killed (on a
+    // timeout or when the process is interrupted)
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    let mut child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|source| spawn_failed(&command, source))?;
+    let _running = interrupt::RunningChild::new(&child);
+
+    // read the output in the background, otherwise the child blocks once the pipes are full
+    fn read_lines(
+        pipe: impl Read + Send + 'static,
+        progress: Option<ProgressBar>,
+        mut forward: impl Write + Send + 'static,
+    ) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut reader = std::io::BufReader::new(pipe);
+            let mut buffer = Vec::new();
+            loop {
+                let start = buffer.len();
+                match std::io::BufRead::read_until(&mut reader, b'\n', &mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let mut write = || {
+                            let _ = forward.write_all(&buffer[start..]);
+                            let _ = forward.flush();
+                        };
+                        match &progress {
+                            // the progress bars are hidden while the line is written
+                            Some(progress) => progress.suspend(write),
+                            None => write(),
+                        }
+
+                        let line = String::from_utf8_lossy(&buffer[start..]);
+                        let line = line.trim_end();
+                        tracing::debug!("{}", line);
+                        if let Some(progress) = &progress {
+                            progress.set_message(line.to_string());
+                        }
+                    }
+                }
+            }
+            buffer
+        })
+    }
+    let stdout = child
+        .stdout
+        .take()
+        .map(|pipe| read_lines(pipe, progress.cloned(), std::io::stdout()));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|pipe| read_lines(pipe, progress.cloned(), std::io::stderr()));
+
+    let kill = |child: &mut std::process::Child| {
+        #[cfg(unix)]
+        interrupt::kill_process_group(child.id());
+        let _ = child.kill();
+        let _ = child.wait();
+    };
+    let started = Instant::now();
+    let status = loop {
+        // on Windows the commands are already terminated when the process is interrupted
+        if interrupt::interrupted() {
+            kill(&mut child);
+            return Err(TestError::Interrupted);
+        }
+
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            kill(&mut child);
+            return Ok(None);
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    let join = |handle: Option<std::thread::JoinHandle<Vec<u8>>>| {
+        handle
+            .map(|handle| handle.join().unwrap_or_default())
+            .unwrap_or_default()
+    };
+
+    Ok(Some(std::process::Output {
+        status,
+        stdout: join(stdout),
+        stderr: join(stderr),
+    }))
+}
+
+/// Write the captured output of a test command to a log file. A compressed log is written with
+/// gzip to the log file with an additional `.gz` extension.
+fn write_log_file(
+    log_file: &Path,
+    cmd: &str,
+    output: &std::process::Output,
+    compress: bool,
+) -> Result<TestLog, std::io::Error> {
+    if let Some(parent) = log_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fn write_log(
+        mut file: impl Write,
+        cmd: &str,
+        output: &std::process::Output,
+    ) -> Result<(), std::io::Error> {
+        writeln!(file, "# command\n{}", cmd)?;
+        writeln!(file, "# exit status\n{}", output.status)?;
+        writeln!(file, "# stdout")?;
+        file.write_all(&output.stdout)?;
+        writeln!(file, "\n# stderr")?;
+        file.write_all(&output.stderr)?;
+        file.flush()
+    }
+
+    let path = if compress {
+        let path = log_file.with_extension("log.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(fs::File::create(&path)?, flate2::Compression::default());
+        write_log(&mut encoder, cmd, output)?;
+        encoder.finish()?;
+        path
+    } else {
+        write_log(fs::File::create(log_file)?, cmd, output)?;
+        log_file.to_path_buf()
+    };
+    let size = fs::metadata(&path)?.len();
+    Ok(TestLog { path, size })
+}
+
+/// The path at which a file of the package is installed into the prefix. Files of `noarch:
+/// python` packages in `site-packages` and `python-scripts` are installed into the
+/// `site-packages` and the scripts directory of the Python installation of the prefix.
+fn installed_path(prefix: &Path, relative_path: &Path, platform: Platform) -> PathBuf {
+    if let Ok(path) = relative_path.strip_prefix("site-packages") {
+        let site_packages = if platform.is_windows() {
+            Some(prefix.join("Lib/site-packages"))
+        } else {
+            fs::read_dir(prefix.join("lib")).ok().and_then(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path().join("site-packages"))
+                    .find(|dir| {
+                        dir.is_dir()
+                            && dir
+                                .parent()
+                                .and_then(Path::file_name)
+                                .map_or(false, |name| name.to_string_lossy().starts_with("python"))
+                    })
+            })
+        };
+        if let Some(site_packages) = site_packages {
+            return site_packages.join(path);
+        }
+    } else if let Ok(path) = relative_path.strip_prefix("python-scripts") {
+        let scripts = if platform.is_windows() {
+            "Scripts"
+        } else {
+            "bin"
+        };
+        return prefix.join(scripts).join(path);
+    }
+    prefix.join(relative_path)
+}
+
+/// Check that all files that are listed in the `info/paths.json` of the package exist in the
+/// prefix, and that files with a recorded (non-zero) size are not empty
+fn check_installed_files(
+    prefix: &Path,
+    package_folder: &Path,
+    platform: Platform,
+) -> Result<(), TestError> {
+    let paths_json = PathsJson::from_path(&package_folder.join("info/paths.json"))?;
+
+    let mut missing = Vec::new();
+    for entry in paths_json.paths {
+        let path = installed_path(prefix, &entry.relative_path, platform);
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            missing.push(entry.relative_path);
+            continue;
+        };
+        let empty = entry.path_type == PathType::HardLink
+            && metadata.is_file()
+            && metadata.len() == 0
+            && entry.size_in_bytes.map_or(false, |size| size > 0);
+        if empty {
+            missing.push(entry.relative_path);
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(TestError::MissingInstalledFile(missing))
+    }
+}
+
+/// The files of the `info/paths.json` of the package that still exist in the prefix after the
+/// package was removed, except for the files that belong to another installed package
+fn uninstall_leftovers(
+    prefix: &Path,
+    package_folder: &Path,
+    platform: Platform,
+    owned: &HashSet<PathBuf>,
+) -> Result<Vec<PathBuf>, TestError> {
+    let paths_json = PathsJson::from_path(&package_folder.join("info/paths.json"))?;
+    Ok(paths_json
+        .paths
+        .into_iter()
+        .filter(|entry| {
+            let path = installed_path(prefix, &entry.relative_path, platform);
+            let relative = path.strip_prefix(prefix).unwrap_or(&path);
+            !owned.contains(relative) && fs::symlink_metadata(&path).is_ok()
+        })
+        .map(|entry| entry.relative_path)
+        .collect())
+}
+
+/// Remove the package from the test environment and check that none of its files are left
+/// behind (see [`TestConfiguration::uninstall_check`])
+async fn check_uninstall(
+    prefix: &Path,
+    pkg: &ArchiveIdentifier,
+    package_folder: &Path,
+    config: &TestConfiguration,
+) -> Result<(), TestError> {
+    let platform = activation_platform(config.target_platform);
+    remove_package(&pkg.name, &platform, prefix, &config.tool_configuration())
+        .await
+        .map_err(TestError::TestEnvironmentSetup)?;
+
+    let owned = find_installed_packages(prefix, 100)
+        .await?
+        .into_iter()
+        .flat_map(|record| record.paths_data.paths)
+        .map(|entry| entry.relative_path)
+        .collect::<HashSet<_>>();
+    let leftovers = uninstall_leftovers(prefix, package_folder, platform, &owned)?;
+    if leftovers.is_empty() {
+        Ok(())
+    } else {
+        Err(TestError::IncompleteUninstall(leftovers))
+    }
+}
+
+/// Check that the installed copies of the files that have a prefix placeholder in the
+/// `info/paths.json` of the package no longer contain the placeholder. Binary files are not
+/// relocated on Windows and are not checked there.
+fn check_prefix_placeholders(
+    prefix: &Path,
+    package_folder: &Path,
+    platform: Platform,
+) -> Result<(), TestError> {
+    let paths_json = PathsJson::from_path(&package_folder.join("info/paths.json"))?;
+
+    let mut not_replaced = Vec::new();
+    for entry in paths_json.paths {
+        let Some(placeholder) = entry.prefix_placeholder else {
+            continue;
+        };
+        if placeholder.file_mode == FileMode::Binary && platform.is_windows() {
+            continue;
+        }
+        // missing files are reported by the check of the installed files
+        let Ok(contents) = fs::read(installed_path(prefix, &entry.relative_path, platform)) else {
+            continue;
+        };
+        let placeholder = placeholder.placeholder.as_bytes();
+        if !placeholder.is_empty()
+            && contents
+                .windows(placeholder.len())
+                .any(|window| window == placeholder)
+        {
+            not_replaced.push(entry.relative_path);
+        }
+    }
+
+    if not_replaced.is_empty() {
+        Ok(())
+    } else {
+        Err(TestError::PrefixNotReplaced(not_replaced))
+    }
+}
+
+/// Check that the name, version and build string in the `info/index.json` of the package match
+/// the file name of the package, and that its `depends` and `constrains` are valid match specs.
+/// The first inconsistency is reported.
+fn check_metadata(package_folder: &Path, pkg: &ArchiveIdentifier) -> Result<(), TestError> {
+    let inconsistent = |reason: String| {
+        Err(TestError::InconsistentMetadata {
+            package: format!("{}-{}-{}", pkg.name, pkg.version, pkg.build_string),
+            reason,
+        })
+    };
+    let index_json = IndexJson::from_package_directory(package_folder)?;
+
+    let name = index_json.name.as_normalized();
+    if name != pkg.name {
+        return inconsistent(format!(
+            "the name in info/index.json is '{}', the file name has '{}'",
+            name, pkg.name
+        ));
+    }
+    let same_version =
+        Version::from_str(&pkg.version).is_ok_and(|version| version == index_json.version);
+    if !same_version {
+        return inconsistent(format!(
+            "the version in info/index.json is '{}', the file name has '{}'",
+            index_json.version, pkg.version
+        ));
+    }
+    if index_json.build != pkg.build_string {
+        return inconsistent(format!(
+            "the build string in info/index.json is '{}', the file name has '{}'",
+            index_json.build, pkg.build_string
+        ));
+    }
+
+    let specs = [
+        ("depends", &index_json.depends),
+        ("constrains", &index_json.constrains),
+    ];
+    for (field, entries) in specs {
+        for entry in entries {
+            if let Err(e) = MatchSpec::from_str(entry) {
+                return inconsistent(format!(
+                    "the {} entry '{}' is not a valid match spec: {}",
+                    field, entry, e
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that every glob pattern (relative to the prefix) matches at least one path in the prefix
+fn check_files_exist(prefix: &Path, patterns: &[String]) -> Result<(), TestError> {
+    if patterns.is_empty() {
+        return Ok(());
+    }
+
+    let mut paths = Vec::new();
+    for entry in walkdir::WalkDir::new(prefix).min_depth(1) {
+        let entry = entry.map_err(std::io::Error::from)?;
+        if let Ok(relative) = entry.path().strip_prefix(prefix) {
+            paths.push(relative.to_path_buf());
+        }
+    }
+
+    for pattern in patterns {
+        let matcher = globset::GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()?
+            .compile_matcher();
+        if !paths.iter().any(|path| matcher.is_match(path)) {
+            return Err(TestError::MissingFile(pattern.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// The different types of tests that can be part of a package
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TestType {
+    /// Commands from `run_test.sh` or `run_test.bat`
+    Commands,
+    /// Python imports from `run_test.py`
+    Python,
+    /// Files that must exist in the prefix, from `run_test.json`
+    Files,
+    /// R scripts from `run_test.r` (or `run_test.R`)
+    R,
+    /// Scripts that are run with an interpreter, e.g. `run_test.pl` (with `perl`)
+    Interpreted,
+    /// Custom tests that are registered with [`TestConfiguration::register_runner`]
+    Custom,
+    /// `pip check` of the Python packages in the test environment
+    PipCheck,
+    /// The linkage check of the shared libraries of the package
+    Linkage,
+    /// The smoke test of the Python entry points of the package
+    EntryPoints,
+    /// The check of the menu entries (shortcuts) of the package
+    MenuInst,
+    /// A test suite (pytest or CTest) that is shipped in the test folder
+    TestSuite,
+    /// The check of the metadata (`info/index.json`) of the package
+    Metadata,
+    /// The check that the files of the package are installed into the prefix
+    InstalledFiles,
+    /// The check that the prefix placeholders of the installed files were replaced
+    PrefixCheck,
+}
+
+/// Which dependencies are installed into the test environment
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TestEnvironmentMode {
+    /// Only the package and its own dependencies, to find tests that rely on tools that are not
+    /// declared as dependencies of the package
+    Minimal,
+    /// The package, its dependencies and the test dependencies (`test_time_dependencies.json`,
+    /// the `requires` of the test manifest, the dependencies of the test types, the run exports
+    /// and the extra dependencies of the configuration)
+    #[default]
+    Full,
+    /// Only the package, without solving its dependencies, on top of a base environment that is
+    /// solved from the extra dependencies of the configuration (e.g. `python`). Failing tests
+    /// reveal dependencies that the package does not declare. Downstream tests use the minimal
+    /// environment instead.
+    NoDeps,
+}
+
+/// Where the test commands are executed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TestBackend {
+    /// Run the test commands on the host
+    #[default]
+    Local,
+    /// Run every test command in a new container of the image (with `docker run`). The test
+    /// prefix and the test folder are mounted into the container at the same paths as on the
+    /// host. The image needs to provide the shell that runs the test scripts.
+    Docker {
+        /// The image of the container, e.g. `ubuntu:22.04`
+        image: String,
+    },
+}
+
+/// Selects which of the tests of a package are run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TestFilter {
+    /// Run all tests
+    #[default]
+    All,
+    /// Only run the command tests (`run_test.sh` or `run_test.bat`)
+    CommandsOnly,
+    /// Only run the Python import tests (`run_test.py`)
+    ImportsOnly,
+    /// Only run the file tests (`run_test.json`)
+    FilesOnly,
+    /// Only run the tests with one of the given names (e.g. `run_test.py`)
+    ByName(Vec<String>),
+    /// Only run the test file with exactly this file name (e.g. `run_test.py`). Fails with
+    /// [`TestError::NoMatchingTest`] if the package has no such test file.
+    ByFileName(String),
+}
+
+/// Parse a filter from a command line argument: `all`, `commands`, `imports` and `files` select
+/// the tests by type, anything else is the file name of the single test file that is run
+impl FromStr for TestFilter {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "all" => TestFilter::All,
+            "commands" => TestFilter::CommandsOnly,
+            "imports" => TestFilter::ImportsOnly,
+            "files" => TestFilter::FilesOnly,
+            file_name => TestFilter::ByFileName(file_name.to_string()),
+        })
+    }
+}
+
+impl TestFilter {
+    fn matches(&self, test: &Tests) -> bool {
+        match self {
+            TestFilter::All => true,
+            TestFilter::CommandsOnly => test.test_type() == TestType::Commands,
+            TestFilter::ImportsOnly => test.test_type() == TestType::Python,
+            TestFilter::FilesOnly => test.test_type() == TestType::Files,
+            TestFilter::ByName(names) => names.contains(&test.name()),
+            TestFilter::ByFileName(file_name) => test
+                .path()
+                .and_then(Path::file_name)
+                .is_some_and(|name| name.to_string_lossy() == *file_name),
+        }
+    }
+}
+
+impl Tests {
+    /// Classify a file from the `info/test` folder, returns `None` if it is not a test
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?.to_string();
+        match file_name.as_str() {
+            "run_test.sh" | "run_test.bat" => Some(Tests::Commands(path)),
+            "run_test.py" => Some(Tests::Python(path)),
+            "run_test.json" => Some(Tests::Files(path)),
+            "run_test.r" | "run_test.R" => Some(Tests::R(path)),
+            _ => {
+                if let Some(runner) = TestSuiteRunner::from_file_name(&file_name) {
+                    let dir = path.parent()?.to_path_buf();
+                    return Some(Tests::TestSuite { runner, dir });
+                }
+                let extension = file_name.strip_prefix("run_test.")?;
+                let (_, interpreter) = TEST_SCRIPT_INTERPRETERS
+                    .iter()
+                    .find(|(ext, _)| *ext == extension)?;
+                Some(Tests::Interpreted {
+                    path,
+                    interpreter: *interpreter,
+                })
+            }
+        }
+    }
+
+    /// The test for a file that was selected with an include pattern, based on its extension
+    /// (`.sh` and `.bat` are commands, `.py` is a Python script, `.json` a list of files, ...)
+    fn from_extension(path: PathBuf) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_string();
+        match extension.as_str() {
+            "sh" | "bat" => Some(Tests::Commands(path)),
+            "py" => Some(Tests::Python(path)),
+            "json" => Some(Tests::Files(path)),
+            "r" | "R" => Some(Tests::R(path)),
+            _ => {
+                let (_, interpreter) = TEST_SCRIPT_INTERPRETERS
+                    .iter()
+                    .find(|(ext, _)| *ext == extension)?;
+                Some(Tests::Interpreted {
+                    path,
+                    interpreter: *interpreter,
+                })
+            }
+        }
+    }
+
+    /// Returns false if the test is a script for the shell of another platform (e.g. a
+    /// `run_test.bat` when testing on Linux)
+    fn runs_on(&self, platform: Platform) -> bool {
+        match self {
+            Tests::Commands(path) | Tests::MinimalActivation(path) => {
+                let ext = path.extension().and_then(|ext| ext.to_str());
+                matches!(
+                    (platform.is_windows(), ext),
+                    (true, Some("bat")) | (false, Some("sh"))
+                )
+            }
+            _ => true,
+        }
+    }
+
+    /// The type of the test
+    fn test_type(&self) -> TestType {
+        match self {
+            Tests::Commands(_) | Tests::MinimalActivation(_) => TestType::Commands,
+            Tests::Python(_) => TestType::Python,
+            Tests::Files(_) => TestType::Files,
+            Tests::R(_) => TestType::R,
+            Tests::Interpreted { .. } => TestType::Interpreted,
+            Tests::Custom(_) => TestType::Custom,
+            Tests::PipCheck => TestType::PipCheck,
+            Tests::LinkageCheck(_) => TestType::Linkage,
+            Tests::EntryPoints(_) => TestType::EntryPoints,
+            Tests::MenuInst(_) => TestType::MenuInst,
+            Tests::InstalledFiles(_) => TestType::InstalledFiles,
+            Tests::PrefixCheck(_) => TestType::PrefixCheck,
+            Tests::MetadataCheck { .. } => TestType::Metadata,
+            Tests::TestSuite { .. } => TestType::TestSuite,
+        }
+    }
+
+    /// The test file, for the tests that are defined by a file of the package
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Tests::Commands(path)
+            | Tests::MinimalActivation(path)
+            | Tests::Python(path)
+            | Tests::Files(path)
+            | Tests::R(path)
+            | Tests::Interpreted { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The name of the test, used e.g. to name log files
+    fn name(&self) -> String {
+        let path = match self {
+            Tests::Commands(path)
+            | Tests::Python(path)
+            | Tests::Files(path)
+            | Tests::R(path)
+            | Tests::Interpreted { path, .. } => path,
+            Tests::Custom(runner) => return runner.name(),
+            Tests::PipCheck => return PIP_CHECK_TEST_NAME.to_string(),
+            Tests::LinkageCheck(_) => return LINKAGE_CHECK_TEST_NAME.to_string(),
+            Tests::InstalledFiles(_) => return INSTALLED_FILES_TEST_NAME.to_string(),
+            Tests::PrefixCheck(_) => return PREFIX_CHECK_TEST_NAME.to_string(),
+            Tests::MetadataCheck { .. } => return METADATA_CHECK_TEST_NAME.to_string(),
+            Tests::EntryPoints(_) => return ENTRY_POINTS_TEST_NAME.to_string(),
+            Tests::MenuInst(_) => return MENUINST_TEST_NAME.to_string(),
+            Tests::TestSuite { runner, .. } => return runner.executable().to_string(),
+            Tests::MinimalActivation(_) => {
+                return format!("{}{}", self.options_name(), MINIMAL_ACTIVATION_SUFFIX)
+            }
+        };
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string_lossy().to_string())
+    }
+
+    /// The name under which the options of the test are found in the test manifest. The run
+    /// with the minimal activation has the options of its script.
+    fn options_name(&self) -> String {
+        match self {
+            Tests::MinimalActivation(path) => Tests::Commands(path.clone()).name(),
+            _ => self.name(),
+        }
+    }
+
+    /// The description of the test (see [`list_tests`])
+    fn description(&self) -> Result<TestDescription, TestError> {
+        let imports = match self {
+            Tests::Python(path) => fs::read_to_string(path)?
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("import "))
+                .map(|module| module.trim().to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(TestDescription {
+            name: self.name(),
+            test_type: self.test_type(),
+            imports,
+        })
+    }
+
+    /// Run the test and turn an unsuccessful exit of the test command into an error. A failing
+    /// (or timed out) test command is re-run up to `config.retries` times, waiting
+    /// `config.retry_backoff` before the first retry and twice as long before every further one.
+    /// If a deadline is given, every attempt is killed once it is reached and no further attempt
+    /// is started ([`TestError::TotalTimeout`]).
+    fn run_checked(
+        &self,
+        environment: &Path,
+        cwd: &Path,
+        config: &TestConfiguration,
+        env_vars: &HashMap<String, String>,
+        log_dir: Option<&Path>,
+        deadline: Option<Instant>,
+    ) -> (Option<CommandOutput>, Result<(), TestError>) {
+        let attempts = config.retries.saturating_add(1);
+        let mut attempt = 1;
+        let base_config = config;
+        let mut deadline_config;
+        loop {
+            // the command of the attempt is killed like a timed out command at the deadline
+            let config = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return (None, Err(total_timeout_error(base_config, self.name())));
+                    }
+                    deadline_config = base_config.clone();
+                    deadline_config.timeout = Some(
+                        base_config
+                            .timeout
+                            .map_or(remaining, |timeout| timeout.min(remaining)),
+                    );
+                    &deadline_config
+                }
+                None => base_config,
+            };
+
+            if attempts > 1 {
+                test_progress!(
+                    config,
+                    "Running {} (attempt {} of {})",
+                    self.name(),
+                    attempt,
+                    attempts
+                );
+            }
+            let (output, result) = self.run_attempt(environment, cwd, config, env_vars, log_dir);
+            let retry = matches!(
+                result,
+                Err(TestError::TestFailed { .. } | TestError::Timeout { .. })
+            );
+            if !retry || attempt >= attempts {
+                return (output, result);
+            }
+
+            let backoff = config
+                .retry_backoff
+                .saturating_mul(2u32.saturating_pow(attempt - 1));
+            if let Err(e) = &result {
+                tracing::warn!(
+                    "Attempt {} of {} of {} failed, retrying in {:?}: {}",
+                    attempt,
+                    attempts,
+                    self.name(),
+                    backoff,
+                    e
+                );
+            }
+            std::thread::sleep(backoff);
+            attempt += 1;
+        }
+    }
+
+    /// Run the test with [`Tests::run_checked`] on the blocking thread pool of the runtime (in
+    /// the given span), so that the packages that are tested concurrently are not stalled while
+    /// the test command runs
+    #[allow(clippy::too_many_arguments)]
+    async fn run_checked_blocking(
+        &self,
+        environment: &Path,
+        cwd: &Path,
+        config: &TestConfiguration,
+        env_vars: &HashMap<String, String>,
+        log_dir: Option<&Path>,
+        deadline: Option<Instant>,
+        span: tracing::Span,
+    ) -> (Option<CommandOutput>, Result<(), TestError>) {
+        let test = self.clone();
+        let (environment, cwd) = (environment.to_path_buf(), cwd.to_path_buf());
+        let (config, env_vars) = (config.clone(), env_vars.clone());
+        let log_dir = log_dir.map(Path::to_path_buf);
+        let result = run_blocking(move || {
+            Ok(span.in_scope(|| {
+                test.run_checked(
+                    &environment,
+                    &cwd,
+                    &config,
+                    &env_vars,
+                    log_dir.as_deref(),
+                    deadline,
+                )
+            }))
+        })
+        .await;
+        result.unwrap_or_else(|e| (None, Err(e)))
+    }
+
+    /// Run the test once, see [`Tests::run_checked`]
+    fn run_attempt(
+        &self,
+        environment: &Path,
+        cwd: &Path,
+        config: &TestConfiguration,
+        env_vars: &HashMap<String, String>,
+        log_dir: Option<&Path>,
+    ) -> (Option<CommandOutput>, Result<(), TestError>) {
+        match self.run(environment, cwd, config, env_vars, log_dir) {
+            Ok(Some(output)) if !output.accepted(&config.allowed_exit_codes) => {
+                let error = TestError::TestFailed {
+                    test: self.name(),
+                    command: output.command.clone(),
+                    exit_code: output.exit_code,
+                    output_tail: if config.capture_output {
+                        output.tail(OUTPUT_TAIL_LINES)
+                    } else {
+                        String::new()
+                    },
+                };
+                (Some(output), Err(error))
+            }
+            Ok(Some(output)) if config.fail_on_stderr && !output.stderr.trim().is_empty() => {
+                let lines = output.stderr.lines().collect::<Vec<_>>();
+                let error = TestError::UnexpectedStderr {
+                    test: self.name(),
+                    stderr_tail: lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..].join("\n"),
+                };
+                (Some(output), Err(error))
+            }
+            Ok(output) => (output, Ok(())),
+            Err(e) => (None, Err(e)),
+        }
+    }
+
+    /// Run the test. Returns the captured output of the test command, if the test runs a command.
+    /// The test failed if an error is returned or the command did not exit successfully.
+    fn run(
+        &self,
+        environment: &Path,
+        cwd: &Path,
+        config: &TestConfiguration,
+        env_vars: &HashMap<String, String>,
+        log_dir: Option<&Path>,
+    ) -> Result<Option<CommandOutput>, TestError> {
+        let default_shell = ShellEnum::default();
+        let platform = activation_platform(config.target_platform);
+        let log_file =
+            |suffix: &str| log_dir.map(|dir| dir.join(format!("{}{suffix}.log", self.name())));
+
+        match self {
+            Tests::Commands(path) => {
+                // scripts for the shell of another platform are skipped before running the test
+                if !self.runs_on(platform) {
+                    return Ok(None);
+                }
+                let contents = fs::read_to_string(path)?;
+                test_progress!(config, "Testing commands:");
+                let output = run_in_environment(
+                    default_shell,
+                    contents,
+                    cwd,
+                    environment,
+                    platform,
+                    ActivationMode::Full,
+                    env_vars,
+                    log_file("").as_deref(),
+                    config,
+                )?;
+                Ok(Some(output))
+            }
+            Tests::MinimalActivation(path) => {
+                if !self.runs_on(platform) {
+                    return Ok(None);
+                }
+                let contents = fs::read_to_string(path)?;
+                test_progress!(config, "Testing commands with minimal activation:");
+                let log_file =
+                    log_dir.map(|dir| dir.join(format!("{}-minimal.log", self.options_name())));
+                let output = run_in_environment(
+                    default_shell,
+                    contents,
+                    cwd,
+                    environment,
+                    platform,
+                    ActivationMode::Minimal,
+                    env_vars,
+                    log_file.as_deref(),
+                    config,
+                )?;
+                Ok(Some(output))
+            }
+            Tests::Python(path) => {
+                let imports = fs::read_to_string(path)?;
+                test_progress!(config, "Testing Python imports:\n{imports}");
+                let python = find_in_prefix(environment, &platform, &["python", "python3"])
+                    .ok_or_else(|| TestError::MissingInterpreter {
+                        name: "python".to_string(),
+                    })?;
+                let python = interpreter_command(python, &config.python_args);
+
+                // every module is imported in isolation, so that all failing imports are found
+                if let Some(modules) = import_statements(&imports) {
+                    let mut failed = Vec::new();
+                    for module in modules {
+                        let output = run_in_environment(
+                            default_shell.clone(),
+                            format!("{} -c \"import {}\"", python, module),
+                            cwd,
+                            environment,
+                            platform,
+                            ActivationMode::Full,
+                            env_vars,
+                            log_file(&format!("-{}", module)).as_deref(),
+                            config,
+                        )?;
+                        if !output.success() {
+                            let error = output
+                                .stderr
+                                .lines()
+                                .rev()
+                                .find(|line| !line.trim().is_empty())
+                                .unwrap_or("import failed")
+                                .trim()
+                                .to_string();
+                            failed.push((module, error));
+                        }
+                    }
+                    return if failed.is_empty() {
+                        Ok(None)
+                    } else {
+                        Err(TestError::ImportsFailed(failed))
+                    };
+                }
+
+                run_in_environment(
+                    default_shell,
+                    format!("{} {}", python, path.to_string_lossy()),
+                    cwd,
+                    environment,
+                    platform,
+                    ActivationMode::Full,
+                    env_vars,
+                    log_file("").as_deref(),
+                    config,
+                )
+                .map(Some)
+            }
+            Tests::R(path) => {
+                test_progress!(config, "Testing R script {:?}", path);
+                let r = find_in_prefix(environment, &platform, &["R"]).ok_or_else(|| {
+                    TestError::MissingInterpreter {
+                        name: "R".to_string(),
+                    }
+                })?;
+                let r = interpreter_command(
+                    r,
+                    config
+                        .interpreter_args
+                        .get(r)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default(),
+                );
+                run_in_environment(
+                    default_shell,
+                    format!("{} --vanilla -f {}", r, path.to_string_lossy()),
+                    cwd,
+                    environment,
+                    platform,
+                    ActivationMode::Full,
+                    env_vars,
+                    log_file("").as_deref(),
+                    config,
+                )
+                .map(Some)
+            }
+            Tests::Interpreted { path, interpreter } => {
+                test_progress!(config, "Testing {:?} with {}", path, interpreter);
+                let interpreter = interpreter_command(
+                    interpreter,
+                    config
+                        .interpreter_args
+                        .get(*interpreter)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default(),
+                );
+                run_in_environment(
+                    default_shell,
+                    format!("{} {}", interpreter, path.to_string_lossy()),
+                    cwd,
+                    environment,
+                    platform,
+                    ActivationMode::Full,
+                    env_vars,
+                    log_file("").as_deref(),
+                    config,
+                )
+                .map(Some)
+            }
+            Tests::Files(path) => {
+                let patterns: Vec<String> = serde_json::from_str(&fs::read_to_string(path)?)?;
+                test_progress!(config, "Testing files:\n{}", patterns.join("\n"));
+                check_files_exist(environment, &patterns)?;
+                Ok(None)
+            }
+            Tests::Custom(runner) => {
+                test_progress!(config, "Running custom test {}", runner.name());
+                runner.run(environment, cwd)?;
+                Ok(None)
+            }
+            Tests::PipCheck => {
+                let python = find_in_prefix(environment, &platform, &["python", "python3"]);
+                let pip = find_in_prefix(environment, &platform, &["pip", "pip3"]);
+                let (Some(python), Some(_)) = (python, pip) else {
+                    tracing::warn!(
+                        "Skipping pip check: python or pip is not installed in the test environment"
+                    );
+                    return Ok(None);
+                };
+
+                test_progress!(config, "Running pip check");
+                let output = run_in_environment(
+                    default_shell,
+                    format!("{} -m pip check", python),
+                    cwd,
+                    environment,
+                    platform,
+                    ActivationMode::Full,
+                    env_vars,
+                    log_file("").as_deref(),
+                    config,
+                )?;
+                if output.success() {
+                    return Ok(Some(output));
+                }
+
+                let problems = pip_check_problems(&output.stdout);
+                if problems.is_empty() {
+                    // not a dependency problem, report the failing command instead
+                    return Ok(Some(output));
+                }
+                Err(TestError::PipCheckFailed(problems))
+            }
+            Tests::LinkageCheck(package_folder) => {
+                test_progress!(
+                    config,
+                    "Checking the linkage of the libraries of the package"
+                );
+                let problems = linkage::check_linkage(
+                    environment,
+                    package_folder,
+                    platform,
+                    &config.linkage_allowlist,
+                )?;
+                if problems.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(TestError::LinkageProblems(problems))
+                }
+            }
+            Tests::InstalledFiles(package_folder) => {
+                test_progress!(config, "Checking the installed files of the package");
+                check_installed_files(environment, package_folder, platform)?;
+                Ok(None)
+            }
+            Tests::PrefixCheck(package_folder) => {
+                test_progress!(config, "Checking the prefix replacement of the package");
+                check_prefix_placeholders(environment, package_folder, platform)?;
+                Ok(None)
+            }
+            Tests::MetadataCheck {
+                package_folder,
+                pkg,
+            } => {
+                test_progress!(config, "Checking the metadata of the package");
+                check_metadata(package_folder, pkg)?;
+                Ok(None)
+            }
+            Tests::EntryPoints(package_folder) => {
+                let entry_points = entry_points_from_folder(package_folder)?;
+                test_progress!(
+                    config,
+                    "Testing entry points: {}",
+                    entry_points
+                        .iter()
+                        .map(|ep| ep.command.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+
+                let mut failed = Vec::new();
+                for entry_point in entry_points {
+                    let executable =
+                        entry_point_executable(environment, &entry_point.command, platform);
+                    if !executable.is_file() {
+                        failed.push((
+                            entry_point.command,
+                            format!("{} is not installed", executable.display()),
+                        ));
+                        continue;
+                    }
+
+                    let output = run_in_environment(
+                        default_shell.clone(),
+                        format!("\"{}\" --help", executable.display()),
+                        cwd,
+                        environment,
+                        platform,
+                        ActivationMode::Full,
+                        env_vars,
+                        log_file(&format!("-{}", entry_point.command)).as_deref(),
+                        config,
+                    )?;
+                    if !output.success() {
+                        failed.push((
+                            entry_point.command,
+                            format!(
+                                "`--help` failed with {}",
+                                format_exit_code(output.exit_code)
+                            ),
+                        ));
+                    }
+                }
+
+                if failed.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(TestError::EntryPointsFailed(failed))
+                }
+            }
+            Tests::MenuInst(package_folder) => {
+                test_progress!(config, "Checking the menu entries of the package");
+                let problems = menuinst::check_menus(environment, package_folder, platform)?;
+                if problems.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(TestError::MenuInstFailed(problems))
+                }
+            }
+            Tests::TestSuite { runner, dir } => {
+                test_progress!(
+                    config,
+                    "Running the {} test suite in {:?}",
+                    runner.executable(),
+                    dir
+                );
+                if find_in_prefix(environment, &platform, &[runner.executable()]).is_none() {
+                    return Err(TestError::MissingInterpreter {
+                        name: runner.executable().to_string(),
+                    });
+                }
+                let output = run_in_environment(
+                    default_shell,
+                    runner.command(),
+                    dir,
+                    environment,
+                    platform,
+                    ActivationMode::Full,
+                    env_vars,
+                    log_file("").as_deref(),
+                    config,
+                )?;
+                if let Some(summary) = runner.summary(&output.stdout) {
+                    test_progress!(config, "{}: {}", runner.executable(), summary);
+                }
+                Ok(Some(output))
+            }
+        }
+    }
+}
+
+/// Build a glob set from patterns that are matched against paths relative to the test folder
+fn test_file_globs(patterns: &[String]) -> Result<globset::GlobSet, TestError> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            globset::GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+/// Find the tests in the `info/test` folder of the package. Without include patterns, the
+/// `run_test.*` files in the test folder are the tests. Files (also in subdirectories) that match
+/// one of the include patterns are tests as well, their type is derived from the extension (e.g.
+/// `tests/*.sh` are command tests). Files that match one of the exclude patterns are never run,
+/// even if they also match an include pattern or are `run_test.*` files.
+async fn tests_from_folder(
+    pkg: &Path,
+    include: &[String],
+    exclude: &[String],
+) -> Result<(PathBuf, Vec<Tests>), TestError> {
+    let mut tests = Vec::new();
+
+    let test_folder = pkg.join("info").join("test");
+
+    if !test_folder.exists() {
+        return Ok((test_folder, tests));
+    }
+
+    let exclude = test_file_globs(exclude)?;
+    let is_excluded = |path: &Path| {
+        path.strip_prefix(&test_folder)
+            .map_or(false, |relative| exclude.is_match(relative))
+    };
+
+    // the tests that are selected with include patterns run after the `run_test.*` files
+    let mut included = Vec::new();
+    if !include.is_empty() {
+        let include = test_file_globs(include)?;
+        for entry in walkdir::WalkDir::new(&test_folder)
+            .min_depth(1)
+            .sort_by_file_name()
+        {
+            let entry = entry.map_err(std::io::Error::from)?;
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(&test_folder) else {
+                continue;
+            };
+            // the test files in the test folder itself are found below
+            if !entry.file_type().is_file()
+                || (relative.parent() == Some(Path::new(""))
+                    && Tests::from_path(path.to_path_buf()).is_some())
+                || !include.is_match(relative)
+                || exclude.is_match(relative)
+            {
+                continue;
+            }
+            match Tests::from_extension(path.to_path_buf()) {
+                Some(test) => included.push(test),
+                None => {
+                    tracing::warn!("Ignoring {:?}, it is not a known type of test file", path);
+                }
+            }
+        }
+    }
+
+    let mut read_dir = tokio::fs::read_dir(&test_folder).await?;
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+        if path.is_dir() {
+            continue;
+        }
+        let is_test_file = path
+            .file_stem()
+            .map_or(false, |stem| stem.to_string_lossy().starts_with("run_test"));
+        if is_excluded(&path) {
+            tracing::debug!("Not running {:?}, it matches an exclude pattern", path);
+            continue;
+        }
+        match Tests::from_path(path.clone()) {
+            Some(test) => tests.push(test),
+            None if is_test_file => {
+                tracing::warn!("Ignoring {:?}, it is not a known type of test file", path);
+            }
+            None => {}
+        }
+    }
+    tests.extend(included);
+
+    // a suite can be configured by several files (e.g. `pytest.ini` and `conftest.py`)
+    let mut runners = Vec::new();
+    tests.retain(|test| match test {
+        Tests::TestSuite { runner, .. } if runners.contains(runner) => false,
+        Tests::TestSuite { runner, .. } => {
+            runners.push(*runner);
+            true
+        }
+        _ => true,
+    });
+
+    Ok((test_folder, tests))
+}
+
+/// Run blocking work (like reading, verifying or extracting a package archive) on the blocking
+/// thread pool of the runtime, so that large archives do not stall the other tasks
+async fn run_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, TestError> + Send + 'static,
+) -> Result<T, TestError> {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(err) => match err.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(err) => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, err).into()),
+        },
+    }
+}
+
+/// Find the file at `find_path` in a (streamed) tar archive and pass a reader of its contents
+/// to `read`. The entries in front of the file are skipped without buffering them, and the
+/// archive is not read any further once the file was found.
+fn file_from_tar<R: Read, T>(
+    mut archive: tar::Archive<R>,
+    archive_path: &Path,
+    find_path: &Path,
+    read: impl FnOnce(&mut dyn Read) -> Result<T, std::io::Error>,
+) -> Result<T, std::io::Error> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?;
+        if path == find_path {
+            return read(&mut entry);
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{:?} not found in {:?}", find_path, archive_path),
+    ))
+}
+
+/// Read the contents of a file of a package (from `info/` or the payload) directly from the
+/// package archive, without extracting it. For `.conda` archives, the file is read from the
+/// metadata or the payload tarball depending on its path.
+///
+/// The archive is read synchronously, which blocks the current thread until the file is found.
+/// In async code, call this from a blocking thread (e.g. with `tokio::task::spawn_blocking`).
+pub fn read_package_file(archive: &Path, inner: &Path) -> Result<Vec<u8>, TestError> {
+    with_package_file(archive, inner, |reader| {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        Ok(contents)
+    })
+}
+
+/// Like [`read_package_file`], but instead of reading the whole file into memory, a streaming
+/// reader of its contents is passed to `read` (e.g. to hash a large payload file). Like
+/// [`read_package_file`], this blocks the current thread.
+pub fn with_package_file<T>(
+    archive: &Path,
+    inner: &Path,
+    read: impl FnOnce(&mut dyn Read) -> Result<T, std::io::Error>,
+) -> Result<T, TestError> {
+    let archive_type = ArchiveType::try_from(archive).ok_or(TestError::ArchiveTypeNotSupported)?;
+    Ok(package_file_contents(archive_type, archive, inner, read)?)
+}
+
+fn package_file_contents<T>(
+    archive_type: ArchiveType,
+    archive_path: &Path,
+    find_path: &Path,
+    read: impl FnOnce(&mut dyn Read) -> Result<T, std::io::Error>,
+) -> Result<T, std::io::Error> {
+    let reader = std::io::BufReader::new(std::fs::File::open(archive_path)?);
+    let to_io_error = |e: rattler_package_streaming::ExtractError| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    };
+
+    match archive_type {
+        ArchiveType::TarBz2 => {
+            let archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+            file_from_tar(archive, archive_path, find_path, read)
+        }
+        // `.conda` archives store the metadata (`info/`) and the payload in separate tarballs
+        ArchiveType::Conda if find_path.starts_with("info") => {
+            let archive =
+                rattler_package_streaming::seek::stream_conda_info(reader).map_err(to_io_error)?;
+            file_from_tar(archive, archive_path, find_path, read)
+        }
+        ArchiveType::Conda => {
+            let archive = rattler_package_streaming::seek::stream_conda_content(reader)
+                .map_err(to_io_error)?;
+            file_from_tar(archive, archive_path, find_path, read)
+        }
+    }
+}
+
+/// List the files that are directly inside of the `info/test` folder of an archive
+fn info_test_files(
+    archive_type: ArchiveType,
+    archive_path: &Path,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let reader = std::fs::File::open(archive_path)?;
+    let test_folder = Path::new("info/test");
+    let mut files = Vec::new();
+
+    match archive_type {
+        ArchiveType::TarBz2 => {
+            let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+            for entry in archive.entries()? {
+                let path = entry?.path()?.into_owned();
+                if path.parent() == Some(test_folder) {
+                    files.push(path);
+                }
+            }
+        }
+        ArchiveType::Conda => {
+            let mut archive = rattler_package_streaming::seek::stream_conda_info(reader)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            for entry in archive.entries()? {
+                let path = entry?.path()?.into_owned();
+                if path.parent() == Some(test_folder) {
+                    files.push(path);
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Extract the complete contents of a package into the `destination` directory, e.g. to inspect
+/// the package after a failing test. The package cache is not touched.
+///
+/// The extraction is synchronous and blocks the current thread. In async code, call this from a
+/// blocking thread (e.g. with `tokio::task::spawn_blocking`).
+pub fn extract_package(package_file: &Path, destination: &Path) -> Result<(), TestError> {
+    let archive_type =
+        ArchiveType::try_from(package_file).ok_or(TestError::ArchiveTypeNotSupported)?;
+
+    // the paths of the package can exceed MAX_PATH on Windows
+    let destination = extended_length_path(destination);
+    fs::create_dir_all(&destination)?;
+    let reader = std::fs::File::open(package_file)?;
+    match archive_type {
+        ArchiveType::TarBz2 => {
+            rattler_package_streaming::read::extract_tar_bz2(reader, &destination)?;
+        }
+        ArchiveType::Conda => {
+            rattler_package_streaming::read::extract_conda(reader, &destination)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpack the `info/test` folder of a (streamed) tar archive into the destination
+fn unpack_test_folder<R: Read>(
+    mut archive: tar::Archive<R>,
+    destination: &Path,
+) -> Result<(), std::io::Error> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.starts_with("info/test") {
+            entry.unpack_in(destination)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extract only the `info/test` folder of a package into the destination
+fn extract_test_folder(
+    archive_type: ArchiveType,
+    archive_path: &Path,
+    destination: &Path,
+) -> Result<(), std::io::Error> {
+    let reader = std::fs::File::open(archive_path)?;
+    match archive_type {
+        ArchiveType::TarBz2 => unpack_test_folder(
+            rattler_package_streaming::read::stream_tar_bz2(reader),
+            destination,
+        ),
+        ArchiveType::Conda => unpack_test_folder(
+            rattler_package_streaming::seek::stream_conda_info(reader)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            destination,
+        ),
+    }
+}
+
+/// A description of a test of a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestDescription {
+    /// The name of the test (e.g. `run_test.py`)
+    pub name: String,
+    /// The type of the test
+    pub test_type: TestType,
+    /// The modules that are imported, for Python import tests
+    pub imports: Vec<String>,
+}
+
+/// List the tests of a package without running them
+///
+/// Only the `info/test` folder of the package is extracted (into a temporary directory), no test
+/// environment is created and no network access is needed. Tests that are defined in the test
+/// manifest are listed instead of the legacy test files, like [`run_test`] would run them.
+pub async fn list_tests(package_file: &Path) -> Result<Vec<TestDescription>, TestError> {
+    list_discovered_tests(package_file, &[], &[]).await
+}
+
+/// Like [`list_tests`], with include and exclude patterns for the test files (see
+/// [`TestConfiguration::test_include`])
+async fn list_discovered_tests(
+    package_file: &Path,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<TestDescription>, TestError> {
+    let archive_type =
+        ArchiveType::try_from(package_file).ok_or(TestError::ArchiveTypeNotSupported)?;
+
+    let tmp_dir = tempfile::tempdir()?;
+    let package_dir = tmp_dir.path().join("package");
+    extract_test_folder(archive_type, package_file, &package_dir)?;
+
+    let (test_folder, mut tests) = tests_from_folder(&package_dir, include, exclude).await?;
+    let manifest = TestManifest::from_folder(&test_folder)?;
+    if manifest.defines_tests() {
+        tests = manifest
+            .write_test_files(tmp_dir.path(), Platform::current())?
+            .into_iter()
+            .filter_map(Tests::from_path)
+            .collect();
+    }
+    if manifest.pip_check {
+        tests.push(Tests::PipCheck);
+    }
+    if manifest.linkage_check {
+        tests.push(Tests::LinkageCheck(package_dir.clone()));
+    }
+    if manifest.entry_point_check {
+        tests.push(Tests::EntryPoints(package_dir.clone()));
+    }
+    if manifest.menuinst_check {
+        tests.push(Tests::MenuInst(package_dir.clone()));
+    }
+    if manifest.prefix_check {
+        tests.push(Tests::PrefixCheck(package_dir.clone()));
+    }
+    if manifest.metadata_check {
+        let pkg = ArchiveIdentifier::try_from_path(package_file)
+            .ok_or_else(|| TestError::InvalidPackageFileName(package_file.to_path_buf()))?;
+        tests.push(Tests::MetadataCheck {
+            package_folder: package_dir.clone(),
+            pkg,
+        });
+    }
+
+    let mut descriptions = tests
+        .iter()
+        .map(Tests::description)
+        .collect::<Result<Vec<_>, TestError>>()?;
+    descriptions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(descriptions)
+}
+
+/// Compare the files of a (streamed) tar archive with their expected SHA256 hashes
+fn verify_tar_contents<R: Read>(
+    mut archive: tar::Archive<R>,
+    expected: &HashMap<PathBuf, String>,
+) -> Result<(), TestError> {
+    use sha2::Digest;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let Some(expected) = expected.get(&path) else {
+            continue;
+        };
+        let mut hasher = sha2::Sha256::new();
+        std::io::copy(&mut entry, &mut hasher)?;
+        let actual = format!("{:x}", hasher.finalize());
+        if &actual != expected {
+            return Err(TestError::ChecksumMismatch {
+                file: path.to_string_lossy().to_string(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Verify the integrity of a package before it is extracted and tested
+///
+/// If an expected SHA256 or MD5 hash is configured, the hash of the package file is compared to
+/// it. Otherwise, the package is only verified if `config.verify_package_contents` is set: then
+/// the contents of every file in the package are compared to the SHA256 hash that is recorded for
+/// it in `info/paths.json`, which means decompressing the whole package once more.
+fn verify_package_checksum(
+    package_file: &Path,
+    archive_type: ArchiveType,
+    sha256: &str,
+    config: &TestConfiguration,
+) -> Result<(), TestError> {
+    let file_name = package_file.to_string_lossy().to_string();
+
+    if let Some(expected) = &config.expected_sha256 {
+        if !expected.eq_ignore_ascii_case(sha256) {
+            return Err(TestError::ChecksumMismatch {
+                file: file_name,
+                expected: expected.to_lowercase(),
+                actual: sha256.to_string(),
+            });
+        }
+        return Ok(());
+    }
+
+    if let Some(expected) = &config.expected_md5 {
+        let actual = format!(
+            "{:x}",
+            rattler_digest::compute_file_digest::<rattler_digest::Md5>(package_file)?
+        );
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(TestError::ChecksumMismatch {
+                file: file_name,
+                expected: expected.to_lowercase(),
+                actual,
+            });
+        }
+        return Ok(());
+    }
+
+    if !config.verify_package_contents {
+        return Ok(());
+    }
+
+    let paths_json =
+        match file_from_archive(archive_type, package_file, Path::new("info/paths.json")) {
+            Ok(contents) => PathsJson::from_str(&contents)?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                tracing::debug!(
+                    "{:?} contains no info/paths.json, not verifying its contents",
+                    package_file
+                );
+                return Ok(());
+            }
+            Err(error) => return Err(TestError::PackageRead(error)),
+        };
+
+    // symlinks are stored without contents, their hash is the hash of the target
+    let expected = paths_json
+        .paths
+        .into_iter()
+        .filter(|entry| entry.path_type == PathType::HardLink)
+        .filter_map(|entry| {
+            entry
+                .sha256
+                .map(|sha256| (entry.relative_path, format!("{:x}", sha256)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let reader = std::fs::File::open(package_file)?;
+    match archive_type {
+        ArchiveType::TarBz2 => verify_tar_contents(
+            rattler_package_streaming::read::stream_tar_bz2(reader),
+            &expected,
+        ),
+        ArchiveType::Conda => verify_tar_contents(
+            rattler_package_streaming::seek::stream_conda_content(reader)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            &expected,
+        ),
+    }
+}
+
+/// Read a text file from an archive, see [`read_package_file`]
+fn file_from_archive(
+    archive_type: ArchiveType,
+    archive_path: &Path,
+    find_path: &Path,
+) -> Result<String, std::io::Error> {
+    let contents = package_file_contents(archive_type, archive_path, find_path, |reader| {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        Ok(contents)
+    })?;
+    String::from_utf8(contents).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Command names that are commonly available on a system and that a package should not
+/// accidentally shadow with one of its entry points
+const WELL_KNOWN_COMMANDS: &[&str] = &[
+    "bash", "build", "cat", "cd", "clear", "cmp", "cp", "curl", "date", "diff", "echo", "env",
+    "file", "find", "git", "grep", "gzip", "head", "install", "kill", "less", "ln", "ls", "make",
+    "man", "mkdir", "more", "mv", "open", "patch", "pip", "ps", "python", "rm", "run", "sed",
+    "server", "setup", "sh", "sort", "ssh", "start", "sudo", "tail", "tar", "test", "time",
+    "touch", "tree", "which", "yes",
+];
+
+/// Read the entry points that a (noarch: python) package declares in its `info/link.json` (in
+/// the given package folder)
+fn entry_points_from_folder(package_folder: &Path) -> Result<Vec<EntryPoint>, std::io::Error> {
+    match LinkJson::from_path(&package_folder.join("info/link.json")) {
+        Ok(link_json) => match link_json.noarch {
+            NoArchLinks::Python(python) => Ok(python.entry_points),
+            NoArchLinks::Generic => Ok(Vec::new()),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The path of the executable of a Python entry point in the prefix (`bin/<command>`, or
+/// `Scripts/<command>.exe` on Windows)
+fn entry_point_executable(prefix: &Path, command: &str, platform: Platform) -> PathBuf {
+    if platform.is_windows() {
+        prefix.join("Scripts").join(format!("{}.exe", command))
+    } else {
+        prefix.join("bin").join(command)
+    }
+}
+
+/// The entry points that collide with well-known system commands. An entry point that is named
+/// like the package itself (e.g. `pip` in the `pip` package) is considered intentional.
+fn check_entry_point_shadowing(package_name: &str, entry_points: &[EntryPoint]) -> Vec<String> {
+    entry_points
+        .iter()
+        .map(|ep| ep.command.as_str())
+        .filter(|command| *command != package_name && WELL_KNOWN_COMMANDS.contains(command))
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// The outcome of the check of the entry points against well-known system commands (see
+/// [`TestConfiguration::check_entry_point_shadowing`]). Shadowing entry points do not fail the
+/// check, they are reported as a warning.
+fn entry_point_shadowing_outcome(
+    package_name: &str,
+    package_folder: &Path,
+) -> Result<TestCaseOutcome, TestError> {
+    let entry_points = entry_points_from_folder(package_folder)?;
+    let shadowing = check_entry_point_shadowing(package_name, &entry_points);
+    let status = if shadowing.is_empty() {
+        TestStatus::Passed
+    } else {
+        let warning = format!(
+            "entry points shadow well-known system commands: {}",
+            shadowing.join(", ")
+        );
+        tracing::warn!("Package `{}`: {}", package_name, warning);
+        TestStatus::Warning(warning)
+    };
+    Ok(TestCaseOutcome::new(
+        ENTRY_POINT_SHADOWING_TEST_NAME.to_string(),
+        TestType::EntryPoints,
+        status,
+        Duration::ZERO,
+        None,
+    ))
+}
+
+/// Detect whether the host has a GPU, either through the `__cuda` virtual package or by probing
+/// for the ROCm system management tool
+fn host_has_gpu() -> bool {
+    let has_cuda = rattler_virtual_packages::VirtualPackage::current()
+        .map(|vpkgs| {
+            vpkgs
+                .iter()
+                .any(|vpkg| matches!(vpkg, rattler_virtual_packages::VirtualPackage::Cuda(_)))
+        })
+        .unwrap_or(false);
+
+    has_cuda
+        || std::process::Command::new("rocm-smi")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+}
+
+/// A match spec that only matches exactly the given package. The version is matched with `==`
+/// so that pre-release and development versions (e.g. `1.0.0rc1` or `1.0.0.dev5`) are not
+/// treated as version prefixes.
+fn exact_match_spec(pkg: &ArchiveIdentifier) -> Result<MatchSpec, TestError> {
+    MatchSpec::from_str(&format!(
+        "{} =={} {}",
+        pkg.name, pkg.version, pkg.build_string
+    ))
+    .map_err(|e| TestError::MatchSpecParse(e.to_string()))
+}
+
+/// The configuration for a test
+#[derive(Clone)]
+pub struct TestConfiguration {
+    /// The test prefix directory (will be created)
+    pub test_prefix: PathBuf,
+    /// The target platform
+    pub target_platform: Option<Platform>,
+    /// If true, the test prefix will not be deleted after the test is run. Otherwise it is
+    /// removed once the tests passed or the test run failed with an error – the prefix of a
+    /// package whose tests fail is kept for debugging.
+    pub keep_test_prefix: bool,
+    /// The channels to use for the test
+    pub channels: Vec<String>,
+    /// If true (the default), the temporary channel that contains the tested package is put in
+    /// front of `channels`, so that the package itself can always be installed
+    pub include_local_channel: bool,
+    /// If true, the channels that the package was built with (as recorded in its
+    /// `info/about.json`) are used after `channels`
+    pub include_package_channels: bool,
+    /// How the channels are prioritized when solving the test environment. With
+    /// [`ChannelPriority::Strict`], a package is only taken from the first channel that contains
+    /// a package with the same name. The default is [`ChannelPriority::Disabled`].
+    pub channel_priority: ChannelPriority,
+    /// The solver that is used for the test environment. Creating the environment fails if the
+    /// solver is not compiled in (see [`SolverBackend::Libsolv`]).
+    pub solver: SolverBackend,
+    /// If true, command tests are run a second time with a minimal activation (only `PREFIX`,
+    /// `CONDA_PREFIX` and `PATH` are set). The second run is reported as its own test (e.g.
+    /// `run_test.sh (minimal activation)`), which fails if the commands only succeed in the fully
+    /// activated environment.
+    pub test_minimal_activation: bool,
+    /// If true, the activation scripts of the test environment are not run at all: every command
+    /// runs with the minimal activation (only `PREFIX`, `CONDA_PREFIX` and `PATH` are set). This
+    /// helps to find out whether a failure is caused by an activation script or by the package
+    /// itself.
+    pub skip_activation: bool,
+    /// If true, the entry points of the package are compared with well-known system commands
+    /// (e.g. `test` or `build`). Entry points that shadow one of them are reported as a warning
+    /// of the `entry_point_shadowing` test ([`TestStatus::Warning`]), which does not fail the
+    /// tests.
+    pub check_entry_point_shadowing: bool,
+    /// If set, the complete output of every test (also of successful ones) is written to
+    /// `<log_dir>/<package>/<test>.log`. The logs are not removed together with the test prefix,
+    /// unless the directory is located inside of it.
+    pub log_dir: Option<PathBuf>,
+    /// If true (and no `log_dir` is set), the output of every test is written to
+    /// `<test_prefix>/logs/<test>.log`, and the last lines of the output of a failing test
+    /// command are part of its error. The output of the tests is always part of the
+    /// [`TestCaseOutcome`]s. Note that the test prefix (and with it the logs) is removed if all
+    /// tests pass, unless `keep_test_prefix` is set.
+    pub capture_output: bool,
+    /// If true, the logs of the tests (see `log_dir` and `capture_output`) are gzip compressed
+    /// (`<test>.log.gz`), and the output of a test is not kept in its [`TestCaseOutcome`] (and
+    /// in the JSON report) – only the path and the size of the log are ([`TestLog`]), the output
+    /// can be read with [`TestLog::read`]. The last lines of the output of a failing test command
+    /// are still part of its error.
+    pub compress_logs: bool,
+    /// If true, testing a package stops at its first failing test. Otherwise all tests of the
+    /// package are run and the failures are reported together. This only applies to the tests of
+    /// a single package – when testing multiple packages, the remaining packages are still tested.
+    pub fail_fast: bool,
+    /// If true, the test prefix is made read-only while the tests are running. Tests that need
+    /// scratch space should use their working directory or a temporary directory. A test that
+    /// fails in the read-only prefix but succeeds in a writable one is reported as a
+    /// [`TestError::PrefixWriteViolation`]. The prefix is only protected by its permissions, so
+    /// this has no effect if the tests run as root (e.g. in many containers).
+    pub read_only_prefix: bool,
+    /// If true, verify that every test only leaves behind the side effects it declares in the
+    /// test manifest. Changes in the test folder or the prefix that are not declared make the
+    /// test fail with [`TestError::UnexpectedSideEffects`].
+    pub verify_side_effects: bool,
+    /// If true, the directories in `side_effect_dirs` (by default the home directory) are
+    /// checked for files that a test created, modified or removed outside of the test prefix and
+    /// the working directory of the test. Such changes are reported as a warning, or make the
+    /// test fail with [`TestError::UnexpectedSideEffects`] if `fail_on_side_effects` is set.
+    pub detect_side_effects: bool,
+    /// The directories that are checked by `detect_side_effects`. If empty, the home directory
+    /// of the user is checked.
+    pub side_effect_dirs: Vec<PathBuf>,
+    /// If true, changes that `detect_side_effects` finds make the test fail instead of only
+    /// being reported as a warning
+    pub fail_on_side_effects: bool,
+    /// Additional dependencies that are added to the test environment if the package contains
+    /// tests of the given type (e.g. `coreutils` for command tests)
+    pub test_type_dependencies: HashMap<TestType, Vec<MatchSpec>>,
+    /// If set, the outcomes of every tested package are recorded in a checkpoint file. When
+    /// resuming, packages that are already recorded (with the same configuration) are not tested
+    /// again and their recorded outcomes are returned instead.
+    pub checkpoint: Option<CheckpointConfig>,
+    /// If true, no network access is allowed: the test environment is created from cached
+    /// repodata and packages and local channels only (creating it fails if a package is not
+    /// available offline), and tests that declare in the test manifest that they require network
+    /// access are skipped (and reported as such) instead of being run
+    pub offline: bool,
+    /// The maximum time a single test command may run. Commands that take longer are killed and
+    /// fail with [`TestError::Timeout`]. If `None`, commands can run indefinitely.
+    pub timeout: Option<Duration>,
+    /// The maximum time all tests of a package may take together. Once it is exceeded, the
+    /// running test command is killed, the remaining tests are not run and testing the package
+    /// fails with [`TestError::TotalTimeout`]. If `None`, there is no limit besides `timeout`.
+    pub total_timeout: Option<Duration>,
+    /// The number of packages that [`run_tests`] tests concurrently (0 and 1 both mean that the
+    /// packages are tested one after the other). When testing concurrently, every package is
+    /// tested in its own subdirectory of `test_prefix`.
+    pub concurrency: usize,
+    /// Additional environment variables that are set for the test commands. They take precedence
+    /// over the default variables (like `PREFIX`), but setting `PATH` is ignored because it is
+    /// set up by the activation of the test environment.
+    pub extra_env: HashMap<String, String>,
+    /// The expected SHA256 hash (hex encoded) of the package file (only useful when testing a
+    /// single package). If neither this nor `expected_md5` is set, the package file is not
+    /// verified (see `verify_package_contents`).
+    pub expected_sha256: Option<String>,
+    /// The expected MD5 hash (hex encoded) of the package file, used if no `expected_sha256` is
+    /// set
+    pub expected_md5: Option<String>,
+    /// If true, the integrity of the package is not verified before it is tested
+    pub skip_checksum_verification: bool,
+    /// If true and no expected hash of the package file is set, the contents of every file in the
+    /// package are verified against the hashes in its `info/paths.json` before it is tested. This
+    /// reads the whole package, which is slow for large packages.
+    pub verify_package_contents: bool,
+    /// The progress bars of the test environment creation and of the running test commands
+    /// (which show the most recent line of output) are added to this. If `None`, progress bars
+    /// are only shown while creating the test environment.
+    pub multi_progress: Option<MultiProgress>,
+    /// How often a failing test command is re-run before the test is reported as failed (0
+    /// means that failing commands are not re-run)
+    pub retries: u32,
+    /// Exit codes of test commands that are accepted in addition to 0 (e.g. a tool that exits
+    /// with 2 to signal "no updates"). Tests can allow further exit codes in the test manifest.
+    pub allowed_exit_codes: Vec<i32>,
+    /// The time to wait before re-running a failing test command for the first time. The time is
+    /// doubled for every further attempt.
+    pub retry_backoff: Duration,
+    /// The client that is used to download repodata and packages. If `None`, a default client
+    /// is used that is configured from the standard proxy (`HTTPS_PROXY`, `HTTP_PROXY`,
+    /// `ALL_PROXY`, `NO_PROXY`) and CA bundle (`REQUESTS_CA_BUNDLE`, `SSL_CERT_FILE`) environment
+    /// variables. A client that is given here is used as is.
+    pub client: Option<AuthenticatedClient>,
+    /// The cache directory for repodata and extracted packages. If `None`, the default cache
+    /// directory of rattler is used.
+    pub cache_dir: Option<PathBuf>,
+    /// Reuse the environments of previous tests from this directory: the solved environment
+    /// without the tested package is created once per set of packages, and test environments
+    /// with the same packages are cloned from it (packages with prefix placeholders and the
+    /// tested package are linked from the package cache). This speeds up testing many packages
+    /// with the same dependencies. The directory grows with every new set of packages and is
+    /// not cleaned up.
+    pub environment_cache: Option<PathBuf>,
+    /// If true (the default), an extraction of another build of the tested package (with the
+    /// same name, version and build string, but a different SHA256 hash) is removed from the
+    /// package cache before the package is installed. An extraction of the same build is reused
+    /// either way. If false, the package cache is left intact – e.g. because it is shared with
+    /// other processes that may use the cached extraction – and the environment is installed
+    /// from a run-local package cache in a temporary directory instead, into which the
+    /// dependencies are downloaded again.
+    pub clear_cached_package: bool,
+    /// Virtual packages (e.g. `__cuda=12.0`) that are used when solving the test environment.
+    /// They override the virtual packages with the same name that are detected on the host, all
+    /// other detected virtual packages are still used.
+    pub virtual_packages: Vec<GenericVirtualPackage>,
+    /// If true and `test_prefix` already contains an environment that satisfies the package and
+    /// its test dependencies, the environment is not created again and the tests are run in the
+    /// existing environment. A reused prefix is never removed after the tests.
+    pub reuse_prefix: bool,
+    /// If true, an existing prefix that does not satisfy the test dependencies is updated
+    /// instead of failing with [`TestError::PrefixNotSatisfied`] (only used with `reuse_prefix`)
+    pub augment_prefix: bool,
+    /// How the activation of the test environment modifies `PATH`. The default is the default
+    /// of `rattler_shell`, which puts the directories of the test environment in front of the
+    /// `PATH` of the current process. The minimal activation (see `test_minimal_activation`)
+    /// always puts them in front.
+    pub path_modification: PathModificationBehavior,
+    /// Which of the tests of a package are run (all by default)
+    pub test_filter: TestFilter,
+    /// Custom tests that are run after the tests of the package, see [`Self::register_runner`]
+    pub runners: Vec<Arc<dyn TestRunner>>,
+    /// Notified when a test starts and finishes and when all tests of a package are done
+    pub observer: Option<Arc<dyn TestObserver>>,
+    /// If true, `pip check` is run in the test environment (in addition to the tests of the
+    /// package) to find broken requirements of the installed Python packages. A package can also
+    /// request this with `pip_check: true` in its test manifest. The check is skipped with a
+    /// warning if `pip` is not installed in the test environment.
+    pub pip_check: bool,
+    /// If true, the linkage of the shared libraries of the package is checked: every dependency
+    /// of a library has to be resolved inside of the test environment or be a library of the
+    /// system. A package can also request this with `linkage_check: true` in its test manifest.
+    pub linkage_check: bool,
+    /// Additional glob patterns of library names (e.g. `libcuda.so*`) or paths that the
+    /// libraries of a package may link against outside of the test environment
+    pub linkage_allowlist: Vec<String>,
+    /// If true, every Python entry point of the package (from its `info/link.json`) is run with
+    /// `--help` in the test environment, and the test fails if an entry point is not installed
+    /// or exits with an error. A package can also request this with `entry_point_check: true`
+    /// in its test manifest.
+    pub entry_point_check: bool,
+    /// If true, the menuinst metadata (`Menu/*.json`) of the package is checked: every menu file
+    /// has to be valid, and the commands and icons of its entries that refer to files in the
+    /// prefix have to exist. A package can also request this with `menuinst_check: true` in its
+    /// test manifest.
+    pub menuinst_check: bool,
+    /// If true, the files of the package that have a prefix placeholder in its `info/paths.json`
+    /// are checked after the installation: a file that still contains the placeholder fails the
+    /// `prefix_check` test with [`TestError::PrefixNotReplaced`]. A package can also request
+    /// this with `prefix_check: true` in its test manifest.
+    pub prefix_check: bool,
+    /// If true, the name, version and build string in the `info/index.json` of the package are
+    /// compared with its file name, and every `depends` and `constrains` entry has to be a valid
+    /// match spec. The first inconsistency fails the `metadata_check` test with
+    /// [`TestError::InconsistentMetadata`]. A package can also request this with
+    /// `metadata_check: true` in its test manifest.
+    pub metadata_check: bool,
+    /// If true, the package is removed from the test environment after all of its tests passed
+    /// (by a transaction from the installed packages to the installed packages without it), and
+    /// the `uninstall_check` test fails with [`TestError::IncompleteUninstall`] if files of its
+    /// `info/paths.json` that no other installed package owns are left in the prefix. The
+    /// check is skipped if the test prefix is reused (`reuse_prefix`), as the prefix would no
+    /// longer contain the package.
+    pub uninstall_check: bool,
+    /// The shell executable that runs the test scripts, instead of the executable of the default
+    /// shell of the platform that is found in `PATH` (e.g. a newer `bash` on macOS). The shell
+    /// is still invoked like the default shell, so it has to be of the same type.
+    pub shell_override: Option<PathBuf>,
+    /// Additional arguments that are passed to the shell before the test script, e.g.
+    /// `--noprofile --norc` for `bash`. They follow the default options of the shell (`/d` for
+    /// `cmd.exe`, `-NoLogo -NoProfile` for PowerShell), unless `replace_default_shell_args` is
+    /// set.
+    pub shell_args: Vec<String>,
+    /// If true, `shell_args` replace the default options of the shell instead of following them
+    pub replace_default_shell_args: bool,
+    /// If true, `bash` and `zsh` run the test scripts as a login shell (with `-l`), so that the
+    /// login profiles (e.g. `/etc/profile` and `~/.bash_profile`) are sourced before the script –
+    /// for legacy test scripts that rely on them. The test environment is still activated after
+    /// the profiles. `--noprofile` in `shell_args` suppresses the profiles of `bash` also in a
+    /// login shell. Other shells are not affected.
+    pub login_shell: bool,
+    /// If true, tests that cannot run on the test platform (e.g. a `run_test.bat` when testing on
+    /// Linux) fail with [`TestError::UnrunnableTest`]. Otherwise they are reported as skipped.
+    pub fail_on_unrunnable_tests: bool,
+    /// If true, only log what would be done – the channels, the specs of the test environment
+    /// and the tests of the package – without creating the test environment or running the
+    /// tests. The package is still verified and indexed, and the returned [`TestResult`] is
+    /// marked as a dry run (with all tests skipped).
+    pub dry_run: bool,
+    /// If true, the tests of the package are collected before the test environment is created,
+    /// and no environment is created for a package that contains no tests. Otherwise the
+    /// environment is still created (which checks that the package can be installed). In both
+    /// cases the [`TestResult`] of such a package is marked with [`TestResult::no_tests`].
+    pub skip_environment_without_tests: bool,
+    /// Additional Python versions for which the Python tests of a package are run. For every
+    /// version, a separate environment with `python =<version>` (e.g. `python =3.11` matches all
+    /// `3.11.x` releases) is created in a subdirectory of `test_prefix`, and the outcomes of its
+    /// tests record the Python version. This is useful to test `noarch: python` packages.
+    pub python_versions: Vec<Version>,
+    /// The working directory of the tests (created if it does not exist). If `None`, the tests
+    /// run in the test folder of the package. The path of the test folder is always available to
+    /// the tests in the `TEST_DIR` environment variable.
+    pub test_cwd: Option<PathBuf>,
+    /// If true, check that all files of the package (as listed in its `info/paths.json`) are
+    /// installed in the test environment – and are not empty if their recorded size is not –
+    /// before any other test is run. Missing files fail the `installed_files` test with
+    /// [`TestError::MissingInstalledFile`].
+    pub verify_installed_files: bool,
+    /// Additional dependencies of the test environment, e.g. to add a test dependency that is
+    /// missing from the package without rebuilding it. They are solved together with the
+    /// package and its own test dependencies.
+    pub extra_dependencies: Vec<MatchSpec>,
+    /// If true, the `weak` and `strong` run exports of the package (from its
+    /// `info/run_exports.json`) are added to the dependencies of the test environment, as they
+    /// would be added to the environment of a package that is built against it
+    pub include_run_exports: bool,
+    /// If true, the local channel is always re-indexed. Otherwise, a channel whose
+    /// `repodata.json` is newer than all of its packages is not indexed again.
+    pub force_reindex: bool,
+    /// Where the test commands are executed (on the host or in a container)
+    pub backend: TestBackend,
+    /// If true, every package is tested in its own subdirectory of `test_prefix` (named after
+    /// the name, version and build string of the package), so that packages that are tested
+    /// with the same configuration do not share a prefix
+    pub unique_prefix_per_package: bool,
+    /// A script that is run in the activated test environment (in the working directory of the
+    /// tests) before the tests of a package, e.g. to write a configuration file or to start a
+    /// service. If it fails, the tests are not run and an error is returned.
+    pub pre_test_script: Option<String>,
+    /// A script that is run in the activated test environment after the tests of a package,
+    /// e.g. to stop a service that the `pre_test_script` started. A failure is only logged.
+    pub post_test_script: Option<String>,
+    /// Glob patterns (relative to the `info/test` folder of the package) of additional files
+    /// that are run as tests, e.g. `tests/*.sh`. The type of such a test is derived from the
+    /// extension of the file. The `run_test.*` files are always tests.
+    pub test_include: Vec<String>,
+    /// Glob patterns (relative to the `info/test` folder of the package) of files that are never
+    /// run as tests. They take precedence over [`Self::test_include`] and also apply to the
+    /// `run_test.*` files.
+    pub test_exclude: Vec<String>,
+    /// If set, the packages of the test environment are written to this file as an explicit
+    /// environment spec (`@EXPLICIT`, with the URL and MD5 hash of every package), so that the
+    /// environment can be recreated exactly. The environments of the additional Python versions
+    /// are not written.
+    pub write_explicit_env: Option<PathBuf>,
+    /// If set, [`run_test`] and [`run_tests`] write the results as a JSON report to this file
+    /// (see [`testresult::to_json_report`]), including the packages whose tests could not be run
+    pub json_report: Option<PathBuf>,
+    /// Before creating the test environment, check that the dependencies of the package itself
+    /// (the `depends` of its `info/index.json`, without the test dependencies) can be solved with
+    /// the channels. Fails with [`TestError::UnsatisfiableDependencies`] otherwise, even if the
+    /// package has no tests.
+    pub check_dependencies: bool,
+    /// The directory in which the temporary channel, the downloaded packages and the scripts of
+    /// the test commands are created (e.g. if `/tmp` is too small for large packages). If not
+    /// set, the default location for temporary files is used.
+    pub temp_dir: Option<PathBuf>,
+    /// Keep the temporary channel with the tested package (and its `repodata.json`) after the
+    /// tests, e.g. to find out why the test environment cannot be solved. Its location is logged.
+    pub keep_test_channel: bool,
+    /// Whether the test environment contains the test dependencies (the default), only the
+    /// package and its own dependencies, or only the package. The mode is recorded in every test
+    /// outcome.
+    pub environment_mode: TestEnvironmentMode,
+    /// If true, a test command that writes anything to stderr fails, even if it exits
+    /// successfully (e.g. to not allow deprecation warnings in strict CI runs). The output of the
+    /// test commands is always captured, so this does not require [`Self::capture_output`].
+    /// Tests that do not run a single command (e.g. the import tests) are not affected.
+    pub fail_on_stderr: bool,
+    /// If true, the progress of the single tests is only logged at `debug` level and every
+    /// package is reported with a single line (see [`TestResult::one_line_summary`]), which
+    /// keeps the output of [`run_tests`] readable for many packages. Failures are still logged.
+    pub quiet: bool,
+    /// Repodata patches (a `patch_instructions.json` file, as used by conda-forge) that are
+    /// applied to the repodata of all channels (including the local channel with the package)
+    /// when solving the test environment, e.g. to reproduce an environment in which a broken
+    /// build was removed or its dependencies were pinned
+    pub repodata_patches: Option<PathBuf>,
+    /// If set, the signature of the package (in `info/signature.json`) is verified with this key
+    /// before anything of the package is extracted or run. An unsigned package or a package
+    /// whose signature does not match fails with [`TestError::SignatureVerificationFailed`].
+    pub verify_signature: Option<PublicKey>,
+    /// Additional arguments that are passed to Python before the test script (and before `-c`
+    /// for the import tests), e.g. `-v` or `-X importtime` to debug failing imports
+    pub python_args: Vec<String>,
+    /// Additional arguments for the other interpreters, keyed by the name of the interpreter
+    /// (e.g. `R` or `perl`). They are passed before the test script.
+    pub interpreter_args: HashMap<String, Vec<String>>,
+    /// If true, a variable (`${NAME}`) in a command of the test manifest that is not set for
+    /// the test commands fails with [`TestError::UndefinedVariable`]. Otherwise it is left as
+    /// it is (e.g. for a variable that is defined by the command itself).
+    pub fail_on_undefined_variables: bool,
+    /// If set, the test environment is created from the packages of this lockfile (an explicit
+    /// environment spec, like the ones written with [`Self::write_explicit_env`]) instead of
+    /// solving the test requirements. Only the packages for the target platform are installed,
+    /// together with the tested package. The locked packages have to be available in the
+    /// channels, and requirements that they do not satisfy are reported as warnings.
+    pub lockfile: Option<PathBuf>,
+    /// If true, the complete script of every test command (the environment variables, the
+    /// activation of the test environment and the command) is logged at `info` level before it
+    /// is run, e.g. to find out why a test sees a different environment than a shell
+    pub print_scripts: bool,
+}
+
+impl Default for TestConfiguration {
+    fn default() -> Self {
+        Self {
+            test_prefix: PathBuf::default(),
+            target_platform: None,
+            keep_test_prefix: false,
+            channels: Vec::new(),
+            include_local_channel: true,
+            include_package_channels: false,
+            channel_priority: ChannelPriority::default(),
+            solver: SolverBackend::default(),
+            test_minimal_activation: false,
+            skip_activation: false,
+            check_entry_point_shadowing: false,
+            log_dir: None,
+            capture_output: false,
+            fail_fast: false,
+            read_only_prefix: false,
+            verify_side_effects: false,
+            detect_side_effects: false,
+            side_effect_dirs: Vec::new(),
+            fail_on_side_effects: false,
+            test_type_dependencies: HashMap::new(),
+            checkpoint: None,
+            offline: false,
+            timeout: None,
+            total_timeout: None,
+            concurrency: 0,
+            extra_env: HashMap::new(),
+            expected_sha256: None,
+            expected_md5: None,
+            skip_checksum_verification: false,
+            verify_package_contents: false,
+            multi_progress: None,
+            retries: 0,
+            allowed_exit_codes: Vec::new(),
+            retry_backoff: Duration::default(),
+            client: None,
+            cache_dir: None,
+            environment_cache: None,
+            virtual_packages: Vec::new(),
+            reuse_prefix: false,
+            augment_prefix: false,
+            path_modification: PathModificationBehavior::default(),
+            test_filter: TestFilter::default(),
+            runners: Vec::new(),
+            observer: None,
+            pip_check: false,
+            linkage_check: false,
+            linkage_allowlist: Vec::new(),
+            entry_point_check: false,
+            menuinst_check: false,
+            prefix_check: false,
+            metadata_check: false,
+            uninstall_check: false,
+            shell_override: None,
+            shell_args: Vec::new(),
+            replace_default_shell_args: false,
+            login_shell: false,
+            fail_on_unrunnable_tests: false,
+            dry_run: false,
+            skip_environment_without_tests: false,
+            python_versions: Vec::new(),
+            test_cwd: None,
+            verify_installed_files: false,
+            extra_dependencies: Vec::new(),
+            include_run_exports: false,
+            force_reindex: false,
+            backend: TestBackend::default(),
+            unique_prefix_per_package: false,
+            pre_test_script: None,
+            post_test_script: None,
+            test_include: Vec::new(),
+            test_exclude: Vec::new(),
+            write_explicit_env: None,
+            json_report: None,
+            check_dependencies: false,
+            temp_dir: None,
+            keep_test_channel: false,
+            environment_mode: TestEnvironmentMode::default(),
+            fail_on_stderr: false,
+            quiet: false,
+            repodata_patches: None,
+            verify_signature: None,
+            python_args: Vec::new(),
+            interpreter_args: HashMap::new(),
+            fail_on_undefined_variables: false,
+            lockfile: None,
+            print_scripts: false,
+            clear_cached_package: true,
+            compress_logs: false,
+        }
+    }
+}
+
+impl TestConfiguration {
+    /// Register a custom test that is run for every tested package, after the tests that are
+    /// part of the package
+    pub fn register_runner(&mut self, runner: Box<dyn TestRunner>) {
+        self.runners.push(Arc::from(runner));
+    }
+
+    /// Check the options before anything is tested, so that a mistake fails with a
+    /// [`TestError::InvalidConfiguration`] that explains it instead of deep in the solver or the
+    /// activation. Options that are suspicious but may be intended are logged as warnings.
+    pub fn validate(&self) -> Result<(), TestError> {
+        let invalid = |message: String| Err(TestError::InvalidConfiguration(message));
+
+        if self.test_prefix.as_os_str().is_empty() {
+            return invalid("the test prefix is not set".to_string());
+        }
+        let parent = self
+            .test_prefix
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty());
+        if let Some(parent) = parent {
+            if !self.test_prefix.exists() && !parent.is_dir() {
+                return invalid(format!(
+                    "the directory {:?} in which the test prefix is created does not exist",
+                    parent
+                ));
+            }
+        }
+
+        if self.channels.is_empty() {
+            if !self.include_local_channel {
+                return invalid(
+                    "no channels are configured, the tested package cannot be installed"
+                        .to_string(),
+                );
+            }
+            if !self.include_package_channels {
+                tracing::warn!(
+                    "No channels are configured, the test environment can only be created from \
+                     the local channel with the tested package – its dependencies are not found"
+                );
+            }
+        }
+
+        if cfg!(windows) {
+            if let Some(name) = reserved_name(&self.test_prefix) {
+                return invalid(format!(
+                    "the test prefix {:?} contains '{}', which is a reserved name on Windows",
+                    self.test_prefix, name
+                ));
+            }
+        }
+
+        if self.timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return invalid("the timeout has to be greater than zero".to_string());
+        }
+
+        if let Some(platform) = self.target_platform {
+            let supported = platform == Platform::NoArch
+                || platform.is_linux()
+                || platform.is_osx()
+                || platform.is_windows();
+            if !supported {
+                return invalid(format!(
+                    "a test environment cannot be created for {}, the target platform has to be \
+                     a Linux, macOS or Windows platform",
+                    platform
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How the test scripts are run: the shell, its arguments and whether it is a login shell
+    fn shell_invocation(&self) -> ShellInvocation<'_> {
+        ShellInvocation {
+            executable: self.shell_override.as_deref(),
+            args: &self.shell_args,
+            replace_default_args: self.replace_default_shell_args,
+            login: self.login_shell,
+        }
+    }
+
+    /// The tool configuration that is used to create the test environment and to download
+    /// packages
+    fn tool_configuration(&self) -> tool_configuration::Configuration {
+        // the default client honors the proxy and CA bundle environment variables
+        let mut configuration = tool_configuration::Configuration {
+            multi_progress_indicator: self.multi_progress.clone().unwrap_or_default(),
+            no_clean: self.keep_test_prefix,
+            cache_dir: self.cache_dir.clone(),
+            offline: self.offline,
+            repodata_patches: self.repodata_patches.clone(),
+            ..Default::default()
+        };
+        if let Some(client) = &self.client {
+            configuration.client = client.clone();
+        }
+        configuration
+    }
+
+    /// The channels that are used to create the test environment: the local channel with the
+    /// tested package (if enabled), the configured channels and the channels the package was
+    /// built with (if enabled). Every channel is only used once, at its first position.
+    fn test_channels(&self, local_channel: &Path, package_channels: &[String]) -> Vec<String> {
+        let local_channel = self
+            .include_local_channel
+            .then(|| local_channel.to_string_lossy().to_string());
+        let package_channels = if self.include_package_channels {
+            package_channels
+        } else {
+            &[]
+        };
+
+        let mut channels = Vec::new();
+        for channel in local_channel
+            .into_iter()
+            .chain(self.channels.iter().cloned())
+            .chain(package_channels.iter().cloned())
+        {
+            if !channels.contains(&channel) {
+                channels.push(channel);
+            }
+        }
+        channels
+    }
+}
+
+// `AuthenticatedClient` and `PathModificationBehavior` do not implement `Debug`
+impl std::fmt::Debug for TestConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TestConfiguration")
+            .field("test_prefix", &self.test_prefix)
+            .field("target_platform", &self.target_platform)
+            .field("keep_test_prefix", &self.keep_test_prefix)
+            .field("channels", &self.channels)
+            .field("include_local_channel", &self.include_local_channel)
+            .field("include_package_channels", &self.include_package_channels)
+            .field("channel_priority", &self.channel_priority)
+            .field("solver", &self.solver)
+            .field("test_minimal_activation", &self.test_minimal_activation)
+            .field("skip_activation", &self.skip_activation)
+            .field(
+                "check_entry_point_shadowing",
+                &self.check_entry_point_shadowing,
+            )
+            .field("log_dir", &self.log_dir)
+            .field("capture_output", &self.capture_output)
+            .field("fail_fast", &self.fail_fast)
+            .field("read_only_prefix", &self.read_only_prefix)
+            .field("verify_side_effects", &self.verify_side_effects)
+            .field("detect_side_effects", &self.detect_side_effects)
+            .field("side_effect_dirs", &self.side_effect_dirs)
+            .field("fail_on_side_effects", &self.fail_on_side_effects)
+            .field("test_type_dependencies", &self.test_type_dependencies)
+            .field("checkpoint", &self.checkpoint)
+            .field("offline", &self.offline)
+            .field("timeout", &self.timeout)
+            .field("total_timeout", &self.total_timeout)
+            .field("concurrency", &self.concurrency)
+            .field("extra_env", &self.extra_env)
+            .field("expected_sha256", &self.expected_sha256)
+            .field("expected_md5", &self.expected_md5)
+            .field(
+                "skip_checksum_verification",
+                &self.skip_checksum_verification,
+            )
+            .field("verify_package_contents", &self.verify_package_contents)
+            .field("multi_progress", &self.multi_progress)
+            .field("retries", &self.retries)
+            .field("allowed_exit_codes", &self.allowed_exit_codes)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("cache_dir", &self.cache_dir)
+            .field("environment_cache", &self.environment_cache)
+            .field("virtual_packages", &self.virtual_packages)
+            .field("reuse_prefix", &self.reuse_prefix)
+            .field("augment_prefix", &self.augment_prefix)
+            .field("test_filter", &self.test_filter)
+            .field("runners", &self.runners)
+            .field("observer", &self.observer)
+            .field("pip_check", &self.pip_check)
+            .field("linkage_check", &self.linkage_check)
+            .field("linkage_allowlist", &self.linkage_allowlist)
+            .field("entry_point_check", &self.entry_point_check)
+            .field("menuinst_check", &self.menuinst_check)
+            .field("prefix_check", &self.prefix_check)
+            .field("metadata_check", &self.metadata_check)
+            .field("uninstall_check", &self.uninstall_check)
+            .field("shell_override", &self.shell_override)
+            .field("shell_args", &self.shell_args)
+            .field(
+                "replace_default_shell_args",
+                &self.replace_default_shell_args,
+            )
+            .field("login_shell", &self.login_shell)
+            .field("fail_on_unrunnable_tests", &self.fail_on_unrunnable_tests)
+            .field("dry_run", &self.dry_run)
+            .field(
+                "skip_environment_without_tests",
+                &self.skip_environment_without_tests,
+            )
+            .field("python_versions", &self.python_versions)
+            .field("test_cwd", &self.test_cwd)
+            .field("verify_installed_files", &self.verify_installed_files)
+            .field("extra_dependencies", &self.extra_dependencies)
+            .field("include_run_exports", &self.include_run_exports)
+            .field("force_reindex", &self.force_reindex)
+            .field("backend", &self.backend)
+            .field("unique_prefix_per_package", &self.unique_prefix_per_package)
+            .field("pre_test_script", &self.pre_test_script)
+            .field("post_test_script", &self.post_test_script)
+            .field("test_include", &self.test_include)
+            .field("test_exclude", &self.test_exclude)
+            .field("write_explicit_env", &self.write_explicit_env)
+            .field("json_report", &self.json_report)
+            .field("check_dependencies", &self.check_dependencies)
+            .field("temp_dir", &self.temp_dir)
+            .field("keep_test_channel", &self.keep_test_channel)
+            .field("environment_mode", &self.environment_mode)
+            .field("fail_on_stderr", &self.fail_on_stderr)
+            .field("quiet", &self.quiet)
+            .field("repodata_patches", &self.repodata_patches)
+            .field("verify_signature", &self.verify_signature)
+            .field("python_args", &self.python_args)
+            .field("interpreter_args", &self.interpreter_args)
+            .field(
+                "fail_on_undefined_variables",
+                &self.fail_on_undefined_variables,
+            )
+            .field("lockfile", &self.lockfile)
+            .field("print_scripts", &self.print_scripts)
+            .field("clear_cached_package", &self.clear_cached_package)
+            .field("compress_logs", &self.compress_logs)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Run a test for a single package
+///
+/// This function creates a temporary directory, copies the package file into it, and then runs the
+/// indexing. It then creates a test environment that installs the package and any extra dependencies
+/// specified in the package test dependencies file.
+///
+/// With the activated test environment, the packaged test files are run:
+///
+/// * `info/test/run_test.sh` or `info/test/run_test.bat` on Windows
+/// * `info/test/run_test.py`
+///
+/// These test files are written at "package creation time" and are part of the package.
+///
+/// # Arguments
+///
+/// * `package_file` - The path to the package file
+/// * `config` - The test configuration
+///
+/// # Returns
+///
+/// * `Ok(TestResult)` with the outcome of every test case. All test cases are run, even if one of
+///   them fails (unless `fail_fast` is set) – use [`TestResult::all_passed`] to check whether the
+///   package passed its tests.
+/// * `Err(TestError)` if the tests could not be run (e.g. the configuration is invalid, see
+///   [`TestConfiguration::validate`], or the test environment could not be created)
+pub async fn run_test(
+    package_file: &Path,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    run_single_test(PackageSource::Archive(package_file), config).await
+}
+
+/// A package that is tested: a package archive or an extracted package (see
+/// [`run_test_from_dir`])
+#[derive(Debug, Clone, Copy)]
+enum PackageSource<'a> {
+    Archive(&'a Path),
+    Directory(&'a Path),
+}
+
+impl PackageSource<'_> {
+    /// The path of the package archive or directory
+    fn path(&self) -> &Path {
+        match self {
+            PackageSource::Archive(path) | PackageSource::Directory(path) => path,
+        }
+    }
+
+    /// The package, from the file name of an archive or the `info/index.json` of a directory
+    fn identifier(&self) -> Result<ArchiveIdentifier, TestError> {
+        match self {
+            PackageSource::Archive(package_file) => ArchiveIdentifier::try_from_path(package_file)
+                .ok_or_else(|| TestError::InvalidPackageFileName(package_file.to_path_buf())),
+            PackageSource::Directory(pkg_dir) => Ok(directory_identifier(
+                &IndexJson::from_package_directory(pkg_dir)?,
+            )),
+        }
+    }
+
+    /// The SHA256 hash (hex encoded) of the package archive or of the files of the directory
+    fn sha256(&self) -> Result<String, TestError> {
+        match self {
+            PackageSource::Archive(package_file) => Ok(format!(
+                "{:x}",
+                rattler_digest::compute_file_digest::<rattler_digest::Sha256>(package_file)?
+            )),
+            PackageSource::Directory(pkg_dir) => directory_digest(pkg_dir),
+        }
+    }
+}
+
+/// The SHA256 hash (hex encoded) of the paths and contents of the files in a directory
+fn directory_digest(dir: &Path) -> Result<String, TestError> {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    for entry in walkdir::WalkDir::new(dir).min_depth(1).sort_by_file_name() {
+        let entry = entry.map_err(std::io::Error::from)?;
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        hasher.update(relative.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        if entry.file_type().is_file() {
+            std::io::copy(&mut fs::File::open(entry.path())?, &mut hasher)?;
+        }
+        hasher.update([0]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Validate the configuration, test the package and write the JSON report (if configured)
+async fn run_single_test(
+    source: PackageSource<'_>,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    config.validate()?;
+    let result = run_test_in_channel(source, config, None).await;
+    if let Some(path) = &config.json_report {
+        write_json_report(path, &[(source.path(), result.as_ref())])?;
+    }
+    result
+}
+
+/// Write the JSON report of the results to the file
+fn write_json_report(
+    path: &Path,
+    results: &[(&Path, Result<&TestResult, &TestError>)],
+) -> Result<(), TestError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, testresult::to_json_report(results)?)?;
+    tracing::info!("Wrote the test report to {:?}", path);
+    Ok(())
+}
+
+/// Like [`run_test`], but uses an already indexed local channel that contains the package (if
+/// given) instead of creating one for the package
+async fn run_test_in_channel(
+    source: PackageSource<'_>,
+    config: &TestConfiguration,
+    local_channel: Option<&Path>,
+) -> Result<TestResult, TestError> {
+    let result = test_package_with_checkpoint(source, config, local_channel).await;
+    if let (Some(observer), Ok(result)) = (&config.observer, &result) {
+        observer.on_package_finish(result);
+    }
+    result
+}
+
+/// Test the package, or take its result from the checkpoint (if configured)
+async fn test_package_with_checkpoint(
+    source: PackageSource<'_>,
+    config: &TestConfiguration,
+    local_channel: Option<&Path>,
+) -> Result<TestResult, TestError> {
+    // the phases of testing the package are recorded in spans under this span
+    let span = tracing::info_span!(
+        "test_package",
+        package = %source
+            .path()
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy(),
+        platform = %activation_platform(config.target_platform),
+    );
+
+    // the skipped tests of a dry run must not end up in the checkpoint
+    let checkpoint_config = match &config.checkpoint {
+        Some(checkpoint_config) if !config.dry_run => checkpoint_config,
+        _ => {
+            return test_source(source, config, local_channel)
+                .instrument(span)
+                .await
+        }
+    };
+
+    let pkg = source.identifier()?;
+    let sha256 = source.sha256()?;
+
+    if checkpoint_config.resume {
+        let checkpoint = Checkpoint::load(&checkpoint_config.path, config)?;
+        if let Some(mut result) = checkpoint.result(&sha256, &pkg) {
+            tracing::info!(
+                "Skipping {:?}, it was already tested in a previous run",
+                source.path()
+            );
+            if let PackageSource::Archive(package_file) = source {
+                result.sha256 = Some(sha256);
+                result.size = Some(fs::metadata(package_file)?.len());
+            }
+            return Ok(result);
+        }
+    }
+
+    let result = test_source(source, config, local_channel)
+        .instrument(span)
+        .await?;
+
+    // packages may be tested concurrently, reload the checkpoint to not lose their records
+    {
+        let _guard = CHECKPOINT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut checkpoint = Checkpoint::load(&checkpoint_config.path, config)?;
+        checkpoint.record(&sha256, source.path(), &result);
+        checkpoint.save(&checkpoint_config.path)?;
+    }
+
+    Ok(result)
+}
+
+/// Serializes the updates of the checkpoint file when packages are tested concurrently
+static CHECKPOINT_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Test a package archive with [`test_package`] or a package directory with
+/// [`test_package_directory`]
+async fn test_source(
+    source: PackageSource<'_>,
+    config: &TestConfiguration,
+    local_channel: Option<&Path>,
+) -> Result<TestResult, TestError> {
+    match source {
+        PackageSource::Archive(package_file) => {
+            test_package(package_file, config, local_channel).await
+        }
+        PackageSource::Directory(pkg_dir) => test_package_directory(pkg_dir, config).await,
+    }
+}
+
+/// Test an already extracted package (a directory with the `info` folder and the files of the
+/// package), e.g. during development before the package archive is created
+///
+/// The package is identified by its `info/index.json`. Instead of indexing an archive, the
+/// record of the package is written into a temporary channel and the directory is copied into
+/// the package cache, from where it is installed into the test environment together with its
+/// test dependencies. The tests are read from the `info/test` folder of the directory.
+///
+/// Like [`run_test`], the configuration is validated, the result is recorded in the checkpoint
+/// and the JSON report (if configured). The checkpoint identifies the directory by the hash of
+/// its files.
+pub async fn run_test_from_dir(
+    pkg_dir: &Path,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    run_single_test(PackageSource::Directory(pkg_dir), config).await
+}
+
+/// The identifier of an extracted package, from its `info/index.json`
+fn directory_identifier(index_json: &IndexJson) -> ArchiveIdentifier {
+    ArchiveIdentifier {
+        name: index_json.name.as_normalized().to_string(),
+        version: index_json.version.to_string(),
+        build_string: index_json.build.clone(),
+        archive_type: ArchiveType::Conda,
+    }
+}
+
+/// Test an extracted package, see [`run_test_from_dir`]
+async fn test_package_directory(
+    pkg_dir: &Path,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    let index_json = IndexJson::from_package_directory(pkg_dir)?;
+    let pkg = directory_identifier(&index_json);
+    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+
+    // a channel that only contains the record of the package
+    let local_channel = create_local_channel(
+        &[],
+        &target_platform,
+        true,
+        config.temp_dir.as_deref(),
+        config.keep_test_channel,
+    )?;
+    let subdir = index_json
+        .subdir
+        .clone()
+        .unwrap_or_else(|| target_platform.to_string());
+    let mut repodata = RepoData {
+        info: Some(ChannelInfo {
+            subdir: subdir.clone(),
+            base_url: None,
+        }),
+        packages: Default::default(),
+        conda_packages: Default::default(),
+        removed: Default::default(),
+        version: Some(1),
+    };
+    repodata.conda_packages.insert(
+        format!("{}.conda", CacheKey::from(pkg.clone())),
+        index::package_record_from_index(index_json, None, None, None),
+    );
+    let subdir = local_channel.path().join(subdir);
+    fs::create_dir_all(&subdir)?;
+    fs::write(
+        subdir.join("repodata.json"),
+        serde_json::to_string_pretty(&repodata)?,
+    )?;
+
+    // the package is installed from the package cache, it cannot be fetched from the channel
+    let mut global_configuration = config.tool_configuration();
+    let cache_key = CacheKey::from(pkg.clone());
+    let mut package_folder = global_configuration
+        .cache_dir()?
+        .join("pkgs")
+        .join(cache_key.to_string());
+    let mut _package_cache = None;
+    if package_folder.exists() {
+        if config.clear_cached_package {
+            tracing::info!("Removing previously cached package {:?}", package_folder);
+            fs::remove_dir_all(&package_folder)?;
+        } else {
+            let package_cache = use_run_local_package_cache(config, &mut global_configuration)?;
+            package_folder = package_cache
+                .path()
+                .join("pkgs")
+                .join(cache_key.to_string());
+            _package_cache = Some(package_cache);
+        }
+    }
+    let cache_dir = global_configuration.cache_dir()?;
+    copy_directory(pkg_dir, &package_folder)?;
+    // the package can differ from the archive of the same name
+    let _ = fs::remove_file(cache_dir.join("pkgs").join(format!("{}.sha256", cache_key)));
+
+    let test_folder = pkg_dir.join("info/test");
+    let manifest = TestManifest::from_folder(&test_folder)?;
+    let test_dependencies = TestTimeDependencies::from_folder(&test_folder)?;
+    let mut dependencies = test_dependencies
+        .dependencies
+        .iter()
+        .map(|s| MatchSpec::from_str(s))
+        .collect::<Result<Vec<_>, _>>()?;
+    dependencies.push(exact_match_spec(&pkg)?);
+    for spec in &manifest.requires {
+        dependencies.push(MatchSpec::from_str(spec)?);
+    }
+    dependencies.extend(test_type_specs(config, &manifest, || {
+        folder_test_files(&test_folder)
+    })?);
+    dependencies.extend(config.extra_dependencies.iter().cloned());
+    let dependencies = environment_dependencies(&pkg, dependencies, config)?;
+
+    let mut channels = config.test_channels(local_channel.path(), &[]);
+    test_dependencies.add_channels(&mut channels);
+
+    let mut prefix_guard = TestPrefixGuard::new(&config.test_prefix, config.keep_test_prefix);
+    let prefix = canonicalize(&config.test_prefix)?;
+    let records = install_test_environment(
+        &pkg,
+        &dependencies,
+        &channels,
+        &prefix,
+        config,
+        &global_configuration,
+    )
+    .await?;
+    write_explicit_environment(&records, config)?;
+
+    let started = Instant::now();
+    let mut test_result = run_tests_in_environment(
+        TestEnvironment {
+            pkg,
+            prefix,
+            package_folder: pkg_dir.to_path_buf(),
+            sha256: None,
+            package_cache: None,
+        },
+        config,
+    )
+    .await?;
+    test_result.total_duration = started.elapsed();
+    if !test_result.all_passed() {
+        prefix_guard.keep();
+    }
+
+    Ok(test_result)
+}
+
+/// The files in the test folder of an extracted package
+fn folder_test_files(test_folder: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    if !test_folder.is_dir() {
+        return Ok(Vec::new());
+    }
+    fs::read_dir(test_folder)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect()
+}
+
+/// Test a package with the tests of a recipe instead of the tests in the package
+///
+/// The `info/test` folder of a package is written from the recipe when the package is created,
+/// while a recipe is developed its tests can change after the package was built. The package is
+/// extracted into a temporary directory, its `info/test` folder is replaced with the test files
+/// of the `test` section of the recipe (like when packaging, the `files` are copied from the
+/// recipe directory and the `source_files` from the work directory), and the extracted package
+/// is tested with [`run_test_from_dir`].
+pub async fn run_recipe_tests(
+    package_file: &Path,
+    test: &Test,
+    recipe_dir: &Path,
+    work_dir: &Path,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    let pkg_dir = create_temp_dir(config.temp_dir.as_deref())?;
+    let (archive, destination) = (package_file.to_path_buf(), pkg_dir.path().to_path_buf());
+    run_blocking(move || extract_package(&archive, &destination)).await?;
+
+    let test_folder = pkg_dir.path().join("info/test");
+    if test_folder.exists() {
+        fs::remove_dir_all(&test_folder)?;
+    }
+    let platform = IndexJson::from_package_directory(pkg_dir.path())?
+        .subdir
+        .and_then(|subdir| Platform::from_str(&subdir).ok())
+        .or(config.target_platform)
+        .unwrap_or_else(Platform::current);
+    packaging::write_recipe_tests(test, &platform, recipe_dir, work_dir, &test_folder).map_err(
+        |e| {
+            TestError::TestEnvironmentSetup(anyhow::anyhow!(
+                "could not write the tests of the recipe: {}",
+                e
+            ))
+        },
+    )?;
+
+    tracing::info!("Testing {:?} with the tests of the recipe", package_file);
+    run_test_from_dir(pkg_dir.path(), config).await
+}
+
+/// Copy a directory recursively, symbolic links are copied as links
+fn copy_directory(source: &Path, destination: &Path) -> Result<(), TestError> {
+    for entry in walkdir::WalkDir::new(source) {
+        let entry = entry.map_err(std::io::Error::from)?;
+        let relative = entry
+            .path()
+            .strip_prefix(source)
+            .expect("walkdir yields paths inside of the source");
+        let target = destination.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if entry.path_is_symlink() {
+            let link = fs::read_link(entry.path())?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(link, &target)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(link, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Test a package that is published in one of the configured channels
+///
+/// The spec has to match exactly one package file in the channels (for the target platform or
+/// `noarch`). The package is downloaded into a temporary directory and then tested like a local
+/// package with [`run_test`]. The downloaded package is removed afterwards, unless
+/// `keep_test_prefix` is set.
+pub async fn run_test_spec(
+    spec: &MatchSpec,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    let tool_configuration = config.tool_configuration();
+    let matching = matching_channel_records(spec, config, &tool_configuration).await?;
+
+    let record = match matching.as_slice() {
+        [] => return Err(TestError::PackageNotFound(spec.to_string())),
+        [record] => record,
+        _ => {
+            return Err(TestError::AmbiguousSpec {
+                spec: spec.to_string(),
+                matches: matching
+                    .iter()
+                    .map(|record| record.url.to_string())
+                    .collect(),
+            })
+        }
+    };
+
+    download_and_test(record, config, &tool_configuration).await
+}
+
+/// Test the newest build of a package that is published in one of the configured channels
+///
+/// Of all packages in the channels that match the spec (usually just the name of the package),
+/// the one with the highest version is tested, then the highest build number, then the newest
+/// timestamp. Only packages for the target platform and `noarch` are considered, if both have
+/// the same version and build a package for the target platform is preferred. The package is
+/// downloaded and tested like with [`run_test_spec`].
+pub async fn run_test_latest(
+    spec: &MatchSpec,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    let tool_configuration = config.tool_configuration();
+    let record = matching_channel_records(spec, config, &tool_configuration)
+        .await?
+        .into_iter()
+        .max_by(|a, b| {
+            let (a, b) = (&a.package_record, &b.package_record);
+            a.version
+                .cmp(&b.version)
+                .then(a.build_number.cmp(&b.build_number))
+                .then(a.timestamp.cmp(&b.timestamp))
+                .then((a.subdir != "noarch").cmp(&(b.subdir != "noarch")))
+        })
+        .ok_or_else(|| TestError::PackageNotFound(spec.to_string()))?;
+
+    tracing::info!(
+        "The newest build of {} is {} ({})",
+        spec,
+        record.file_name,
+        record.url
+    );
+    download_and_test(&record, config, &tool_configuration).await
+}
+
+/// The records of the configured channels that match the spec, from the subdirectories of the
+/// target platform and `noarch`
+async fn matching_channel_records(
+    spec: &MatchSpec,
+    config: &TestConfiguration,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<Vec<RepoDataRecord>, TestError> {
+    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+    let repodatas = load_repodatas(
+        std::slice::from_ref(spec),
+        &config.channels,
+        target_platform,
+        tool_configuration,
+    )
+    .await
+    .map_err(TestError::TestEnvironmentSetup)?;
+
+    // the channels may specify other subdirectories
+    let target_platform = target_platform.to_string();
+    Ok(repodatas
+        .into_iter()
+        .flatten()
+        .filter(|record| spec.matches(&record.package_record))
+        .filter(|record| {
+            let subdir = &record.package_record.subdir;
+            *subdir == target_platform || subdir == "noarch"
+        })
+        .collect())
+}
+
+/// Test the newest build of a package for each of the platforms
+///
+/// For every platform, the package that matches the spec is selected and tested like with
+/// [`run_test_latest`], with the platform as target platform and in its own subdirectory of
+/// `test_prefix`. Platforms whose executables cannot run on this host (e.g. `win-64` or
+/// `linux-aarch64` on `linux-64`) are skipped. The result of every tested platform is returned
+/// together with the platform, a failing platform does not stop the remaining platforms from
+/// being tested.
+pub async fn run_test_platforms(
+    spec: &MatchSpec,
+    platforms: &[Platform],
+    config: &TestConfiguration,
+) -> Vec<(Platform, Result<TestResult, TestError>)> {
+    let mut results = Vec::new();
+    for &platform in platforms {
+        if !can_run_on_host(activation_platform(Some(platform))) {
+            tracing::warn!(
+                "Skipping {} for {}: cannot run {} executables on {}",
+                spec,
+                platform,
+                platform,
+                Platform::current()
+            );
+            continue;
+        }
+
+        tracing::info!("Testing {} for {}", spec, platform);
+        let config = TestConfiguration {
+            target_platform: Some(platform),
+            test_prefix: config.test_prefix.join(platform.to_string()),
+            ..config.clone()
+        };
+        let result = match fs::create_dir_all(&config.test_prefix) {
+            Ok(()) => run_test_latest(spec, &config).await,
+            Err(e) => Err(e.into()),
+        };
+        match &result {
+            Ok(result) if result.all_passed() => {}
+            Ok(_) => tracing::error!("The tests of {} failed for {}", spec, platform),
+            Err(e) => tracing::error!("Could not test {} for {}: {}", spec, platform, e),
+        }
+        results.push((platform, result));
+    }
+    results
+}
+
+/// Test a package against several sets of channels, e.g. to find out whether it still works
+/// with the public channels only or depends on a package that is only available in an internal
+/// channel
+///
+/// For every channel set, the package is tested like with [`run_test`] with the channels of the
+/// set instead of the configured channels, in its own subdirectory (`channels-<index>`) of
+/// `test_prefix`. The channels the package was built with are still added if
+/// `include_package_channels` is set. The result of every channel set is returned together with
+/// the set, a failing set does not stop the remaining sets from being tested.
+pub async fn run_test_channel_sets(
+    package_file: &Path,
+    channel_sets: Vec<Vec<String>>,
+    config: &TestConfiguration,
+) -> Vec<(Vec<String>, Result<TestResult, TestError>)> {
+    let mut results = Vec::new();
+    for (index, channels) in channel_sets.into_iter().enumerate() {
+        tracing::info!(
+            "Testing {:?} with the channels {}",
+            package_file,
+            channels.join(", ")
+        );
+        let config = TestConfiguration {
+            channels: channels.clone(),
+            test_prefix: config.test_prefix.join(format!("channels-{}", index)),
+            ..config.clone()
+        };
+        let result = match fs::create_dir_all(&config.test_prefix) {
+            Ok(()) => run_test(package_file, &config).await,
+            Err(e) => Err(e.into()),
+        };
+        match &result {
+            Ok(result) if result.all_passed() => {}
+            Ok(_) => tracing::error!(
+                "The tests of {:?} failed with the channels {}",
+                package_file,
+                channels.join(", ")
+            ),
+            Err(e) => tracing::error!(
+                "Could not test {:?} with the channels {}: {}",
+                package_file,
+                channels.join(", "),
+                e
+            ),
+        }
+        results.push((channels, result));
+    }
+    results
+}
+
+/// Download a package of a channel into a temporary directory and test it with [`run_test`]
+async fn download_and_test(
+    record: &RepoDataRecord,
+    config: &TestConfiguration,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<TestResult, TestError> {
+    if config.offline {
+        return Err(TestError::TestEnvironmentSetup(anyhow::anyhow!(
+            "cannot download {} when testing offline",
+            record.url
+        )));
+    }
+
+    let download_dir = create_temp_dir(config.temp_dir.as_deref())?;
+    let package_file = download_dir.path().join(&record.file_name);
+    tracing::info!("Downloading {} to {:?}", record.url, package_file);
+    let response = tool_configuration
+        .client
+        .get(record.url.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    // the package is streamed to the file, large packages are not kept in memory
+    let stream = futures::TryStreamExt::map_err(response.bytes_stream(), |e| {
+        std::io::Error::new(std::io::ErrorKind::Other, e)
+    });
+    let reader = tokio_util::io::StreamReader::new(stream);
+    tokio::pin!(reader);
+    let mut file = tokio::fs::File::create(&package_file).await?;
+    tokio::io::copy(&mut reader, &mut file).await?;
+    tokio::io::AsyncWriteExt::flush(&mut file).await?;
+
+    // verify the download with the hashes from the repodata, unless hashes are configured
+    let config = if config.expected_sha256.is_none() && config.expected_md5.is_none() {
+        TestConfiguration {
+            expected_sha256: record
+                .package_record
+                .sha256
+                .map(|sha256| format!("{:x}", sha256)),
+            expected_md5: record.package_record.md5.map(|md5| format!("{:x}", md5)),
+            ..config.clone()
+        }
+    } else {
+        config.clone()
+    };
+
+    let result = run_test(&package_file, &config).await;
+
+    if config.keep_test_prefix {
+        let kept = download_dir.into_path();
+        tracing::info!("Keeping the downloaded package in {:?}", kept);
+    }
+
+    result
+}
+
+/// Run the tests of all packages (`*.conda` and `*.tar.bz2` files) in a directory
+///
+/// The directory is not searched recursively. The packages are indexed once and then tested with
+/// [`run_test`], up to `config.concurrency` packages at the same time (one after the other by
+/// default). The result of every package is returned, a failing package does not stop the
+/// remaining packages from being tested.
+pub async fn run_tests(
+    dir: &Path,
+    config: &TestConfiguration,
+) -> Result<Vec<(PathBuf, Result<TestResult, TestError>)>, TestError> {
+    let mut package_files = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    package_files.retain(|path| path.is_file() && ArchiveType::try_from(path.as_path()).is_some());
+    package_files.sort();
+
+    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+    let local_channel = create_local_channel(
+        &package_files
+            .iter()
+            .map(PathBuf::as_path)
+            .collect::<Vec<_>>(),
+        &target_platform,
+        config.force_reindex,
+        config.temp_dir.as_deref(),
+        config.keep_test_channel,
+    )?;
+
+    let mut results = Vec::new();
+    let concurrency = config.concurrency.max(1);
+    if concurrency == 1 {
+        for package_file in package_files {
+            tracing::info!("Testing {:?}", package_file);
+            let result = run_test_in_channel(
+                PackageSource::Archive(&package_file),
+                config,
+                Some(local_channel.path()),
+            )
+            .await;
+            results.push((package_file, result));
+        }
+    } else {
+        let multi_progress = config.multi_progress.clone().unwrap_or_default();
+        let mut tasks = tokio::task::JoinSet::new();
+        let mut pending = package_files.into_iter();
+        // the packages of the tasks that did not return yet, a cancelled task returns nothing
+        let mut running = BTreeSet::new();
+
+        loop {
+            while tasks.len() < concurrency {
+                let Some(package_file) = pending.next() else {
+                    break;
+                };
+                let file_name = package_file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+
+                // every concurrently tested package gets its own prefix
+                let mut config = config.clone();
+                config.test_prefix = config.test_prefix.join(&file_name);
+                config.multi_progress = Some(multi_progress.clone());
+                let local_channel = local_channel.path().to_path_buf();
+
+                let progress_bar = multi_progress.add(
+                    indicatif::ProgressBar::new_spinner()
+                        .with_message(format!("Testing {}", file_name)),
+                );
+                progress_bar.enable_steady_tick(Duration::from_millis(100));
+
+                running.insert(package_file.clone());
+                tasks.spawn(async move {
+                    let result = match fs::create_dir_all(&config.test_prefix) {
+                        Ok(()) => {
+                            run_test_in_channel(
+                                PackageSource::Archive(&package_file),
+                                &config,
+                                Some(&local_channel),
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e.into()),
+                    };
+                    let status = match &result {
+                        Ok(result) if result.all_passed() => "passed",
+                        Ok(_) => "failed",
+                        Err(_) => "errored",
+                    };
+                    progress_bar.finish_with_message(format!("{}: {}", file_name, status));
+                    (package_file, result)
+                });
+            }
+
+            match tasks.join_next().await {
+                Some(Ok((package_file, result))) => {
+                    running.remove(&package_file);
+                    results.push((package_file, result));
+                }
+                Some(Err(e)) => match e.try_into_panic() {
+                    Ok(panic) => std::panic::resume_unwind(panic),
+                    Err(e) => tracing::error!("A test task was cancelled: {}", e),
+                },
+                None => break,
+            }
+        }
+
+        for package_file in running {
+            let error = std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "the tests of the package were cancelled",
+            );
+            results.push((package_file, Err(error.into())));
+        }
+
+        // report the packages in a deterministic order
+        results.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    let passed = results
+        .iter()
+        .filter(|(_, result)| matches!(result, Ok(result) if result.all_passed()))
+        .count();
+    tracing::info!(
+        "{} of {} packages passed their tests, {} failed",
+        passed,
+        results.len(),
+        results.len() - passed
+    );
+
+    if let Some(path) = &config.json_report {
+        let report = results
+            .iter()
+            .map(|(package_file, result)| (package_file.as_path(), result.as_ref()))
+            .collect::<Vec<_>>();
+        write_json_report(path, &report)?;
+    }
+
+    Ok(results)
+}
+
+/// Run the tests of a single package (like [`run_test`]) and render a human readable summary
+/// of all test outcomes. Failing tests are part of the summary – an error is only returned if
+/// the tests could not be run at all.
+pub async fn run_test_with_summary(
+    package_file: &Path,
+    config: &TestConfiguration,
+) -> Result<String, TestError> {
+    Ok(run_test(package_file, config).await?.summary())
+}
+
+/// Run the tests of downstream packages against a package
+///
+/// For every downstream spec, an environment that contains the package (exactly this build) and
+/// the downstream package is created, and the tests of the downstream package are run in it. This
+/// verifies that packages that depend on the package still work with the new build. Every
+/// downstream package is tested in its own subdirectory of `test_prefix`, and the result of every
+/// downstream package is returned together with its spec.
+pub async fn run_downstream_tests(
+    package_file: &Path,
+    downstream: &[MatchSpec],
+    config: &TestConfiguration,
+) -> Result<Vec<(MatchSpec, Result<TestResult, TestError>)>, TestError> {
+    let pkg = ArchiveIdentifier::try_from_path(package_file)
+        .ok_or_else(|| TestError::InvalidPackageFileName(package_file.to_path_buf()))?;
+    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+    let tmp_repo = create_local_channel(
+        &[package_file],
+        &target_platform,
+        config.force_reindex,
+        config.temp_dir.as_deref(),
+        config.keep_test_channel,
+    )?;
+
+    let mut results = Vec::new();
+    for spec in downstream {
+        tracing::info!(
+            "Testing downstream package {} against {:?}",
+            spec,
+            package_file
+        );
+        let result = test_downstream_package(&pkg, spec, tmp_repo.path(), config).await;
+        match &result {
+            Ok(result) if result.all_passed() => {}
+            Ok(_) => tracing::error!("The tests of downstream package {} failed", spec),
+            Err(e) => tracing::error!("Could not test downstream package {}: {}", spec, e),
+        }
+        results.push((spec.clone(), result));
+    }
+
+    Ok(results)
+}
+
+/// Test a single downstream package in an environment together with the package
+async fn test_downstream_package(
+    pkg: &ArchiveIdentifier,
+    spec: &MatchSpec,
+    local_channel: &Path,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    let name = spec
+        .name
+        .as_ref()
+        .map(|name| name.as_normalized().to_string())
+        .unwrap_or_else(|| spec.to_string());
+    let mut config = config.clone();
+    config.test_prefix = config.test_prefix.join(name);
+    fs::create_dir_all(&config.test_prefix)?;
+
+    let mut prefix_guard = TestPrefixGuard::new(&config.test_prefix, config.keep_test_prefix);
+    let prefix = canonicalize(&config.test_prefix)?;
+    let global_configuration = config.tool_configuration();
+    let cache_dir = global_configuration.cache_dir()?;
+    let platform = activation_platform(config.target_platform);
+    let mut channels = config.test_channels(local_channel, &[]);
+
+    let mut dependencies = vec![exact_match_spec(pkg)?, spec.clone()];
+    let records = create_environment(
+        &dependencies,
+        &platform,
+        &prefix,
+        &channels,
+        &config.virtual_packages,
+        config.channel_priority,
+        config.solver,
+        &global_configuration,
+    )
+    .await
+    .map_err(|e| environment_error(e, &dependencies, &channels))?;
+
+    let record = records
+        .iter()
+        .find(|record| spec.matches(&record.package_record))
+        .ok_or_else(|| TestError::PackageNotFound(spec.to_string()))?;
+    let downstream = ArchiveIdentifier::try_from_filename(&record.file_name)
+        .ok_or_else(|| TestError::InvalidPackageFileName(PathBuf::from(&record.file_name)))?;
+    let package_folder = cache_dir
+        .join("pkgs")
+        .join(CacheKey::from(downstream.clone()).to_string());
+
+    // the downstream package is only known after solving, add its test dependencies afterwards
+    let test_folder = package_folder.join("info").join("test");
+    let test_dependencies = TestTimeDependencies::from_folder(&test_folder)?;
+    test_dependencies.add_channels(&mut channels);
+    let mut test_specs = test_dependencies.dependencies;
+    test_specs.extend(TestManifest::from_folder(&test_folder)?.requires);
+    let full = config.environment_mode == TestEnvironmentMode::Full;
+    if full && (!test_specs.is_empty() || !config.extra_dependencies.is_empty()) {
+        for test_spec in test_specs {
+            dependencies.push(MatchSpec::from_str(&test_spec)?);
+        }
+        dependencies.extend(config.extra_dependencies.iter().cloned());
+        create_environment(
+            &dependencies,
+            &platform,
+            &prefix,
+            &channels,
+            &config.virtual_packages,
+            config.channel_priority,
+            config.solver,
+            &global_configuration,
+        )
+        .await
+        .map_err(|e| environment_error(e, &dependencies, &channels))?;
+    }
+
+    let environment = TestEnvironment {
+        pkg: downstream,
+        prefix,
+        package_folder,
+        sha256: None,
+        package_cache: None,
+    };
+    let test_result = run_tests_in_environment(environment, &config).await?;
+    if !test_result.all_passed() {
+        prefix_guard.keep();
+    }
+
+    Ok(test_result)
+}
+
+/// Create a temporary directory in `temp_dir` (see [`TestConfiguration::temp_dir`]), or in the
+/// default location for temporary files (which respects `TMPDIR`)
+fn create_temp_dir(temp_dir: Option<&Path>) -> Result<tempfile::TempDir, std::io::Error> {
+    match temp_dir {
+        Some(temp_dir) => {
+            fs::create_dir_all(temp_dir)?;
+            tempfile::Builder::new().tempdir_in(temp_dir)
+        }
+        None => tempfile::tempdir(),
+    }
+}
+
+/// A temporary local channel with the tested packages. It is removed when it is dropped, unless
+/// it is kept (see [`TestConfiguration::keep_test_channel`]).
+struct LocalChannel {
+    _tmp_dir: Option<tempfile::TempDir>,
+    path: PathBuf,
+}
+
+impl LocalChannel {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Copy the packages into a temporary channel and index it. If `keep` is set, the channel is not
+/// removed afterwards, e.g. to inspect its `repodata.json` if the test environment cannot be
+/// solved.
+fn create_local_channel(
+    package_files: &[&Path],
+    target_platform: &Platform,
+    force_reindex: bool,
+    temp_dir: Option<&Path>,
+    keep: bool,
+) -> Result<LocalChannel, TestError> {
+    let tmp_repo = create_temp_dir(temp_dir)?;
+    let tmp_repo = if keep {
+        let path = tmp_repo.into_path();
+        tracing::info!("Keeping the test channel in {:?}", path);
+        LocalChannel {
+            _tmp_dir: None,
+            path,
+        }
+    } else {
+        LocalChannel {
+            path: tmp_repo.path().to_path_buf(),
+            _tmp_dir: Some(tmp_repo),
+        }
+    };
+
+    let subdir = tmp_repo.path().join(target_platform.to_string());
+    std::fs::create_dir_all(&subdir)?;
+
+    let copy_span = tracing::info_span!(
+        "copy_packages",
+        packages = package_files.len(),
+        platform = %target_platform
+    )
+    .entered();
+    for package_file in package_files {
+        let file_name = package_file
+            .file_name()
+            .ok_or(TestError::MissingPackageFileName)?;
+        // noarch packages are installed from the `noarch` subdir, like from a real channel
+        let subdir = if is_noarch_package(package_file) {
+            let noarch = tmp_repo.path().join("noarch");
+            fs::create_dir_all(&noarch)?;
+            noarch
+        } else {
+            subdir.clone()
+        };
+        std::fs::copy(package_file, subdir.join(file_name))?;
+    }
+    drop(copy_span);
+
+    // index the temporary channel
+    tracing::info_span!("index_channel", platform = %target_platform)
+        .in_scope(|| index_channel(tmp_repo.path(), target_platform, force_reindex))?;
+
+    Ok(tmp_repo)
+}
+
+/// Returns true if the package is built for the `noarch` subdir (e.g. a `noarch: generic` or
+/// `noarch: python` package). Packages whose metadata cannot be read are not noarch packages,
+/// the indexing reports them.
+fn is_noarch_package(package_file: &Path) -> bool {
+    let Some(archive_type) = ArchiveType::try_from(package_file) else {
+        return false;
+    };
+    file_from_archive(archive_type, package_file, Path::new("info/index.json"))
+        .ok()
+        .and_then(|contents| IndexJson::from_str(&contents).ok())
+        .and_then(|index_json| index_json.subdir)
+        .is_some_and(|subdir| subdir == "noarch")
+}
+
+/// Returns true if every subdirectory of the channel that contains packages has a
+/// `repodata.json` that is newer than all of its packages
+fn repodata_is_current(channel: &Path) -> Result<bool, TestError> {
+    for subdir in fs::read_dir(channel)? {
+        let subdir = subdir?.path();
+        if !subdir.is_dir() {
+            continue;
+        }
+
+        let mut newest_package = None;
+        for entry in fs::read_dir(&subdir)? {
+            let path = entry?.path();
+            if ArchiveType::try_from(path.as_path()).is_some() {
+                let modified = fs::metadata(&path)?.modified()?;
+                newest_package = newest_package.max(Some(modified));
+            }
+        }
+        let Some(newest_package) = newest_package else {
+            continue;
+        };
+
+        match fs::metadata(subdir.join("repodata.json")) {
+            Ok(repodata) if repodata.modified()? >= newest_package => {}
+            Ok(_) => return Ok(false),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Index the channel, unless its repodata is newer than its packages (and re-indexing is not
+/// forced)
+fn index_channel(
+    channel: &Path,
+    target_platform: &Platform,
+    force_reindex: bool,
+) -> Result<(), TestError> {
+    if !force_reindex && repodata_is_current(channel)? {
+        tracing::debug!(
+            "The repodata of {:?} is up to date, not re-indexing",
+            channel
+        );
+        return Ok(());
+    }
+    let indexing_failed = |source| TestError::IndexingFailed {
+        path: channel.to_path_buf(),
+        platform: *target_platform,
+        source,
+    };
+    // the `noarch` subdir is only indexed if it has no repodata yet, it may be outdated
+    let noarch = channel.join("noarch");
+    if *target_platform != Platform::NoArch && noarch.join("repodata.json").exists() {
+        fs::remove_file(noarch.join("repodata.json"))?;
+    }
+    index::index(channel, Some(target_platform)).map_err(indexing_failed)?;
+
+    // packages that cannot be read are left out of the repodata, but the tests need all of them
+    for subdir in [channel.join(target_platform.to_string()), noarch] {
+        if !subdir.join("repodata.json").exists() {
+            continue;
+        }
+        let repodata =
+            RepoData::from_path(subdir.join("repodata.json")).map_err(indexing_failed)?;
+        for entry in fs::read_dir(&subdir)? {
+            let path = entry?.path();
+            if ArchiveType::try_from(path.as_path()).is_none() {
+                continue;
+            }
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if !repodata.packages.contains_key(&file_name)
+                && !repodata.conda_packages.contains_key(&file_name)
+            {
+                return Err(indexing_failed(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("could not read the package metadata (info/index.json) of {file_name}"),
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A test environment in which a package and its test dependencies are installed
+struct TestEnvironment {
+    /// The package that is installed in the environment
+    pkg: ArchiveIdentifier,
+    /// The prefix of the environment
+    prefix: PathBuf,
+    /// The folder of the extracted package in the package cache
+    package_folder: PathBuf,
+    /// The SHA256 hash of the package file (if the package was installed from a package file)
+    sha256: Option<String>,
+    /// The run-local package cache that the package was extracted into, instead of the
+    /// configured package cache (see [`TestConfiguration::clear_cached_package`])
+    package_cache: Option<tempfile::TempDir>,
+}
+
+/// The contents of `info/test/test_time_dependencies.json`: a list of match specs, or (as written
+/// by newer packaging tools) an object with the dependencies and the channels they come from
+#[derive(Debug, Default, serde::Deserialize)]
+struct TestTimeDependencies {
+    #[serde(default)]
+    dependencies: Vec<String>,
+    #[serde(default)]
+    channels: Vec<String>,
+}
+
+impl TestTimeDependencies {
+    /// Parse the contents of the file (at `path`, for the error message)
+    fn parse(contents: &str, path: &Path) -> Result<Self, TestError> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Format {
+            List(Vec<String>),
+            Object(TestTimeDependencies),
+        }
+
+        match serde_json::from_str(contents) {
+            Ok(Format::List(dependencies)) => Ok(Self {
+                dependencies,
+                channels: Vec::new(),
+            }),
+            Ok(Format::Object(dependencies)) => Ok(dependencies),
+            Err(source) => Err(TestError::TestDependenciesParseError {
+                file: path.to_path_buf(),
+                source,
+            }),
+        }
+    }
+
+    /// Read the file from a test folder, if it exists
+    fn from_folder(test_folder: &Path) -> Result<Self, TestError> {
+        let path = test_folder.join("test_time_dependencies.json");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read_to_string(&path)?, &path)
+    }
+
+    /// Add the channels that are not used yet to the channels of the solve (with the lowest
+    /// priority)
+    fn add_channels(&self, channels: &mut Vec<String>) {
+        for channel in &self.channels {
+            if !channels.contains(channel) {
+                channels.push(channel.clone());
+            }
+        }
+    }
+}
+
+/// What is needed to create the test environment of a package
+struct TestRequirements {
+    /// The package that is tested
+    pkg: ArchiveIdentifier,
+    /// The SHA256 hash (hex encoded) of the package file
+    sha256: String,
+    /// The package itself and its test dependencies
+    dependencies: Vec<MatchSpec>,
+    /// The channels from which the test environment is created
+    channels: Vec<String>,
+}
+
+/// [`test_requirements`] on a blocking thread, as verifying the package reads the whole archive
+async fn blocking_test_requirements(
+    package_file: &Path,
+    config: &TestConfiguration,
+    local_channel: &Path,
+) -> Result<TestRequirements, TestError> {
+    let (package_file, config, local_channel) = (
+        package_file.to_path_buf(),
+        config.clone(),
+        local_channel.to_path_buf(),
+    );
+    run_blocking(move || test_requirements(&package_file, &config, &local_channel)).await
+}
+
+/// Verify the package and collect the dependencies and channels of its test environment
+fn test_requirements(
+    package_file: &Path,
+    config: &TestConfiguration,
+    local_channel: &Path,
+) -> Result<TestRequirements, TestError> {
+    let archive_type =
+        ArchiveType::try_from(package_file).ok_or(TestError::ArchiveTypeNotSupported)?;
+
+    let sha256 = format!(
+        "{:x}",
+        rattler_digest::compute_file_digest::<rattler_digest::Sha256>(package_file)?
+    );
+    if !config.skip_checksum_verification {
+        verify_package_checksum(package_file, archive_type, &sha256, config)?;
+    }
+
+    let test_dep_json = PathBuf::from("info/test/test_time_dependencies.json");
+    let test_dependencies = file_from_archive(archive_type, package_file, &test_dep_json);
+
+    let test_dependencies = match test_dependencies {
+        Ok(contents) => TestTimeDependencies::parse(&contents, &test_dep_json)?,
+        Err(error) => {
+            if error.kind() == std::io::ErrorKind::NotFound {
+                TestTimeDependencies::default()
+            } else {
+                return Err(TestError::PackageRead(error));
+            }
+        }
+    };
+    let mut dependencies = test_dependencies
+        .dependencies
+        .iter()
+        .map(|s| MatchSpec::from_str(s))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if config.include_run_exports {
+        match file_from_archive(
+            archive_type,
+            package_file,
+            Path::new("info/run_exports.json"),
+        ) {
+            Ok(contents) => {
+                let run_exports = RunExportsJson::from_str(&contents)?;
+                for spec in run_exports.weak.iter().chain(run_exports.strong.iter()) {
+                    dependencies.push(MatchSpec::from_str(spec)?);
+                }
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => return Err(TestError::PackageRead(error)),
+        }
+    }
+
+    let package_channels = if config.include_package_channels {
+        match file_from_archive(archive_type, package_file, Path::new("info/about.json")) {
+            Ok(contents) => AboutJson::from_str(&contents)?.channels,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(TestError::PackageRead(error)),
+        }
+    } else {
+        Vec::new()
+    };
+    let mut channels = config.test_channels(local_channel, &package_channels);
+    test_dependencies.add_channels(&mut channels);
+
+    let pkg = ArchiveIdentifier::try_from_path(package_file)
+        .ok_or_else(|| TestError::InvalidPackageFileName(package_file.to_path_buf()))?;
+
+    dependencies.push(exact_match_spec(&pkg)?);
+
+    let manifest_path = Path::new("info/test").join(manifest::MANIFEST_FILE_NAME);
+    let manifest = match file_from_archive(archive_type, package_file, &manifest_path) {
+        Ok(contents) => TestManifest::from_yaml(&contents)?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => TestManifest::default(),
+        Err(error) => return Err(TestError::PackageRead(error)),
+    };
+    for spec in &manifest.requires {
+        dependencies.push(MatchSpec::from_str(spec)?);
+    }
+
+    dependencies.extend(test_type_specs(config, &manifest, || {
+        info_test_files(archive_type, package_file)
+    })?);
+
+    dependencies.extend(config.extra_dependencies.iter().cloned());
+    let dependencies = environment_dependencies(&pkg, dependencies, config)?;
+
+    Ok(TestRequirements {
+        pkg,
+        sha256,
+        dependencies,
+        channels,
+    })
+}
+
+/// The dependencies from `config.test_type_dependencies` of the types of tests that are run for
+/// the package. `test_files` lists the files in the `info/test` folder of the package, it is
+/// only called if the manifest of the package does not define the tests.
+fn test_type_specs(
+    config: &TestConfiguration,
+    manifest: &TestManifest,
+    test_files: impl FnOnce() -> Result<Vec<PathBuf>, std::io::Error>,
+) -> Result<Vec<MatchSpec>, TestError> {
+    let mut specs = Vec::new();
+    if config.test_type_dependencies.is_empty() {
+        return Ok(specs);
+    }
+    let mut test_types = if manifest.defines_tests() {
+        let mut test_types = BTreeSet::new();
+        if !manifest.commands.is_empty() {
+            test_types.insert(TestType::Commands);
+        }
+        if !manifest.imports.is_empty() {
+            test_types.insert(TestType::Python);
+        }
+        if !manifest.files.is_empty() {
+            test_types.insert(TestType::Files);
+        }
+        test_types
+    } else {
+        test_files()?
+            .into_iter()
+            .filter_map(Tests::from_path)
+            .map(|test| test.test_type())
+            .collect::<BTreeSet<_>>()
+    };
+    if config.pip_check || manifest.pip_check {
+        test_types.insert(TestType::PipCheck);
+    }
+    if config.linkage_check || manifest.linkage_check {
+        test_types.insert(TestType::Linkage);
+    }
+    if config.entry_point_check || manifest.entry_point_check {
+        test_types.insert(TestType::EntryPoints);
+    }
+    if config.menuinst_check || manifest.menuinst_check {
+        test_types.insert(TestType::MenuInst);
+    }
+    if config.prefix_check || manifest.prefix_check {
+        test_types.insert(TestType::PrefixCheck);
+    }
+    if config.verify_installed_files {
+        test_types.insert(TestType::InstalledFiles);
+    }
+    for test_type in test_types {
+        if let Some(specs_of_type) = config.test_type_dependencies.get(&test_type) {
+            specs.extend(specs_of_type.iter().cloned());
+        }
+    }
+    Ok(specs)
+}
+
+/// The dependencies of the test environment in the configured environment mode
+fn environment_dependencies(
+    pkg: &ArchiveIdentifier,
+    dependencies: Vec<MatchSpec>,
+    config: &TestConfiguration,
+) -> Result<Vec<MatchSpec>, TestError> {
+    Ok(match config.environment_mode {
+        // the minimal environment only contains the package and its own dependencies
+        TestEnvironmentMode::Minimal => vec![exact_match_spec(pkg)?],
+        // the package is installed on top of the base, without its dependencies
+        TestEnvironmentMode::NoDeps => config.extra_dependencies.clone(),
+        TestEnvironmentMode::Full => dependencies,
+    })
+}
+
+/// Create the test environment for a package: the package is copied into a temporary channel,
+/// which is indexed and used together with the configured channels to install the package and
+/// its test dependencies into the test prefix.
+async fn prepare_test_environment(
+    package_file: &Path,
+    config: &TestConfiguration,
+    local_channel: Option<&Path>,
+) -> Result<TestEnvironment, TestError> {
+    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+    let tmp_repo;
+    let local_channel = match local_channel {
+        Some(local_channel) => local_channel,
+        None => {
+            tmp_repo = create_local_channel(
+                &[package_file],
+                &target_platform,
+                config.force_reindex,
+                config.temp_dir.as_deref(),
+                config.keep_test_channel,
+            )?;
+            tmp_repo.path()
+        }
+    };
+
+    let TestRequirements {
+        pkg,
+        sha256,
+        dependencies,
+        channels,
+    } = blocking_test_requirements(package_file, config, local_channel).await?;
+
+    let mut global_configuration = config.tool_configuration();
+
+    // The package cache is keyed by name, version and build string only. Remember the SHA256 of
+    // the package that was extracted into the cache, so that a rebuilt package with the same name
+    // does not reuse the stale extracted package.
+    let cache_key = CacheKey::from(pkg.clone());
+    let cache_dir = global_configuration.cache_dir()?;
+    let mut package_folder = cache_dir.join("pkgs").join(cache_key.to_string());
+    let mut sha256_file = cache_dir.join("pkgs").join(format!("{}.sha256", cache_key));
+
+    let mut package_cache = None;
+    if config.clear_cached_package {
+        reuse_cached_extraction(&package_folder, &sha256_file, &sha256)?;
+    } else if package_folder.exists() && !is_cached_extraction_of(&sha256_file, &sha256) {
+        // another build of the package is cached, it may be in use by another process
+        let cache = use_run_local_package_cache(config, &mut global_configuration)?;
+        package_folder = cache.path().join("pkgs").join(cache_key.to_string());
+        sha256_file = cache
+            .path()
+            .join("pkgs")
+            .join(format!("{}.sha256", cache_key));
+        package_cache = Some(cache);
+    }
+
+    let prefix = canonicalize(&config.test_prefix)?;
+    if cfg!(windows) {
+        let (prefix, package_file) = (prefix.clone(), package_file.to_path_buf());
+        run_blocking(move || warn_about_long_paths(&prefix, &package_file)).await?;
+    }
+
+    let reuse_prefix = if config.reuse_prefix && prefix.join("conda-meta").is_dir() {
+        let installed = find_installed_packages(&prefix, 100).await?;
+        let mut missing = dependencies
+            .iter()
+            .filter(|spec| {
+                !installed
+                    .iter()
+                    .any(|record| spec.matches(&record.repodata_record.package_record))
+            })
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        // the installed package has to be this exact build, not an older build with the same name
+        let package_installed = installed.iter().any(|record| {
+            record
+                .repodata_record
+                .package_record
+                .sha256
+                .map(|hash| format!("{:x}", hash))
+                .as_deref()
+                == Some(sha256.as_str())
+        });
+        if missing.is_empty() && !package_installed {
+            missing.push(format!("{} (a different build is installed)", pkg.name));
+        }
+
+        if missing.is_empty() {
+            true
+        } else if config.augment_prefix {
+            tracing::info!(
+                "The existing prefix {:?} is missing {}, updating it",
+                prefix,
+                missing.join(", ")
+            );
+            false
+        } else {
+            return Err(TestError::PrefixNotSatisfied { prefix, missing });
+        }
+    } else {
+        false
+    };
+
+    let records = if reuse_prefix {
+        tracing::info!("Reusing the existing test environment in {:?}", prefix);
+        if !package_folder.exists() {
+            let span = tracing::info_span!("extract_package", package = %pkg.name);
+            let (archive, destination) = (package_file.to_path_buf(), package_folder.clone());
+            run_blocking(move || span.in_scope(|| extract_package(&archive, &destination))).await?;
+        }
+        if config.write_explicit_env.is_some() {
+            find_installed_packages(&prefix, 100)
+                .await?
+                .into_iter()
+                .map(|record| record.repodata_record)
+                .collect()
+        } else {
+            Vec::new()
+        }
+    } else {
+        install_test_environment(
+            &pkg,
+            &dependencies,
+            &channels,
+            &prefix,
+            config,
+            &global_configuration,
+        )
+        .await?
+    };
+
+    write_explicit_environment(&records, config)?;
+    fs::write(&sha256_file, &sha256)?;
+
+    Ok(TestEnvironment {
+        pkg,
+        prefix,
+        package_folder,
+        sha256: Some(sha256),
+        package_cache,
+    })
+}
+
+/// Install the test dependencies of the package into the prefix: from the lockfile (if
+/// configured), on top of the base dependencies (in the `NoDeps` environment mode) or by solving
+/// the dependencies. Returns the records of the installed packages.
+async fn install_test_environment(
+    pkg: &ArchiveIdentifier,
+    dependencies: &[MatchSpec],
+    channels: &[String],
+    prefix: &Path,
+    config: &TestConfiguration,
+    global_configuration: &tool_configuration::Configuration,
+) -> Result<Vec<RepoDataRecord>, TestError> {
+    let records = if let Some(lockfile) = &config.lockfile {
+        tracing::info!(
+            "Creating test environment in {:?} from the lockfile {:?}",
+            prefix,
+            lockfile
+        );
+
+        let platform = activation_platform(config.target_platform);
+        let records = lockfile::locked_records(
+            lockfile,
+            pkg,
+            platform,
+            dependencies,
+            channels,
+            global_configuration,
+        )
+        .await?;
+        install_records(
+            &records,
+            pkg,
+            platform,
+            prefix,
+            config,
+            global_configuration,
+        )
+        .instrument(tracing::info_span!(
+            "create_environment",
+            package = %pkg.name,
+            platform = %platform
+        ))
+        .await?;
+        records
+    } else if config.environment_mode == TestEnvironmentMode::NoDeps {
+        tracing::info!(
+            "Creating test environment in {:?} without the dependencies of {}",
+            prefix,
+            pkg.name
+        );
+
+        let platform = activation_platform(config.target_platform);
+        install_without_dependencies(
+            pkg,
+            dependencies,
+            platform,
+            prefix,
+            channels,
+            config,
+            global_configuration,
+        )
+        .instrument(tracing::info_span!(
+            "create_environment",
+            package = %pkg.name,
+            platform = %platform
+        ))
+        .await?
+    } else {
+        tracing::info!("Creating test environment in {:?}", prefix);
+
+        let platform = activation_platform(config.target_platform);
+        let span = tracing::info_span!(
+            "create_environment",
+            package = %pkg.name,
+            platform = %platform
+        );
+        if config.environment_cache.is_some() {
+            let records = solve_environment(
+                dependencies,
+                Vec::new(),
+                channels,
+                platform,
+                &config.virtual_packages,
+                config.channel_priority,
+                config.solver,
+                global_configuration,
+            )
+            .await
+            .map_err(|e| environment_error(e, dependencies, channels))?;
+            install_records(
+                &records,
+                pkg,
+                platform,
+                prefix,
+                config,
+                global_configuration,
+            )
+            .instrument(span)
+            .await?;
+            records
+        } else {
+            create_environment(
+                dependencies,
+                &platform,
+                prefix,
+                channels,
+                &config.virtual_packages,
+                config.channel_priority,
+                config.solver,
+                global_configuration,
+            )
+            .instrument(span)
+            .await
+            .map_err(|e| environment_error(e, dependencies, channels))?
+        }
+    };
+    Ok(records)
+}
+
+/// Write the explicit environment spec of the installed records (if configured)
+fn write_explicit_environment(
+    records: &[RepoDataRecord],
+    config: &TestConfiguration,
+) -> Result<(), TestError> {
+    if let Some(path) = &config.write_explicit_env {
+        tracing::info!("Writing the explicit environment spec to {:?}", path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let platform = activation_platform(config.target_platform);
+        fs::write(path, explicit_environment_spec(records, platform))?;
+    }
+    Ok(())
+}
+
+/// Install the records into the prefix without solving, by cloning the environment from the
+/// environment cache if one is configured (see [`TestConfiguration::environment_cache`])
+async fn install_records(
+    records: &[RepoDataRecord],
+    pkg: &ArchiveIdentifier,
+    platform: Platform,
+    prefix: &Path,
+    config: &TestConfiguration,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<(), TestError> {
+    match &config.environment_cache {
+        Some(cache_dir) => {
+            environment_cache::install_cached_environment(
+                cache_dir,
+                records,
+                &pkg.name,
+                platform,
+                prefix,
+                tool_configuration,
+            )
+            .await
+        }
+        None => install_environment(&records.to_vec(), &platform, prefix, tool_configuration)
+            .await
+            .map_err(TestError::TestEnvironmentSetup),
+    }
+}
+
+/// Paths that are less than this many characters below MAX_PATH are reported as too long
+const LONG_PATH_MARGIN: usize = 20;
+
+/// Warn if a file of the package gets close to MAX_PATH when it is installed into the prefix.
+/// The files are created through extended-length paths, but tools in the test environment that
+/// do not support them (e.g. `cmd.exe`) fail to open the file.
+fn warn_about_long_paths(prefix: &Path, package_file: &Path) -> Result<(), TestError> {
+    // packages without paths.json are not checked
+    let Ok(contents) = read_package_file(package_file, Path::new("info/paths.json")) else {
+        return Ok(());
+    };
+    let paths_json = PathsJson::from_str(&String::from_utf8_lossy(&contents))?;
+    let longest = paths_json
+        .paths
+        .iter()
+        .map(|entry| prefix.join(&entry.relative_path))
+        .max_by_key(|path| path.as_os_str().len());
+    if let Some(path) = longest {
+        let length = path.as_os_str().len();
+        if length + LONG_PATH_MARGIN >= MAX_PATH {
+            tracing::warn!(
+                "{:?} has {} characters, tools that are limited to {} characters (MAX_PATH) cannot open it. Use a shorter test prefix or enable long paths in Windows.",
+                path,
+                length,
+                MAX_PATH
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Install the package without solving its dependencies, together with the environment that is
+/// solved from the base specs (see [`TestEnvironmentMode::NoDeps`]). Returns the installed records.
+async fn install_without_dependencies(
+    pkg: &ArchiveIdentifier,
+    base: &[MatchSpec],
+    platform: Platform,
+    prefix: &Path,
+    channels: &[String],
+    config: &TestConfiguration,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<Vec<RepoDataRecord>, TestError> {
+    let package_spec = exact_match_spec(pkg)?;
+    let package_record = load_repodatas(
+        &[package_spec.clone()],
+        channels,
+        platform,
+        tool_configuration,
+    )
+    .await
+    .map_err(TestError::TestEnvironmentSetup)?
+    .into_iter()
+    .flatten()
+    .find(|record| package_spec.matches(&record.package_record))
+    .ok_or_else(|| TestError::PackageNotFound(package_spec.to_string()))?;
+
+    let mut records = if base.is_empty() {
+        Vec::new()
+    } else {
+        solve_environment(
+            base,
+            Vec::new(),
+            channels,
+            platform,
+            &config.virtual_packages,
+            config.channel_priority,
+            config.solver,
+            tool_configuration,
+        )
+        .await
+        .map_err(|e| environment_error(e, base, channels))?
+    };
+    // the base has to be installed with the tested build of the package
+    records.retain(|record| record.package_record.name.as_normalized() != pkg.name);
+    let depends = &package_record.package_record.depends;
+    if !depends.is_empty() {
+        tracing::info!(
+            "Not installing the dependencies of {}: {}",
+            pkg.name,
+            depends.join(", ")
+        );
+    }
+    records.push(package_record);
+
+    install_environment(&records, &platform, prefix, tool_configuration)
+        .await
+        .map_err(TestError::TestEnvironmentSetup)?;
+    Ok(records)
+}
+
+/// Check whether the package that was extracted into the package cache is the package with the
+/// given SHA256 hash (as recorded in the marker file). If it is, the extraction is reused and the
+/// installation does not extract the package again. Otherwise the stale extraction is removed.
+/// Returns true if the cached extraction is reused.
+fn reuse_cached_extraction(
+    package_folder: &Path,
+    sha256_file: &Path,
+    sha256: &str,
+) -> Result<bool, TestError> {
+    if !package_folder.exists() {
+        return Ok(false);
+    }
+
+    if is_cached_extraction_of(sha256_file, sha256) {
+        tracing::info!("Reusing cached extraction {:?}", package_folder);
+        return Ok(true);
+    }
+
+    tracing::info!("Removing previously cached package {:?}", package_folder);
+    fs::remove_dir_all(package_folder)?;
+    // the marker belongs to the removed extraction
+    let _ = fs::remove_file(sha256_file);
+    Ok(false)
+}
+
+/// Returns true if the marker file records that the package with the given SHA256 hash was
+/// extracted into the package cache
+fn is_cached_extraction_of(sha256_file: &Path, sha256: &str) -> bool {
+    let cached_sha256 = fs::read_to_string(sha256_file).ok();
+    cached_sha256.as_deref().map(str::trim) == Some(sha256)
+}
+
+/// Install the packages of the run from a package cache in a temporary directory instead of the
+/// configured package cache, which contains another build of the tested package that is not
+/// removed (see [`TestConfiguration::clear_cached_package`])
+fn use_run_local_package_cache(
+    config: &TestConfiguration,
+    global_configuration: &mut tool_configuration::Configuration,
+) -> Result<tempfile::TempDir, TestError> {
+    let package_cache = create_temp_dir(config.temp_dir.as_deref())?;
+    tracing::info!(
+        "The package cache contains another build of the package, using the run-local package cache {:?}",
+        package_cache.path()
+    );
+    global_configuration.cache_dir = Some(package_cache.path().to_path_buf());
+    Ok(package_cache)
+}
+
+/// Render the records of an environment as an explicit environment spec (like `conda list
+/// --explicit --md5`), from which the environment can be recreated without solving
+fn explicit_environment_spec(records: &[RepoDataRecord], platform: Platform) -> String {
+    let mut spec = format!("# platform: {}\n@EXPLICIT\n", platform);
+    for record in records {
+        match &record.package_record.md5 {
+            Some(md5) => spec.push_str(&format!("{}#{:x}\n", record.url, md5)),
+            None => spec.push_str(&format!("{}\n", record.url)),
+        }
+    }
+    spec
+}
+
+/// Run a single, arbitrary command in the test environment of a package
+///
+/// The environment is created exactly like [`run_test`] would create it (including the test
+/// dependencies of the package) and the command is run in the fully activated environment, with
+/// the test folder of the package as working directory. The output of the command is captured and
+/// returned, a non-zero exit code is not treated as an error. Like a test command, the command
+/// is killed after `timeout` and its output is written to `<log_dir>/<package>/command.log` if
+/// a log directory is set.
+///
+/// The test prefix is kept if `keep_test_prefix` is set, otherwise it is removed afterwards.
+pub async fn run_command_in_test_environment(
+    package_file: &Path,
+    config: &TestConfiguration,
+    command: &str,
+) -> Result<CommandOutput, TestError> {
+    config.validate()?;
+    if let Some(key) = &config.verify_signature {
+        signature::verify_signature(package_file, key)?;
+    }
+
+    let _prefix_guard = TestPrefixGuard::new(
+        &config.test_prefix,
+        config.keep_test_prefix || config.reuse_prefix,
+    );
+    let environment = prepare_test_environment(package_file, config, None).await?;
+
+    let test_folder = environment.package_folder.join("info/test");
+    let cwd = if test_folder.exists() {
+        test_folder
+    } else {
+        environment.package_folder.clone()
+    };
+
+    let log_file = match &config.log_dir {
+        Some(dir) => {
+            let dir = dir.join(CacheKey::from(environment.pkg.clone()).to_string());
+            fs::create_dir_all(&dir)?;
+            Some(dir.join(format!("{}.log", COMMAND_LOG_NAME)))
+        }
+        None => None,
+    };
+    let platform = activation_platform(config.target_platform);
+    let env_vars = test_env_vars(&environment.pkg, config);
+    let prefix = environment.prefix.clone();
+    let (config, command) = (config.clone(), command.to_string());
+    // the command is waited for on the blocking thread pool, like the tests
+    run_blocking(move || {
+        run_in_environment(
+            platform_shell(platform),
+            command,
+            &cwd,
+            &prefix,
+            platform,
+            ActivationMode::Full,
+            &env_vars,
+            log_file.as_deref(),
+            &config,
+        )
+    })
+    .await
+}
+
+/// The name of the log file of [`run_command_in_test_environment`]
+const COMMAND_LOG_NAME: &str = "command";
+
+/// The shell that runs the scripts of the platform: `cmd.exe` on Windows and `bash` everywhere
+/// else
+fn platform_shell(platform: Platform) -> ShellEnum {
+    if platform.is_windows() {
+        ShellEnum::CmdExe(CmdExe)
+    } else {
+        ShellEnum::Bash(Bash)
+    }
+}
+
+/// Removes the test prefix when it is dropped (on success as well as on errors, including
+/// [`TestError::Interrupted`]), unless the prefix should be kept
+struct TestPrefixGuard {
+    prefix: PathBuf,
+    keep: bool,
+    _interrupt: interrupt::InterruptGuard,
+}
+
+impl TestPrefixGuard {
+    fn new(prefix: &Path, keep: bool) -> Self {
+        Self {
+            prefix: prefix.to_path_buf(),
+            keep,
+            _interrupt: interrupt::InterruptGuard::new(),
+        }
+    }
+
+    /// Keep the prefix, e.g. to be able to inspect it after a test failed
+    fn keep(&mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for TestPrefixGuard {
+    fn drop(&mut self) {
+        if self.keep || !self.prefix.exists() {
+            return;
+        }
+        let _span = tracing::info_span!("cleanup", prefix = ?self.prefix).entered();
+        // a failing cleanup must not hide the outcome of the tests
+        if let Err(e) = fs::remove_dir_all(&self.prefix) {
+            tracing::warn!("Failed to remove the test prefix {:?}: {}", self.prefix, e);
+        }
+    }
+}
+
+/// Run the tests of a package and collect the outcome of every test
+async fn test_package(
+    package_file: &Path,
+    config: &TestConfiguration,
+    local_channel: Option<&Path>,
+) -> Result<TestResult, TestError> {
+    let unique_config;
+    let config = if config.unique_prefix_per_package {
+        let pkg = ArchiveIdentifier::try_from_path(package_file)
+            .ok_or_else(|| TestError::InvalidPackageFileName(package_file.to_path_buf()))?;
+        let mut config = config.clone();
+        config.test_prefix = config
+            .test_prefix
+            .join(format!("{}-{}-{}", pkg.name, pkg.version, pkg.build_string));
+        if !config.dry_run {
+            fs::create_dir_all(&config.test_prefix)?;
+        }
+        unique_config = config;
+        &unique_config
+    } else {
+        config
+    };
+
+    if let Some(key) = &config.verify_signature {
+        signature::verify_signature(package_file, key)?;
+    }
+
+    if config.dry_run {
+        return dry_run(package_file, config, local_channel).await;
+    }
+
+    let started = Instant::now();
+    if config.skip_environment_without_tests && contains_no_tests(package_file, config).await? {
+        let pkg = ArchiveIdentifier::try_from_path(package_file)
+            .ok_or_else(|| TestError::InvalidPackageFileName(package_file.to_path_buf()))?;
+        tracing::warn!(
+            "The package {} contains no tests, the test environment is not created",
+            pkg.name
+        );
+        let mut test_result = TestResult::new(pkg);
+        test_result.no_tests = true;
+        test_result.total_duration = started.elapsed();
+        test_result.size = Some(fs::metadata(package_file)?.len());
+        return Ok(test_result);
+    }
+
+    let mut prefix_guard = TestPrefixGuard::new(
+        &config.test_prefix,
+        config.keep_test_prefix || config.reuse_prefix,
+    );
+
+    // the local channel is shared with the environments of the other Python versions
+    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+    let tmp_repo;
+    let local_channel = match local_channel {
+        Some(local_channel) => local_channel,
+        None => {
+            tmp_repo = create_local_channel(
+                &[package_file],
+                &target_platform,
+                config.force_reindex,
+                config.temp_dir.as_deref(),
+                config.keep_test_channel,
+            )?;
+            tmp_repo.path()
+        }
+    };
+    if config.check_dependencies {
+        check_package_dependencies(package_file, config, local_channel).await?;
+    }
+    let environment = prepare_test_environment(package_file, config, Some(local_channel)).await?;
+
+    let mut test_result = run_tests_in_environment(environment, config).await?;
+
+    let has_python_tests = test_result
+        .outcomes
+        .iter()
+        .any(|outcome| outcome.test_type == TestType::Python);
+    if has_python_tests {
+        for version in &config.python_versions {
+            let outcomes =
+                test_with_python_version(package_file, config, local_channel, version).await?;
+            test_result.outcomes.extend(outcomes);
+        }
+    }
+
+    test_result.total_duration = started.elapsed();
+    test_result.size = Some(fs::metadata(package_file)?.len());
+    if config.quiet {
+        tracing::debug!("Test durations: {}", test_result.duration_summary());
+        tracing::info!("{}", test_result.one_line_summary());
+    } else {
+        tracing::info!("Test durations: {}", test_result.duration_summary());
+    }
+    if !test_result.all_passed() {
+        // keep the prefix of failing tests for debugging
+        prefix_guard.keep();
+    }
+
+    Ok(test_result)
+}
+
+/// Returns true if the package contains no tests and none of the checks or custom tests of the
+/// configuration are run, so that there is nothing to test in the test environment
+async fn contains_no_tests(
+    package_file: &Path,
+    config: &TestConfiguration,
+) -> Result<bool, TestError> {
+    let configured = config.pip_check
+        || config.linkage_check
+        || config.entry_point_check
+        || config.menuinst_check
+        || config.prefix_check
+        || config.metadata_check
+        || config.check_entry_point_shadowing
+        || config.verify_installed_files
+        || !config.runners.is_empty();
+    if configured {
+        return Ok(false);
+    }
+    let tests =
+        list_discovered_tests(package_file, &config.test_include, &config.test_exclude).await?;
+    Ok(tests.is_empty())
+}
+
+/// Check that an environment with the package and its own dependencies (without the test
+/// dependencies) can be solved. Nothing is installed.
+async fn check_package_dependencies(
+    package_file: &Path,
+    config: &TestConfiguration,
+    local_channel: &Path,
+) -> Result<(), TestError> {
+    let archive_type =
+        ArchiveType::try_from(package_file).ok_or(TestError::ArchiveTypeNotSupported)?;
+    let pkg = ArchiveIdentifier::try_from_path(package_file)
+        .ok_or_else(|| TestError::InvalidPackageFileName(package_file.to_path_buf()))?;
+    let index_json = IndexJson::from_str(&file_from_archive(
+        archive_type,
+        package_file,
+        Path::new("info/index.json"),
+    )?)?;
+
+    let mut specs = vec![exact_match_spec(&pkg)?];
+    for spec in &index_json.depends {
+        specs.push(MatchSpec::from_str(spec)?);
+    }
+
+    tracing::info!(
+        "Checking that the dependencies of {} can be solved",
+        pkg.name
+    );
+    let result = solve_environment(
+        &specs,
+        Vec::new(),
+        &config.test_channels(local_channel, &[]),
+        activation_platform(config.target_platform),
+        &config.virtual_packages,
+        config.channel_priority,
+        config.solver,
+        &config.tool_configuration(),
+    )
+    .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(error) => match error.downcast_ref::<rattler_solve::SolveError>() {
+            Some(rattler_solve::SolveError::Unsolvable(problems)) => {
+                Err(TestError::UnsatisfiableDependencies {
+                    package: format!("{}-{}-{}", pkg.name, pkg.version, pkg.build_string),
+                    specs: index_json.depends.clone(),
+                    problems: problems.clone(),
+                })
+            }
+            _ => Err(TestError::TestEnvironmentSetup(error)),
+        },
+    }
+}
+
+/// The error for a failure to create the test environment. If the environment cannot be solved,
+/// the error lists the requested specs and the channels, together with the explanation of the
+/// solver (e.g. the conflicting dependencies).
+fn environment_error(error: anyhow::Error, specs: &[MatchSpec], channels: &[String]) -> TestError {
+    let explanation = match error.downcast_ref::<rattler_solve::SolveError>() {
+        Some(rattler_solve::SolveError::Unsolvable(problems)) => problems.join("\n"),
+        Some(error) => error.to_string(),
+        None => return TestError::TestEnvironmentSetup(error),
+    };
+    TestError::EnvironmentSolveFailed {
+        specs: specs.iter().map(ToString::to_string).collect(),
+        channels: channels.to_vec(),
+        explanation,
+    }
+}
+
+/// Run the Python tests of a package in a separate environment (in a subdirectory of
+/// `test_prefix`) with the given Python version
+async fn test_with_python_version(
+    package_file: &Path,
+    config: &TestConfiguration,
+    local_channel: &Path,
+    version: &Version,
+) -> Result<Vec<TestCaseOutcome>, TestError> {
+    tracing::info!("Running the Python tests with Python {}", version);
+    let mut config = config.clone();
+    config.test_prefix = config.test_prefix.join(format!("python-{}", version));
+    config.test_filter = TestFilter::ImportsOnly;
+    config.pip_check = false;
+    config.linkage_check = false;
+    config.entry_point_check = false;
+    config.menuinst_check = false;
+    config.prefix_check = false;
+    config.metadata_check = false;
+    config.check_entry_point_shadowing = false;
+    config.uninstall_check = false;
+    config.write_explicit_env = None;
+    // the lockfile pins a single Python version
+    config.lockfile = None;
+    config.runners = Vec::new();
+    config
+        .test_type_dependencies
+        .entry(TestType::Python)
+        .or_default()
+        .push(MatchSpec::from_str(&format!("python ={}", version))?);
+    fs::create_dir_all(&config.test_prefix)?;
+
+    let mut prefix_guard = TestPrefixGuard::new(&config.test_prefix, config.keep_test_prefix);
+    let environment = prepare_test_environment(package_file, &config, Some(local_channel)).await?;
+    let test_result = run_tests_in_environment(environment, &config).await?;
+    if !test_result.all_passed() {
+        prefix_guard.keep();
+    }
+
+    // the other tests of the package are not run again for the Python version
+    Ok(test_result
+        .outcomes
+        .into_iter()
+        .filter(|outcome| !matches!(outcome.status, TestStatus::Skipped(SkipReason::Filtered)))
+        .map(|mut outcome| {
+            outcome.python_version = Some(version.clone());
+            outcome
+        })
+        .collect())
+}
+
+/// Log what testing the package would do (the test environment that would be created and the
+/// tests that would be run), without creating the environment or running any tests. The tests
+/// are reported as skipped.
+async fn dry_run(
+    package_file: &Path,
+    config: &TestConfiguration,
+    local_channel: Option<&Path>,
+) -> Result<TestResult, TestError> {
+    let target_platform = config.target_platform.unwrap_or_else(Platform::current);
+    let tmp_repo;
+    let local_channel = match local_channel {
+        Some(local_channel) => local_channel,
+        None => {
+            tmp_repo = create_local_channel(
+                &[package_file],
+                &target_platform,
+                config.force_reindex,
+                config.temp_dir.as_deref(),
+                config.keep_test_channel,
+            )?;
+            tmp_repo.path()
+        }
+    };
+
+    let requirements = blocking_test_requirements(package_file, config, local_channel).await?;
+    let tests =
+        list_discovered_tests(package_file, &config.test_include, &config.test_exclude).await?;
+
+    let pkg = &requirements.pkg;
+    tracing::info!(
+        "Dry run: testing {}-{}-{} in {:?} ({})",
+        pkg.name,
+        pkg.version,
+        pkg.build_string,
+        config.test_prefix,
+        activation_platform(config.target_platform)
+    );
+    tracing::info!("Channels:");
+    for channel in &requirements.channels {
+        tracing::info!(" - {}", channel);
+    }
+    tracing::info!("Test environment specs:");
+    for spec in &requirements.dependencies {
+        tracing::info!(" - {}", spec);
+    }
+    tracing::info!("Tests:");
+    for test in &tests {
+        tracing::info!(" - {} ({:?})", test.name, test.test_type);
+    }
+
+    let mut test_result = TestResult::new(requirements.pkg);
+    test_result.dry_run = true;
+    test_result.sha256 = Some(requirements.sha256);
+    test_result.size = Some(fs::metadata(package_file)?.len());
+    test_result.outcomes = tests
+        .into_iter()
+        .map(|test| TestCaseOutcome::skipped(test.name, test.test_type, SkipReason::DryRun))
+        .collect();
+    Ok(test_result)
+}
+
+/// The name of the script that runs before the tests, see [`TestConfiguration::pre_test_script`]
+const PRE_TEST_SCRIPT_NAME: &str = "pre_test_script";
+
+/// The name of the script that runs after the tests, see [`TestConfiguration::post_test_script`]
+const POST_TEST_SCRIPT_NAME: &str = "post_test_script";
+
+/// Run a setup or teardown script in the activated test environment
+fn run_hook(
+    name: &str,
+    script: &str,
+    prefix: &Path,
+    cwd: &Path,
+    env_vars: &HashMap<String, String>,
+    log_dir: Option<&Path>,
+    config: &TestConfiguration,
+) -> Result<(), TestError> {
+    tracing::info!("Running the {}", name);
+    let log_file = log_dir.map(|dir| dir.join(format!("{}.log", name)));
+    let output = run_in_environment(
+        ShellEnum::default(),
+        script.to_string(),
+        cwd,
+        prefix,
+        activation_platform(config.target_platform),
+        ActivationMode::Full,
+        env_vars,
+        log_file.as_deref(),
+        config,
+    )?;
+    if output.success() {
+        return Ok(());
+    }
+    Err(TestError::TestFailed {
+        test: name.to_string(),
+        command: output.command.clone(),
+        exit_code: output.exit_code,
+        output_tail: if config.capture_output {
+            output.tail(OUTPUT_TAIL_LINES)
+        } else {
+            String::new()
+        },
+    })
+}
+
+/// Check the captured output of a test against the expectations of the test manifest. The error
+/// shows the unmet expectation (`-`) and the offending lines of the output (`+`), like a diff.
+fn check_expected_output(
+    test: &str,
+    expectations: &[OutputExpectation],
+    output: Option<&CommandOutput>,
+) -> Result<(), TestError> {
+    let text = output
+        .map(|output| format!("{}\n{}", output.stdout, output.stderr))
+        .unwrap_or_default();
+    for expectation in expectations {
+        let Some((unmet, lines)) = expectation.check(&text)? else {
+            continue;
+        };
+        let mut diff = format!("- output {}", unmet);
+        if output.is_none() {
+            diff.push_str("\n+ (the test has no captured output)");
+        }
+        for line in &lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..] {
+            diff.push_str("\n+ ");
+            diff.push_str(line);
+        }
+        return Err(TestError::UnexpectedOutput {
+            test: test.to_string(),
+            diff,
+        });
+    }
+    Ok(())
+}
+
+/// The error of a test that was aborted because the tests exceeded
+/// [`TestConfiguration::total_timeout`]
+fn total_timeout_error(config: &TestConfiguration, test: String) -> TestError {
+    TestError::TotalTimeout {
+        limit: config.total_timeout.unwrap_or_default(),
+        test,
+    }
+}
+
+/// Record the environment mode of the outcomes from `reported` on and pass them to the observer.
+/// Returns the number of reported outcomes.
+fn report_finished_tests(
+    config: &TestConfiguration,
+    outcomes: &mut [TestCaseOutcome],
+    reported: usize,
+) -> usize {
+    for outcome in &mut outcomes[reported..] {
+        outcome.environment_mode = config.environment_mode;
+        if let Some(observer) = &config.observer {
+            observer.on_test_finish(outcome);
+        }
+    }
+    outcomes.len()
+}
+
+/// Add a run with the minimal activation after every command test (see
+/// [`TestConfiguration::test_minimal_activation`])
+fn with_minimal_activation_runs(tests: Vec<Tests>) -> Vec<Tests> {
+    let mut with_minimal = Vec::with_capacity(tests.len());
+    for test in tests {
+        let minimal = match &test {
+            Tests::Commands(path) => Some(Tests::MinimalActivation(path.clone())),
+            _ => None,
+        };
+        with_minimal.push(test);
+        with_minimal.extend(minimal);
+    }
+    with_minimal
+}
+
+/// Run the tests of a package in its (already created) test environment
+async fn run_tests_in_environment(
+    environment: TestEnvironment,
+    config: &TestConfiguration,
+) -> Result<TestResult, TestError> {
+    let TestEnvironment {
+        pkg,
+        prefix,
+        package_folder,
+        sha256,
+        package_cache: _package_cache,
+    } = environment;
+
+    // the environment may have been (re)created since the tests last ran in the prefix
+    invalidate_activation_scripts(&prefix);
+
+    let mut test_result = TestResult::new(pkg.clone());
+    test_result.sha256 = sha256;
+    let cache_key = CacheKey::from(pkg.clone());
+
+    test_progress!(config, "Collecting tests from {:?}", package_folder);
+    let (test_folder, mut tests) =
+        tests_from_folder(&package_folder, &config.test_include, &config.test_exclude)
+            .instrument(tracing::info_span!("collect_tests", package = %pkg.name))
+            .await?;
+    let mut manifest = TestManifest::from_folder(&test_folder)?;
+
+    let mut env_vars = test_env_vars(&pkg, config);
+    // scripts can find the files of the test folder, also if they run in another directory
+    env_vars
+        .entry("TEST_DIR".to_string())
+        .or_insert_with(|| test_folder.to_string_lossy().to_string());
+
+    // tests that are defined in the manifest take precedence over the legacy test files
+    let _manifest_tests_dir = if manifest.defines_tests() {
+        let dir = create_temp_dir(config.temp_dir.as_deref())?;
+        let platform = activation_platform(config.target_platform);
+        let variables = script_env_vars(&prefix, platform, &env_vars)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        manifest.expand_variables(&variables, config.fail_on_undefined_variables)?;
+        tests = manifest
+            .write_test_files(dir.path(), platform)?
+            .into_iter()
+            .filter_map(Tests::from_path)
+            .collect();
+
+        // the imports that are not selected for the platform are reported as skipped
+        if config
+            .test_filter
+            .matches(&Tests::Python(dir.path().join("run_test.py")))
+        {
+            for module in manifest.skipped_imports(platform) {
+                test_result.outcomes.push(TestCaseOutcome::skipped(
+                    format!("import {}", module),
+                    TestType::Python,
+                    SkipReason::NotSelected(platform),
+                ));
+            }
+        }
+        Some(dir)
+    } else {
+        None
+    };
+
+    if config.pip_check || manifest.pip_check {
+        tests.push(Tests::PipCheck);
+    }
+    if config.linkage_check || manifest.linkage_check {
+        tests.push(Tests::LinkageCheck(package_folder.clone()));
+    }
+    if config.entry_point_check || manifest.entry_point_check {
+        tests.push(Tests::EntryPoints(package_folder.clone()));
+    }
+    if config.menuinst_check || manifest.menuinst_check {
+        tests.push(Tests::MenuInst(package_folder.clone()));
+    }
+    if config.prefix_check || manifest.prefix_check {
+        tests.push(Tests::PrefixCheck(package_folder.clone()));
+    }
+    if config.metadata_check || manifest.metadata_check {
+        tests.push(Tests::MetadataCheck {
+            package_folder: package_folder.clone(),
+            pkg: pkg.clone(),
+        });
+    }
+    // the installed files are checked before any script is run
+    if config.verify_installed_files {
+        tests.insert(0, Tests::InstalledFiles(package_folder.clone()));
+    }
+
+    // custom tests run after the tests of the package
+    tests.extend(config.runners.iter().cloned().map(Tests::Custom));
+
+    if tests.is_empty() && test_result.outcomes.is_empty() {
+        tracing::warn!("The package {} contains no tests", pkg.name);
+        test_result.no_tests = true;
+        return Ok(test_result);
+    }
+
+    if config.test_filter != TestFilter::All {
+        let available = tests.iter().map(Tests::name).collect::<Vec<_>>();
+        let (selected, filtered): (Vec<_>, Vec<_>) = tests
+            .into_iter()
+            .partition(|test| config.test_filter.matches(test));
+        tests = selected;
+        if let TestFilter::ByFileName(name) = &config.test_filter {
+            if tests.is_empty() {
+                return Err(TestError::NoMatchingTest {
+                    name: name.clone(),
+                    available,
+                });
+            }
+        }
+        // the tests that do not match the filter are reported as skipped
+        for test in filtered {
+            test_result.outcomes.push(TestCaseOutcome::skipped(
+                test.name(),
+                test.test_type(),
+                SkipReason::Filtered,
+            ));
+        }
+        if tests.is_empty() && !available.is_empty() {
+            tracing::warn!(
+                "None of the {} tests of {} match the test filter {:?}",
+                available.len(),
+                pkg.name,
+                config.test_filter
+            );
+        }
+    }
+
+    // without activation, the second run would be the same as the first one
+    if config.test_minimal_activation && !config.skip_activation {
+        tests = with_minimal_activation_runs(tests);
+    }
+
+    if config.check_entry_point_shadowing {
+        let outcome = entry_point_shadowing_outcome(&pkg.name, &package_folder)?;
+        test_result.outcomes.push(outcome);
+    }
+
+    let has_gpu = once_cell::unsync::Lazy::new(host_has_gpu);
+
+    let cwd = match &config.test_cwd {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            canonicalize(dir)?
+        }
+        None => test_folder.clone(),
+    };
+
+    let log_dir = match (&config.log_dir, config.capture_output) {
+        (Some(dir), _) => Some(dir.join(cache_key.to_string())),
+        (None, true) => Some(prefix.join("logs")),
+        (None, false) => None,
+    };
+    if let Some(log_dir) = &log_dir {
+        fs::create_dir_all(log_dir)?;
+    }
+
+    // the setup may write into the prefix, so it runs before the prefix is made read-only
+    if let Some(script) = &config.pre_test_script {
+        run_hook(
+            PRE_TEST_SCRIPT_NAME,
+            script,
+            &prefix,
+            &cwd,
+            &env_vars,
+            log_dir.as_deref(),
+            config,
+        )?;
+    }
+
+    let mut read_only_prefix = if config.read_only_prefix {
+        // the logs may be written into the prefix, keep them writable
+        Some(ReadOnlyPrefix::new(&prefix, log_dir.as_deref())?)
+    } else {
+        None
+    };
+
+    // the directories outside of the test environment that are checked for side effects, without
+    // the directories in which the tests are allowed to write
+    let outside_dirs = if !config.detect_side_effects {
+        Vec::new()
+    } else if config.side_effect_dirs.is_empty() {
+        home_dir().into_iter().collect()
+    } else {
+        config.side_effect_dirs.clone()
+    };
+    let outside_dirs = outside_dirs
+        .iter()
+        .filter_map(|dir| canonicalize(dir).ok())
+        .collect::<Vec<_>>();
+    let inside_dirs = [
+        Some(&prefix),
+        Some(&cwd),
+        Some(&test_folder),
+        log_dir.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|dir| canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+    .collect::<Vec<_>>();
+
+    let deadline = config.total_timeout.map(|limit| Instant::now() + limit);
+    let mut reported = 0;
+    for test in tests {
+        reported = report_finished_tests(config, &mut test_result.outcomes, reported);
+        if interrupt::interrupted() {
+            return Err(TestError::Interrupted);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(total_timeout_error(config, test.name()));
+        }
+
+        let options = manifest.options(&test.options_name());
+        let test_config;
+        let config = if options.allowed_exit_codes.is_empty() {
+            config
+        } else {
+            let mut config = config.clone();
+            config
+                .allowed_exit_codes
+                .extend(options.allowed_exit_codes.iter().copied());
+            test_config = config;
+            &test_config
+        };
+        if options.requires_gpu && !*has_gpu {
+            test_progress!(config, "Skipping test {}: no GPU available", test.name());
+            test_result.outcomes.push(TestCaseOutcome::skipped(
+                test.name(),
+                test.test_type(),
+                SkipReason::NoGpu,
+            ));
+            continue;
+        }
+
+        let platform = activation_platform(config.target_platform);
+        // the files, menu entries and metadata are checked without running executables of the
+        // platform
+        if !matches!(
+            test.test_type(),
+            TestType::Files
+                | TestType::MenuInst
+                | TestType::Metadata
+                | TestType::InstalledFiles
+                | TestType::PrefixCheck
+        ) && !can_run_on_host(platform)
+        {
+            tracing::warn!(
+                "Skipping test {}: cannot run {} executables on {}",
+                test.name(),
+                platform,
+                Platform::current()
+            );
+            test_result.outcomes.push(TestCaseOutcome::skipped(
+                test.name(),
+                test.test_type(),
+                SkipReason::ForeignPlatform(platform),
+            ));
+            continue;
+        }
+
+        if !test.runs_on(platform) {
+            if config.fail_on_unrunnable_tests {
+                tracing::error!("Test {} cannot run on {}", test.name(), platform);
+                let error = TestError::UnrunnableTest {
+                    test: test.name(),
+                    platform,
+                };
+                test_result.outcomes.push(TestCaseOutcome::new(
+                    test.name(),
+                    test.test_type(),
+                    TestStatus::Failed(error),
+                    Duration::ZERO,
+                    None,
+                ));
+                if config.fail_fast {
+                    break;
+                }
+            } else {
+                tracing::warn!("Skipping test {}: cannot run on {}", test.name(), platform);
+                test_result.outcomes.push(TestCaseOutcome::skipped(
+                    test.name(),
+                    test.test_type(),
+                    SkipReason::OtherShell(platform),
+                ));
+            }
+            continue;
+        }
+
+        if options.requires_network && config.offline {
+            test_progress!(
+                config,
+                "Skipping test {}: requires network (offline mode)",
+                test.name()
+            );
+            test_result.outcomes.push(TestCaseOutcome::skipped(
+                test.name(),
+                test.test_type(),
+                SkipReason::Offline,
+            ));
+            continue;
+        }
+
+        let snapshots = if config.verify_side_effects {
+            Some([
+                DirectorySnapshot::new(&test_folder)?,
+                DirectorySnapshot::new(&prefix)?,
+            ])
+        } else {
+            None
+        };
+        let outside_snapshots = outside_dirs
+            .iter()
+            .map(|dir| DirectorySnapshot::outside(dir, &inside_dirs))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(observer) = &config.observer {
+            observer.on_test_start(&test.description()?);
+        }
+        let started = Instant::now();
+        let span = tracing::info_span!(
+            "run_test",
+            package = %pkg.name,
+            test = %test.name(),
+            test_type = ?test.test_type()
+        );
+        let (output, mut result) = test
+            .run_checked_blocking(
+                &prefix,
+                &cwd,
+                config,
+                &env_vars,
+                log_dir.as_deref(),
+                deadline,
+                span,
+            )
+            .await;
+        let duration = started.elapsed();
+        if matches!(result, Err(TestError::Interrupted)) {
+            return Err(TestError::Interrupted);
+        }
+
+        // the remaining tests are not run, the prefix is removed by the caller
+        let deadline_passed = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        if matches!(result, Err(TestError::TotalTimeout { .. }))
+            || (deadline_passed && matches!(result, Err(TestError::Timeout { .. })))
+        {
+            tracing::error!(
+                "The tests of {} exceeded the total time limit of {:?}",
+                pkg.name,
+                config.total_timeout.unwrap_or_default()
+            );
+            return Err(total_timeout_error(config, test.name()));
+        }
+
+        // find out whether the command failed because it tried to write into the prefix: it is
+        // run again in the writable prefix, and only counts as a violation if that run passes all
+        // of the checks below as well (so that a flaky test is not reported as a violation)
+        let mut read_only_failure = None;
+        let mut writable_output = None;
+        if let (Err(TestError::TestFailed { .. }), Some(read_only_prefix)) =
+            (&result, read_only_prefix.as_mut())
+        {
+            read_only_prefix.restore()?;
+            let (rerun_output, rerun_result) = test
+                .run_checked_blocking(
+                    &prefix,
+                    &cwd,
+                    config,
+                    &env_vars,
+                    log_dir.as_deref(),
+                    deadline,
+                    tracing::Span::current(),
+                )
+                .await;
+            read_only_prefix.apply()?;
+            if matches!(rerun_result, Err(TestError::Interrupted)) {
+                return Err(TestError::Interrupted);
+            }
+            if rerun_result.is_ok() {
+                read_only_failure = Some(std::mem::replace(&mut result, rerun_result));
+                writable_output = rerun_output;
+            }
+        }
+        let checked_output = if read_only_failure.is_some() {
+            writable_output.as_ref()
+        } else {
+            output.as_ref()
+        };
+
+        if result.is_ok() && !options.expect_output.is_empty() {
+            result = check_expected_output(&test.name(), &options.expect_output, checked_output);
+        }
+
+        if !outside_snapshots.is_empty() {
+            let allowed = side_effects::allowed_side_effects(&[])?;
+            let mut paths = Vec::new();
+            for snapshot in &outside_snapshots {
+                paths.extend(snapshot.unexpected_changes(&allowed)?);
+            }
+            if !paths.is_empty() {
+                if config.fail_on_side_effects && result.is_ok() {
+                    result = Err(TestError::UnexpectedSideEffects {
+                        test: test.name(),
+                        paths,
+                    });
+                } else {
+                    tracing::warn!(
+                        "Test {} changed files outside of the test environment:\n{}",
+                        test.name(),
+                        format_paths(&paths)
+                    );
+                }
+            }
+        }
+
+        if let (true, Some(snapshots)) = (result.is_ok(), snapshots) {
+            let allowed = side_effects::allowed_side_effects(&options.side_effects)?;
+            let mut paths = Vec::new();
+            for snapshot in &snapshots {
+                paths.extend(snapshot.unexpected_changes(&allowed)?);
+            }
+            // the log files are written by us, not by the test
+            if let Some(log_dir) = &log_dir {
+                paths.retain(|path| !path.starts_with(log_dir));
+            }
+            if !paths.is_empty() {
+                result = Err(TestError::UnexpectedSideEffects {
+                    test: test.name(),
+                    paths,
+                });
+            }
+        }
+
+        if let Some(failure) = read_only_failure {
+            result = match result {
+                Ok(()) => Err(TestError::PrefixWriteViolation(test.name())),
+                Err(_) => Err(failure),
+            };
+        }
+
+        let failed = result.is_err();
+        let status = match result {
+            Ok(()) => TestStatus::Passed,
+            Err(e) => {
+                tracing::error!("Test {} failed: {}", test.name(), e);
+                TestStatus::Failed(e)
+            }
+        };
+        test_result.outcomes.push(TestCaseOutcome::new(
+            test.name(),
+            test.test_type(),
+            status,
+            duration,
+            output,
+        ));
+
+        if failed && config.fail_fast {
+            break;
+        }
+    }
+
+    drop(read_only_prefix);
+
+    reported = report_finished_tests(config, &mut test_result.outcomes, reported);
+
+    // the package is removed last, the tests cannot use the environment afterwards
+    if config.uninstall_check && test_result.all_passed() {
+        if config.reuse_prefix {
+            tracing::warn!("The uninstall check is skipped, the test prefix is reused");
+        } else {
+            test_progress!(config, "Removing {} from the test environment", pkg.name);
+            let started = Instant::now();
+            let status = match check_uninstall(&prefix, &pkg, &package_folder, config).await {
+                Ok(()) => TestStatus::Passed,
+                Err(e) => {
+                    tracing::error!("Test {} failed: {}", UNINSTALL_CHECK_TEST_NAME, e);
+                    TestStatus::Failed(e)
+                }
+            };
+            test_result.outcomes.push(TestCaseOutcome::new(
+                UNINSTALL_CHECK_TEST_NAME.to_string(),
+                TestType::Files,
+                status,
+                started.elapsed(),
+                None,
+            ));
+        }
+    }
+    report_finished_tests(config, &mut test_result.outcomes, reported);
+
+    // a failing teardown does not change the outcomes of the tests
+    if let Some(script) = &config.post_test_script {
+        if let Err(e) = run_hook(
+            POST_TEST_SCRIPT_NAME,
+            script,
+            &prefix,
+            &cwd,
+            &env_vars,
+            log_dir.as_deref(),
+            config,
+        ) {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    if !test_result.all_passed() {
+        let failed = test_result
+            .outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome.status, TestStatus::Failed(_)))
+            .map(|outcome| outcome.name.as_str())
+            .collect::<Vec<_>>();
+        tracing::error!(
+            "{} of {} tests failed: {}",
+            failed.len(),
+            test_result.outcomes.len(),
+            failed.join(", ")
+        );
+        return Ok(test_result);
+    }
+
+    let skipped = test_result
+        .outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome.status, TestStatus::Skipped(_)))
+        .count();
+    if skipped == 0 {
+        test_progress!(
+            config,
+            "{} all tests passed!",
+            style(console::Emoji("✔", "")).green()
+        );
+    } else {
+        test_progress!(
+            config,
+            "{} {} tests passed, {} skipped",
+            style(console::Emoji("✔", "")).green(),
+            test_result.outcomes.len() - skipped,
+            skipped
+        );
+    }
+
+    Ok(test_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        path::{Path, PathBuf},
+        str::FromStr,
+    };
+
+    use rattler_conda_types::{
+        package::{ArchiveIdentifier, ArchiveType},
+        MatchSpec, Platform, Version,
+    };
+
+    use super::{
+        can_run_on, check_entry_point_shadowing, check_files_exist, check_installed_files,
+        check_metadata, check_prefix_placeholders, copy_directory, create_local_channel,
+        create_temp_dir, directory_digest, entry_point_executable, entry_point_shadowing_outcome,
+        entry_points_from_folder, environment_command, environment_error, exact_match_spec,
+        extract_package, find_in_prefix, folder_test_files, import_statements, interpreter_command,
+        matching_channel_records, normalize_line_endings, package_env_vars, pip_check_problems,
+        read_package_file, repodata_is_current, report_finished_tests, reuse_cached_extraction,
+        run_test_from_dir, strip_conda_paths, test_type_specs, tests_from_folder,
+        uninstall_leftovers, use_run_local_package_cache, verify_package_checksum,
+        verify_tar_contents, with_minimal_activation_runs, with_package_file, write_log_file,
+        ActivationMode, CommandOutput, ShellInvocation, TestCaseOutcome, TestConfiguration,
+        TestEnvironmentMode, TestError, TestFilter, TestManifest, TestObserver, TestStatus,
+        TestSuiteRunner, TestTimeDependencies, TestType, Tests,
+    };
+
+    #[test]
+    fn exact_match_spec_for_pre_releases() {
+        for (file_name, version, other_versions) in [
+            (
+                "foo-1.0.0rc1-h123_0.tar.bz2",
+                "1.0.0rc1",
+                &["1.0.0", "1.0.0rc10", "1.0.0rc1.post1"][..],
+            ),
+            (
+                "foo-1.0.0.dev5-h123_0.conda",
+                "1.0.0.dev5",
+                &["1.0.0", "1.0.0.dev50", "1.0.0.dev6"][..],
+            ),
+        ] {
+            let pkg = ArchiveIdentifier::try_from_filename(file_name).unwrap();
+            let spec = exact_match_spec(&pkg).unwrap();
+
+            assert_eq!(spec.name.as_ref().unwrap().as_normalized(), "foo");
+            let version_spec = spec.version.as_ref().unwrap();
+            assert!(version_spec.matches(&Version::from_str(version).unwrap()));
+            for other in other_versions {
+                assert!(
+                    !version_spec.matches(&Version::from_str(other).unwrap()),
+                    "{} should not match {}",
+                    spec,
+                    other
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn files_test_matches_globs() {
+        let prefix = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(prefix.path().join("bin")).unwrap();
+        std::fs::create_dir_all(prefix.path().join("lib/nested/dir")).unwrap();
+        std::fs::write(prefix.path().join("bin/mytool-1.0"), "").unwrap();
+        std::fs::write(prefix.path().join("lib/nested/dir/libfoo.so"), "").unwrap();
+
+        let patterns =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        assert!(check_files_exist(prefix.path(), &[]).is_ok());
+        assert!(check_files_exist(
+            prefix.path(),
+            &patterns(&["bin/mytool*", "lib/**/libfoo.so"])
+        )
+        .is_ok());
+
+        // `*` does not cross directory boundaries
+        let result = check_files_exist(prefix.path(), &patterns(&["bin/mytool*", "lib/*.so"]));
+        assert!(matches!(result, Err(TestError::MissingFile(pattern)) if pattern == "lib/*.so"));
+    }
+
+    #[test]
+    fn package_env_vars_from_identifier() {
+        let pkg = ArchiveIdentifier::try_from_filename("foo-1.2.3-py311h123_3.conda").unwrap();
+        let env_vars = package_env_vars(&pkg);
+        assert_eq!(env_vars["PKG_NAME"], "foo");
+        assert_eq!(env_vars["PKG_VERSION"], "1.2.3");
+        assert_eq!(env_vars["PKG_BUILDNUM"], "3");
+
+        let pkg = ArchiveIdentifier::try_from_filename("foo-1.2.3-custom.tar.bz2").unwrap();
+        assert_eq!(package_env_vars(&pkg)["PKG_BUILDNUM"], "0");
+    }
+
+    #[test]
+    fn solve_errors_list_the_specs_and_channels() {
+        let specs = vec![
+            MatchSpec::from_str("foo ==1.0 0").unwrap(),
+            MatchSpec::from_str("pytest").unwrap(),
+        ];
+        let channels = vec!["conda-forge".to_string()];
+        let error = anyhow::Error::from(rattler_solve::SolveError::Unsolvable(vec![
+            "nothing provides pytest".to_string(),
+        ]));
+        let error = environment_error(error, &specs, &channels);
+        assert!(matches!(error, TestError::EnvironmentSolveFailed { .. }));
+        let message = error.to_string();
+        assert!(message.contains("nothing provides pytest"), "{}", message);
+        assert!(message.contains(" - pytest\n"), "{}", message);
+        assert!(
+            message.ends_with("Channels:\n - conda-forge"),
+            "{}",
+            message
+        );
+
+        let error = environment_error(anyhow::anyhow!("download failed"), &specs, &channels);
+        assert!(matches!(error, TestError::TestEnvironmentSetup(_)));
+    }
+
+    #[test]
+    fn both_formats_of_test_dependencies_are_parsed() {
+        let path = Path::new("info/test/test_time_dependencies.json");
+        let list = TestTimeDependencies::parse(r#"["pytest", "numpy >=1.20"]"#, path).unwrap();
+        assert_eq!(list.dependencies, ["pytest", "numpy >=1.20"]);
+        assert!(list.channels.is_empty());
+
+        let object = TestTimeDependencies::parse(
+            r#"{"dependencies": ["pytest"], "channels": ["conda-forge", "bioconda"]}"#,
+            path,
+        )
+        .unwrap();
+        assert_eq!(object.dependencies, ["pytest"]);
+        let mut channels = vec!["local".to_string(), "conda-forge".to_string()];
+        object.add_channels(&mut channels);
+        assert_eq!(channels, ["local", "conda-forge", "bioconda"]);
+
+        let error = TestTimeDependencies::parse(r#"{"dependencies": "pytest"}"#, path).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "Failed to parse the test dependencies in info/test/test_time_dependencies.json"
+        ));
+    }
+
+    #[tokio::test]
+    async fn tests_from_folder_ignores_extensionless_files() {
+        let pkg = tempfile::tempdir().unwrap();
+        let test_folder = pkg.path().join("info/test");
+        fs::create_dir_all(&test_folder).unwrap();
+        fs::write(test_folder.join("run_test"), "echo hello").unwrap();
+        fs::write(test_folder.join("run_test.py"), "import foo").unwrap();
+
+        let (folder, tests) = tests_from_folder(pkg.path(), &[], &[]).await.unwrap();
+        assert_eq!(folder, test_folder);
+        assert_eq!(
+            tests.iter().map(|test| test.name()).collect::<Vec<_>>(),
+            vec!["run_test.py".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_suites_are_detected() {
+        let pkg = tempfile::tempdir().unwrap();
+        let test_folder = pkg.path().join("info/test");
+        fs::create_dir_all(&test_folder).unwrap();
+        fs::write(test_folder.join("pytest.ini"), "[pytest]\n").unwrap();
+        fs::write(test_folder.join("conftest.py"), "").unwrap();
+        fs::write(test_folder.join("test_foo.py"), "def test_foo(): pass").unwrap();
+
+        let (_, tests) = tests_from_folder(pkg.path(), &[], &[]).await.unwrap();
+        assert_eq!(tests.len(), 1);
+        assert!(matches!(
+            &tests[0],
+            Tests::TestSuite { runner: TestSuiteRunner::Pytest, dir } if *dir == test_folder
+        ));
+        assert_eq!(tests[0].name(), "pytest");
+
+        assert_eq!(
+            TestSuiteRunner::Pytest.summary(
+                "test_foo.py .F\n=== short test summary info ===\n==== 1 failed, 1 passed in 0.12s ====\n"
+            ),
+            Some("1 failed, 1 passed".to_string())
+        );
+        assert_eq!(
+            TestSuiteRunner::Ctest
+                .summary("    Start 1: foo\n\n75% tests passed, 1 tests failed out of 4\n"),
+            Some("75% tests passed, 1 tests failed out of 4".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn tests_from_folder_with_patterns() {
+        let pkg = tempfile::tempdir().unwrap();
+        let test_folder = pkg.path().join("info/test");
+        fs::create_dir_all(test_folder.join("tests")).unwrap();
+        fs::write(test_folder.join("run_test.sh"), "true").unwrap();
+        fs::write(test_folder.join("run_test_helper.sh"), "true").unwrap();
+        fs::write(test_folder.join("tests/a.sh"), "true").unwrap();
+        fs::write(test_folder.join("tests/b.sh"), "true").unwrap();
+
+        let names =
+            |tests: Vec<super::Tests>| tests.iter().map(|test| test.name()).collect::<Vec<_>>();
+
+        let (_, tests) = tests_from_folder(pkg.path(), &[], &[]).await.unwrap();
+        assert_eq!(names(tests), vec!["run_test.sh"]);
+
+        let (_, tests) = tests_from_folder(
+            pkg.path(),
+            &["tests/*.sh".to_string()],
+            &["tests/b.sh".to_string()],
+        )
+        .await
+        .unwrap();
+        assert_eq!(names(tests), vec!["run_test.sh", "a.sh"]);
+
+        let (_, tests) = tests_from_folder(pkg.path(), &[], &["run_test.sh".to_string()])
+            .await
+            .unwrap();
+        assert!(tests.is_empty());
+    }
+
+    #[test]
+    fn pip_check_output() {
+        assert!(pip_check_problems("No broken requirements found.\n").is_empty());
+        assert_eq!(
+            pip_check_problems(
+                "foo 1.0 requires bar, which is not installed.\n\nbaz 2.0 has requirement qux>=2, but you have qux 1.0.\n"
+            ),
+            vec![
+                "foo 1.0 requires bar, which is not installed.".to_string(),
+                "baz 2.0 has requirement qux>=2, but you have qux 1.0.".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn installed_files_are_checked() {
+        let package = tempfile::tempdir().unwrap();
+        fs::create_dir_all(package.path().join("info")).unwrap();
+        fs::write(
+            package.path().join("info/paths.json"),
+            r#"{
+                "paths": [
+                    { "_path": "bin/foo", "path_type": "hardlink", "size_in_bytes": 3 },
+                    { "_path": "share/empty", "path_type": "hardlink", "size_in_bytes": 0 },
+                    { "_path": "site-packages/foo/__init__.py", "path_type": "hardlink", "size_in_bytes": 5 }
+                ],
+                "paths_version": 1
+            }"#,
+        )
+        .unwrap();
+
+        let prefix = tempfile::tempdir().unwrap();
+        fs::create_dir_all(prefix.path().join("bin")).unwrap();
+        fs::create_dir_all(prefix.path().join("share")).unwrap();
+        fs::create_dir_all(prefix.path().join("lib/python3.12/site-packages/foo")).unwrap();
+        fs::write(prefix.path().join("bin/foo"), "").unwrap();
+        fs::write(prefix.path().join("share/empty"), "").unwrap();
+
+        assert!(matches!(
+            check_installed_files(prefix.path(), package.path(), Platform::Linux64),
+            Err(TestError::MissingInstalledFile(paths)) if paths == vec![
+                PathBuf::from("bin/foo"),
+                PathBuf::from("site-packages/foo/__init__.py"),
+            ]
+        ));
+
+        fs::write(prefix.path().join("bin/foo"), "foo").unwrap();
+        fs::write(
+            prefix
+                .path()
+                .join("lib/python3.12/site-packages/foo/__init__.py"),
+            "# foo",
+        )
+        .unwrap();
+        assert!(check_installed_files(prefix.path(), package.path(), Platform::Linux64).is_ok());
+    }
+
+    #[test]
+    fn leftover_prefix_placeholders_are_detected() {
+        let package = tempfile::tempdir().unwrap();
+        fs::create_dir_all(package.path().join("info")).unwrap();
+        fs::write(
+            package.path().join("info/paths.json"),
+            r#"{
+                "paths": [
+                    { "_path": "bin/foo-config", "path_type": "hardlink", "prefix_placeholder": "/opt/placehold_placehold", "file_mode": "text" },
+                    { "_path": "lib/libfoo.so", "path_type": "hardlink", "prefix_placeholder": "/opt/placehold_placehold", "file_mode": "binary" },
+                    { "_path": "share/foo.txt", "path_type": "hardlink" }
+                ],
+                "paths_version": 1
+            }"#,
+        )
+        .unwrap();
+
+        let prefix = tempfile::tempdir().unwrap();
+        fs::create_dir_all(prefix.path().join("bin")).unwrap();
+        fs::create_dir_all(prefix.path().join("lib")).unwrap();
+        fs::create_dir_all(prefix.path().join("share")).unwrap();
+        fs::write(
+            prefix.path().join("bin/foo-config"),
+            "prefix=/opt/placehold_placehold\n",
+        )
+        .unwrap();
+        fs::write(
+            prefix.path().join("lib/libfoo.so"),
+            b"\x7fELF\0/opt/placehold_placehold\0",
+        )
+        .unwrap();
+        fs::write(
+            prefix.path().join("share/foo.txt"),
+            "/opt/placehold_placehold",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            check_prefix_placeholders(prefix.path(), package.path(), Platform::Linux64),
+            Err(TestError::PrefixNotReplaced(paths)) if paths == vec![
+                PathBuf::from("bin/foo-config"),
+                PathBuf::from("lib/libfoo.so"),
+            ]
+        ));
+        // binary files are not relocated on Windows
+        assert!(matches!(
+            check_prefix_placeholders(prefix.path(), package.path(), Platform::Win64),
+            Err(TestError::PrefixNotReplaced(paths)) if paths == vec![PathBuf::from("bin/foo-config")]
+        ));
+
+        let replaced = format!("prefix={}\n", prefix.path().display());
+        fs::write(prefix.path().join("bin/foo-config"), replaced).unwrap();
+        fs::write(
+            prefix.path().join("lib/libfoo.so"),
+            b"\x7fELF\0/tmp/prefix\0",
+        )
+        .unwrap();
+        assert!(
+            check_prefix_placeholders(prefix.path(), package.path(), Platform::Linux64).is_ok()
+        );
+    }
+
+    #[test]
+    fn files_left_after_the_removal_are_found() {
+        let package = tempfile::tempdir().unwrap();
+        fs::create_dir_all(package.path().join("info")).unwrap();
+        fs::write(
+            package.path().join("info/paths.json"),
+            r#"{
+                "paths": [
+                    { "_path": "bin/foo", "path_type": "hardlink" },
+                    { "_path": "etc/foo.conf", "path_type": "hardlink" },
+                    { "_path": "share/common.txt", "path_type": "hardlink" }
+                ],
+                "paths_version": 1
+            }"#,
+        )
+        .unwrap();
+
+        let prefix = tempfile::tempdir().unwrap();
+        fs::create_dir_all(prefix.path().join("etc")).unwrap();
+        fs::create_dir_all(prefix.path().join("share")).unwrap();
+        fs::write(prefix.path().join("etc/foo.conf"), "").unwrap();
+        fs::write(prefix.path().join("share/common.txt"), "").unwrap();
+
+        // the shared file belongs to another installed package
+        let owned = HashSet::from([PathBuf::from("share/common.txt")]);
+        assert_eq!(
+            uninstall_leftovers(prefix.path(), package.path(), Platform::Linux64, &owned).unwrap(),
+            vec![PathBuf::from("etc/foo.conf")]
+        );
+    }
+
+    #[test]
+    fn python_import_statements() {
+        assert_eq!(
+            import_statements("import foo\n\n# comment\nimport foo.bar, baz\n"),
+            Some(vec![
+                "foo".to_string(),
+                "foo.bar".to_string(),
+                "baz".to_string()
+            ])
+        );
+        assert_eq!(import_statements("import foo\nfoo.run()\n"), None);
+        assert_eq!(import_statements("import foo as f\n"), None);
+    }
+
+    #[test]
+    fn find_python_in_prefix() {
+        let prefix = tempfile::tempdir().unwrap();
+        let platform = Platform::Linux64;
+        let names = ["python", "python3"];
+        assert_eq!(find_in_prefix(prefix.path(), &platform, &names), None);
+
+        fs::create_dir_all(prefix.path().join("bin")).unwrap();
+        fs::write(prefix.path().join("bin/python3"), "").unwrap();
+        assert_eq!(
+            find_in_prefix(prefix.path(), &platform, &names),
+            Some("python3")
+        );
+
+        fs::write(prefix.path().join("bin/python"), "").unwrap();
+        assert_eq!(
+            find_in_prefix(prefix.path(), &platform, &names),
+            Some("python")
+        );
+    }
+
+    #[test]
+    fn interpreter_arguments_precede_the_script() {
+        assert_eq!(interpreter_command("python", &[]), "python");
+        assert_eq!(
+            interpreter_command("python", &["-X".to_string(), "importtime".to_string()]),
+            "python -X importtime"
+        );
+    }
+
+    #[test]
+    fn verify_tar_contents_detects_mismatch() {
+        let contents = b"hello world";
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "bin/hello", &contents[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let hello_sha256 = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let expected = HashMap::from([(PathBuf::from("bin/hello"), hello_sha256.to_string())]);
+        assert!(verify_tar_contents(tar::Archive::new(archive.as_slice()), &expected).is_ok());
+
+        let expected = HashMap::from([(PathBuf::from("bin/hello"), "0".repeat(64))]);
+        assert!(matches!(
+            verify_tar_contents(tar::Archive::new(archive.as_slice()), &expected),
+            Err(TestError::ChecksumMismatch { actual, .. }) if actual == hello_sha256
+        ));
+    }
+
+    #[test]
+    fn files_are_read_from_both_archive_types() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir_all(source.path().join("info")).unwrap();
+        fs::create_dir_all(source.path().join("bin")).unwrap();
+        fs::write(source.path().join("info/index.json"), "{}").unwrap();
+        fs::write(source.path().join("bin/hello"), "hello").unwrap();
+        let paths = vec![
+            source.path().join("info/index.json"),
+            source.path().join("bin/hello"),
+        ];
+
+        let output = tempfile::tempdir().unwrap();
+        let tar_bz2 = output.path().join("hello-1.0-0.tar.bz2");
+        rattler_package_streaming::write::write_tar_bz2_package(
+            fs::File::create(&tar_bz2).unwrap(),
+            source.path(),
+            &paths,
+            rattler_package_streaming::write::CompressionLevel::Default,
+            None,
+        )
+        .unwrap();
+        let conda = output.path().join("hello-1.0-0.conda");
+        rattler_package_streaming::write::write_conda_package(
+            fs::File::create(&conda).unwrap(),
+            source.path(),
+            &paths,
+            rattler_package_streaming::write::CompressionLevel::Default,
+            "hello-1.0-0",
+            None,
+        )
+        .unwrap();
+
+        for archive in [&tar_bz2, &conda] {
+            assert_eq!(
+                read_package_file(archive, Path::new("info/index.json")).unwrap(),
+                b"{}"
+            );
+            assert_eq!(
+                read_package_file(archive, Path::new("bin/hello")).unwrap(),
+                b"hello"
+            );
+            // only read the start of the file
+            let mut start = [0; 2];
+            with_package_file(archive, Path::new("bin/hello"), |reader| {
+                reader.read_exact(&mut start)
+            })
+            .unwrap();
+            assert_eq!(&start, b"he");
+            assert!(matches!(
+                read_package_file(archive, Path::new("bin/missing")),
+                Err(TestError::PackageRead(e)) if e.kind() == std::io::ErrorKind::NotFound
+            ));
+        }
+    }
+
+    #[test]
+    fn entry_point_executables() {
+        let prefix = Path::new("/prefix");
+        assert_eq!(
+            entry_point_executable(prefix, "mytool", Platform::Linux64),
+            prefix.join("bin/mytool")
+        );
+        assert_eq!(
+            entry_point_executable(prefix, "mytool", Platform::Win64),
+            prefix.join("Scripts").join("mytool.exe")
+        );
+    }
+
+    #[test]
+    fn directories_are_copied() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir_all(source.path().join("info/test")).unwrap();
+        fs::write(source.path().join("info/index.json"), "{}").unwrap();
+        fs::write(source.path().join("info/test/run_test.sh"), "true").unwrap();
+
+        let destination = tempfile::tempdir().unwrap();
+        let destination = destination.path().join("pkg");
+        copy_directory(source.path(), &destination).unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("info/test/run_test.sh")).unwrap(),
+            "true"
+        );
+        assert!(destination.join("info/index.json").is_file());
+    }
+
+    #[test]
+    fn repodata_is_current_after_indexing() {
+        let channel = tempfile::tempdir().unwrap();
+        let subdir = channel.path().join("linux-64");
+        fs::create_dir_all(&subdir).unwrap();
+        assert!(repodata_is_current(channel.path()).unwrap());
+
+        fs::write(subdir.join("foo-1.0-0.tar.bz2"), "").unwrap();
+        assert!(!repodata_is_current(channel.path()).unwrap());
+
+        fs::write(subdir.join("repodata.json"), "{}").unwrap();
+        assert!(repodata_is_current(channel.path()).unwrap());
+    }
+
+    #[test]
+    fn cached_extraction_is_reused_if_the_hash_matches() {
+        let cache = tempfile::tempdir().unwrap();
+        let package_folder = cache.path().join("foo-1.0-0");
+        let sha256_file = cache.path().join("foo-1.0-0.sha256");
+        assert!(!reuse_cached_extraction(&package_folder, &sha256_file, "abc").unwrap());
+
+        fs::create_dir_all(package_folder.join("info")).unwrap();
+        fs::write(&sha256_file, "abc\n").unwrap();
+        assert!(reuse_cached_extraction(&package_folder, &sha256_file, "abc").unwrap());
+        assert!(package_folder.exists());
+
+        assert!(!reuse_cached_extraction(&package_folder, &sha256_file, "def").unwrap());
+        assert!(!package_folder.exists());
+        assert!(!sha256_file.exists());
+    }
+
+    #[test]
+    fn run_local_package_cache_replaces_the_cache_dir() {
+        let shared_cache = tempfile::tempdir().unwrap();
+        let config = TestConfiguration {
+            cache_dir: Some(shared_cache.path().to_path_buf()),
+            clear_cached_package: false,
+            ..Default::default()
+        };
+        assert!(TestConfiguration::default().clear_cached_package);
+
+        let mut global_configuration = config.tool_configuration();
+        let package_cache =
+            use_run_local_package_cache(&config, &mut global_configuration).unwrap();
+        assert_eq!(
+            global_configuration.cache_dir().unwrap(),
+            package_cache.path()
+        );
+        assert_ne!(package_cache.path(), shared_cache.path());
+    }
+
+    #[test]
+    fn tests_are_filtered_by_file_name() {
+        let filter = TestFilter::from_str("run_test.py").unwrap();
+        assert_eq!(filter, TestFilter::ByFileName("run_test.py".to_string()));
+        assert_eq!(
+            TestFilter::from_str("imports").unwrap(),
+            TestFilter::ImportsOnly
+        );
+
+        assert!(filter.matches(&Tests::Python(PathBuf::from("info/test/run_test.py"))));
+        assert!(!filter.matches(&Tests::Commands(PathBuf::from("info/test/run_test.sh"))));
+        assert!(!filter.matches(&Tests::PipCheck));
+
+        // the checks of the installed files are not file tests of the package
+        let installed_files = Tests::InstalledFiles(PathBuf::from("pkg"));
+        assert_eq!(installed_files.test_type(), TestType::InstalledFiles);
+        assert!(!TestFilter::FilesOnly.matches(&installed_files));
+        assert!(TestFilter::FilesOnly.matches(&Tests::Files(PathBuf::from("run_test.json"))));
+        let prefix_check = Tests::PrefixCheck(PathBuf::from("pkg"));
+        assert_eq!(prefix_check.test_type(), TestType::PrefixCheck);
+        assert!(!TestFilter::FilesOnly.matches(&prefix_check));
+
+        let error = TestError::NoMatchingTest {
+            name: "run_test.pl".to_string(),
+            available: vec!["run_test.py".to_string(), "run_test.sh".to_string()],
+        };
+        assert_eq!(
+            error.to_string(),
+            "no test named run_test.pl found; available: run_test.py, run_test.sh"
+        );
+    }
+
+    #[test]
+    fn kept_test_channels_are_not_removed() {
+        let channel = create_local_channel(&[], &Platform::Linux64, true, None, false).unwrap();
+        let path = channel.path().to_path_buf();
+        assert!(path.join("linux-64").is_dir());
+        drop(channel);
+        assert!(!path.exists());
+
+        let channel = create_local_channel(&[], &Platform::Linux64, true, None, true).unwrap();
+        let path = channel.path().to_path_buf();
+        drop(channel);
+        assert!(path.join("linux-64").is_dir());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn noarch_generic_packages_are_tested_from_the_noarch_subdir() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir_all(source.path().join("info/test")).unwrap();
+        fs::create_dir_all(source.path().join("fonts")).unwrap();
+        fs::write(
+            source.path().join("info/index.json"),
+            r#"{"name": "myfont", "version": "1.0", "build": "0", "build_number": 0, "subdir": "noarch", "noarch": "generic", "depends": []}"#,
+        )
+        .unwrap();
+        fs::write(
+            source.path().join("info/test/run_test.json"),
+            r#"["fonts/*.ttf"]"#,
+        )
+        .unwrap();
+        fs::write(source.path().join("fonts/myfont.ttf"), "font").unwrap();
+        let paths = vec![
+            source.path().join("info/index.json"),
+            source.path().join("info/test/run_test.json"),
+            source.path().join("fonts/myfont.ttf"),
+        ];
+
+        let output = tempfile::tempdir().unwrap();
+        let package = output.path().join("myfont-1.0-0.conda");
+        rattler_package_streaming::write::write_conda_package(
+            fs::File::create(&package).unwrap(),
+            source.path(),
+            &paths,
+            rattler_package_streaming::write::CompressionLevel::Default,
+            "myfont-1.0-0",
+            None,
+        )
+        .unwrap();
+
+        // the package is indexed in the noarch subdir, next to the (empty) platform subdir
+        let channel =
+            create_local_channel(&[package.as_path()], &Platform::Linux64, false, None, false)
+                .unwrap();
+        let noarch = channel.path().join("noarch");
+        assert!(noarch.join("myfont-1.0-0.conda").exists());
+        assert!(!channel.path().join("linux-64/myfont-1.0-0.conda").exists());
+        let repodata = fs::read_to_string(noarch.join("repodata.json")).unwrap();
+        assert!(repodata.contains("myfont-1.0-0.conda"));
+
+        // the files test resolves the paths in the prefix of the host
+        let prefix = tempfile::tempdir().unwrap();
+        extract_package(&package, prefix.path()).unwrap();
+        let test = Tests::Files(prefix.path().join("info/test/run_test.json"));
+        let (_, result) = test.run_attempt(
+            prefix.path(),
+            prefix.path(),
+            &TestConfiguration::default(),
+            &HashMap::new(),
+            None,
+        );
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[test]
+    fn corrupt_packages_fail_the_indexing() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("corrupt-1.0-0.tar.bz2");
+        fs::write(&package, "not a package").unwrap();
+
+        let error =
+            create_local_channel(&[package.as_path()], &Platform::Linux64, false, None, false)
+                .unwrap_err();
+        assert!(matches!(
+            &error,
+            TestError::IndexingFailed { platform, .. } if *platform == Platform::Linux64
+        ));
+        let message = error.to_string();
+        assert!(message.contains("linux-64"), "{message}");
+        assert!(message.contains("corrupt-1.0-0.tar.bz2"), "{message}");
+    }
+
+    #[test]
+    fn configuration_is_validated() {
+        let dir = tempfile::tempdir().unwrap();
+        let valid = TestConfiguration {
+            test_prefix: dir.path().join("prefix"),
+            ..Default::default()
+        };
+        valid.validate().unwrap();
+
+        let invalid = [
+            TestConfiguration::default(),
+            TestConfiguration {
+                test_prefix: dir.path().join("missing/prefix"),
+                ..Default::default()
+            },
+            TestConfiguration {
+                include_local_channel: false,
+                ..valid.clone()
+            },
+            TestConfiguration {
+                timeout: Some(std::time::Duration::ZERO),
+                ..valid.clone()
+            },
+        ];
+        for config in invalid {
+            assert!(matches!(
+                config.validate(),
+                Err(TestError::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_channels_order() {
+        let package_channels = vec!["conda-forge".to_string(), "internal".to_string()];
+        let mut config = TestConfiguration {
+            channels: vec!["conda-forge".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            config.test_channels(Path::new("/tmp/local"), &package_channels),
+            vec!["/tmp/local".to_string(), "conda-forge".to_string()]
+        );
+
+        config.include_local_channel = false;
+        config.include_package_channels = true;
+        assert_eq!(
+            config.test_channels(Path::new("/tmp/local"), &package_channels),
+            vec!["conda-forge".to_string(), "internal".to_string()]
+        );
+    }
+
+    #[test]
+    fn line_endings_are_normalized() {
+        use rattler_shell::shell::{Bash, CmdExe, ShellEnum};
+
+        let script = "echo hello\r\necho world\n";
+        assert_eq!(
+            normalize_line_endings(script, &ShellEnum::Bash(Bash)),
+            "echo hello\necho world\n"
+        );
+        assert_eq!(
+            normalize_line_endings(script, &ShellEnum::CmdExe(CmdExe)),
+            "echo hello\r\necho world\r\n"
+        );
+    }
+
+    #[test]
+    fn temporary_files_are_created_in_the_temp_dir() {
+        use rattler_shell::{activation::PathModificationBehavior, shell::ShellEnum};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let nested = temp_dir.path().join("nested");
+        let dir = create_temp_dir(Some(&nested)).unwrap();
+        assert!(dir.path().starts_with(&nested));
+
+        let prefix = tempfile::tempdir().unwrap();
+        let (_command, script) = environment_command(
+            ShellEnum::default(),
+            ShellInvocation::default(),
+            "echo hello",
+            prefix.path(),
+            prefix.path(),
+            Platform::current(),
+            ActivationMode::Minimal,
+            PathModificationBehavior::default(),
+            &HashMap::new(),
+            Some(&nested),
+        )
+        .unwrap();
+        assert!(script.starts_with(&nested));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_on_stderr_fails_the_test_in_strict_mode() {
+        let prefix = tempfile::tempdir().unwrap();
+        let script = prefix.path().join("run_test.sh");
+        fs::write(&script, "echo deprecated >&2\n").unwrap();
+        let test = Tests::Commands(script);
+
+        let mut config = TestConfiguration::default();
+        let run = |config: &TestConfiguration| {
+            test.run_attempt(prefix.path(), prefix.path(), config, &HashMap::new(), None)
+                .1
+        };
+        assert!(run(&config).is_ok());
+
+        config.fail_on_stderr = true;
+        assert!(matches!(
+            run(&config),
+            Err(TestError::UnexpectedStderr { stderr_tail, .. }) if stderr_tail == "deprecated"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn compressed_logs_are_read_back() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let output = std::process::Output {
+            status: std::process::ExitStatus::from_raw(0),
+            stdout: b"hello\n".to_vec(),
+            stderr: b"warning\n".to_vec(),
+        };
+        let log = write_log_file(
+            &dir.path().join("run_test.sh.log"),
+            "echo hello",
+            &output,
+            true,
+        )
+        .unwrap();
+        assert_eq!(log.path, dir.path().join("run_test.sh.log.gz"));
+        assert!(log.is_compressed());
+        assert_eq!(log.size, fs::metadata(&log.path).unwrap().len());
+        let contents = log.read().unwrap();
+        assert!(contents.starts_with("# command\necho hello\n"));
+        assert!(contents.contains("# stdout\nhello\n"));
+        assert!(contents.ends_with("# stderr\nwarning\n"));
+
+        // only the log is kept in the outcome
+        let outcome = TestCaseOutcome::new(
+            "run_test.sh".to_string(),
+            TestType::Commands,
+            TestStatus::Passed,
+            std::time::Duration::ZERO,
+            Some(CommandOutput {
+                stdout: "hello\n".to_string(),
+                log: Some(log.clone()),
+                ..Default::default()
+            }),
+        );
+        assert!(outcome.stdout.is_empty());
+        assert_eq!(outcome.log, Some(log));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn minimal_activation_is_reported_separately() {
+        let prefix = tempfile::tempdir().unwrap();
+        let activate_d = prefix.path().join("etc/conda/activate.d");
+        fs::create_dir_all(&activate_d).unwrap();
+        fs::write(activate_d.join("mypkg.sh"), "export MYPKG_DATA=1\n").unwrap();
+        let script = prefix.path().join("run_test.sh");
+        fs::write(&script, "test -n \"$MYPKG_DATA\"\n").unwrap();
+
+        let tests =
+            with_minimal_activation_runs(vec![Tests::Commands(script.clone()), Tests::PipCheck]);
+        let names = tests.iter().map(Tests::name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "run_test.sh",
+                "run_test.sh (minimal activation)",
+                "pip_check"
+            ]
+        );
+        assert_eq!(tests[1].options_name(), "run_test.sh");
+        assert_eq!(tests[1].test_type(), TestType::Commands);
+
+        let config = TestConfiguration::default();
+        let log_dir = tempfile::tempdir().unwrap();
+        let run = |test: &Tests| {
+            test.run_attempt(
+                prefix.path(),
+                prefix.path(),
+                &config,
+                &HashMap::new(),
+                Some(log_dir.path()),
+            )
+            .1
+        };
+        assert!(run(&tests[0]).is_ok());
+        assert!(matches!(
+            run(&tests[1]),
+            Err(TestError::TestFailed { test, .. }) if test == "run_test.sh (minimal activation)"
+        ));
+        assert!(log_dir.path().join("run_test.sh-minimal.log").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn missing_shell_is_not_a_failing_test() {
+        let prefix = tempfile::tempdir().unwrap();
+        let script = prefix.path().join("run_test.sh");
+        fs::write(&script, "true\n").unwrap();
+        let test = Tests::Commands(script);
+
+        let shell = prefix.path().join("no-such-bash");
+        let config = TestConfiguration {
+            shell_override: Some(shell.clone()),
+            ..Default::default()
+        };
+        let (_, result) =
+            test.run_attempt(prefix.path(), prefix.path(), &config, &HashMap::new(), None);
+        assert!(matches!(
+            result,
+            Err(TestError::ShellSpawnFailed { executable, source })
+                if executable == shell && source.kind() == std::io::ErrorKind::NotFound
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn activation_scripts_of_packages_are_sourced() {
+        use rattler_shell::{
+            activation::PathModificationBehavior,
+            shell::{Bash, ShellEnum},
+        };
+
+        let prefix = tempfile::tempdir().unwrap();
+        let activate_d = prefix.path().join("etc/conda/activate.d");
+        fs::create_dir_all(&activate_d).unwrap();
+        fs::write(
+            activate_d.join("mypkg.sh"),
+            "export MYPKG_DATA=\"$CONDA_PREFIX/share/mypkg\"\n",
+        )
+        .unwrap();
+
+        let (mut command, _script) = environment_command(
+            ShellEnum::Bash(Bash),
+            ShellInvocation::default(),
+            "echo \"$MYPKG_DATA\"",
+            prefix.path(),
+            prefix.path(),
+            Platform::current(),
+            ActivationMode::Full,
+            PathModificationBehavior::default(),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            prefix.path().join("share/mypkg").to_string_lossy()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn conda_prefix_is_set_without_full_activation() {
+        use rattler_shell::{
+            activation::PathModificationBehavior,
+            shell::{Bash, ShellEnum},
+        };
+
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        let (mut command, _script) = environment_command(
+            ShellEnum::Bash(Bash),
+            ShellInvocation::default(),
+            "echo \"$CONDA_PREFIX|$CONDA_DEFAULT_ENV\"",
+            prefix,
+            prefix,
+            Platform::current(),
+            ActivationMode::Minimal,
+            PathModificationBehavior::default(),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            format!(
+                "{}|{}",
+                prefix.display(),
+                prefix.file_name().unwrap().to_string_lossy()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crlf_script_runs_in_bash() {
+        use rattler_shell::{
+            activation::PathModificationBehavior,
+            shell::{Bash, ShellEnum},
+        };
+
+        let prefix = tempfile::tempdir().unwrap();
+        let (mut command, _script) = environment_command(
+            ShellEnum::Bash(Bash),
+            ShellInvocation::default(),
+            "echo hello\r\nexit 0\r\n",
+            prefix.path(),
+            prefix.path(),
+            Platform::current(),
+            ActivationMode::Minimal,
+            PathModificationBehavior::default(),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_args_are_passed_to_bash() {
+        use rattler_shell::{
+            activation::PathModificationBehavior,
+            shell::{Bash, ShellEnum},
+        };
+
+        let prefix = tempfile::tempdir().unwrap();
+        let args = ["--noprofile".to_string(), "--norc".to_string()];
+        let (mut command, script) = environment_command(
+            ShellEnum::Bash(Bash),
+            ShellInvocation {
+                args: &args,
+                ..Default::default()
+            },
+            "echo hello",
+            prefix.path(),
+            prefix.path(),
+            Platform::current(),
+            ActivationMode::Minimal,
+            PathModificationBehavior::default(),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec![
+                std::ffi::OsStr::new("--noprofile"),
+                std::ffi::OsStr::new("--norc"),
+                script.as_os_str()
+            ]
+        );
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+    }
+
+    #[test]
+    fn login_shell_option_follows_the_shell_args() {
+        use rattler_shell::{
+            activation::PathModificationBehavior,
+            shell::{Bash, ShellEnum},
+        };
+
+        let prefix = tempfile::tempdir().unwrap();
+        let args = ["--noprofile".to_string()];
+        let (command, script) = environment_command(
+            ShellEnum::Bash(Bash),
+            ShellInvocation {
+                args: &args,
+                login: true,
+                ..Default::default()
+            },
+            "echo hello",
+            prefix.path(),
+            prefix.path(),
+            Platform::current(),
+            ActivationMode::Minimal,
+            PathModificationBehavior::default(),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec![
+                std::ffi::OsStr::new("--noprofile"),
+                std::ffi::OsStr::new("-l"),
+                script.as_os_str()
+            ]
+        );
+    }
+
+    #[test]
+    fn outer_conda_environments_are_removed_from_path() {
+        let path = vec![
+            PathBuf::from("/opt/conda/envs/outer/bin"),
+            PathBuf::from("/usr/bin"),
+            PathBuf::from("/opt/conda/bin"),
+            PathBuf::from("/opt/conda/condabin"),
+        ];
+        let prefixes = [
+            PathBuf::from("/opt/conda/envs/outer"),
+            PathBuf::from("/opt/conda"),
+        ];
+        assert_eq!(
+            strip_conda_paths(path, &prefixes, &Platform::Linux64),
+            vec![
+                PathBuf::from("/usr/bin"),
+                PathBuf::from("/opt/conda/condabin")
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_prefix_comes_first_in_path() {
+        use rattler_shell::{
+            activation::PathModificationBehavior,
+            shell::{Bash, ShellEnum},
+        };
+
+        let prefix = tempfile::tempdir().unwrap();
+        let (mut command, _script) = environment_command(
+            ShellEnum::Bash(Bash),
+            ShellInvocation::default(),
+            "echo \"$PATH\"",
+            prefix.path(),
+            prefix.path(),
+            Platform::current(),
+            ActivationMode::Full,
+            PathModificationBehavior::default(),
+            &HashMap::new(),
+            None,
+        )
+        .unwrap();
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let first = std::env::split_paths(stdout.trim()).next().unwrap();
+        assert_eq!(first, prefix.path().join("bin"));
+    }
+
+    #[test]
+    fn finished_tests_are_reported_once() {
+        #[derive(Debug, Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl TestObserver for Recorder {
+            fn on_test_finish(&self, outcome: &TestCaseOutcome) {
+                self.0.lock().unwrap().push(outcome.name.clone());
+            }
+        }
+
+        let recorder = std::sync::Arc::new(Recorder::default());
+        let config = TestConfiguration {
+            observer: Some(recorder.clone()),
+            environment_mode: TestEnvironmentMode::Minimal,
+            ..Default::default()
+        };
+        let outcome = |name: &str| {
+            TestCaseOutcome::new(
+                name.to_string(),
+                TestType::Commands,
+                TestStatus::Passed,
+                std::time::Duration::ZERO,
+                None,
+            )
+        };
+
+        let mut outcomes = vec![outcome("run_test.sh")];
+        let reported = report_finished_tests(&config, &mut outcomes, 0);
+        outcomes.push(outcome("run_test.py"));
+        let reported = report_finished_tests(&config, &mut outcomes, reported);
+        assert_eq!(report_finished_tests(&config, &mut outcomes, reported), 2);
+
+        assert_eq!(*recorder.0.lock().unwrap(), ["run_test.sh", "run_test.py"]);
+        assert!(outcomes
+            .iter()
+            .all(|outcome| outcome.environment_mode == TestEnvironmentMode::Minimal));
+    }
+
+    #[test]
+    fn no_deps_runs_are_labeled() {
+        let mut outcomes = vec![TestCaseOutcome::new(
+            "run_test.sh".to_string(),
+            TestType::Commands,
+            TestStatus::Passed,
+            std::time::Duration::ZERO,
+            None,
+        )];
+        let config = TestConfiguration {
+            environment_mode: TestEnvironmentMode::NoDeps,
+            ..Default::default()
+        };
+        report_finished_tests(&config, &mut outcomes, 0);
+        assert_eq!(
+            serde_json::to_value(outcomes[0].environment_mode).unwrap(),
+            "no_deps"
+        );
+    }
+
+    #[test]
+    fn inconsistent_metadata_is_reported() {
+        let package = tempfile::tempdir().unwrap();
+        fs::create_dir_all(package.path().join("info")).unwrap();
+        let write_index = |version: &str, depends: &str| {
+            fs::write(
+                package.path().join("info/index.json"),
+                format!(
+                    r#"{{"name": "foo", "version": "{}", "build": "h123_0", "build_number": 0, "depends": [{}], "constrains": ["bar >=2"]}}"#,
+                    version, depends
+                ),
+            )
+            .unwrap();
+        };
+        let pkg = ArchiveIdentifier::try_from_filename("foo-1.0-h123_0.conda").unwrap();
+
+        write_index("1.0", r#""python >=3.8""#);
+        assert!(check_metadata(package.path(), &pkg).is_ok());
+
+        write_index("1.1", r#""python >=3.8""#);
+        assert!(matches!(
+            check_metadata(package.path(), &pkg),
+            Err(TestError::InconsistentMetadata { package, reason })
+                if package == "foo-1.0-h123_0" && reason.contains("'1.1'")
+        ));
+
+        write_index("1.0", r#""python >=3.8", "numpy >=>1""#);
+        assert!(matches!(
+            check_metadata(package.path(), &pkg),
+            Err(TestError::InconsistentMetadata { reason, .. })
+                if reason.contains("depends entry 'numpy >=>1'")
+        ));
+
+        let other = ArchiveIdentifier::try_from_filename("foo-1.0-h456_0.conda").unwrap();
+        write_index("1.0", r#""python >=3.8""#);
+        assert!(matches!(
+            check_metadata(package.path(), &other),
+            Err(TestError::InconsistentMetadata { reason, .. }) if reason.contains("build string")
+        ));
+    }
+
+    #[test]
+    fn shadowing_entry_points_are_a_warning() {
+        let package = tempfile::tempdir().unwrap();
+        fs::create_dir_all(package.path().join("info")).unwrap();
+        let outcome = entry_point_shadowing_outcome("foo", package.path()).unwrap();
+        assert!(matches!(outcome.status, TestStatus::Passed));
+
+        fs::write(
+            package.path().join("info/link.json"),
+            r#"{
+                "noarch": {
+                    "type": "python",
+                    "entry_points": ["test = foo.cli:test", "foo = foo.cli:main", "make = foo.cli:make", "foo-tool = foo:main"]
+                },
+                "package_metadata_version": 1
+            }"#,
+        )
+        .unwrap();
+        let entry_points = entry_points_from_folder(package.path()).unwrap();
+        assert_eq!(
+            check_entry_point_shadowing("foo", &entry_points),
+            ["test", "make"]
+        );
+        // the entry point that is named like the package is intentional
+        assert_eq!(check_entry_point_shadowing("make", &entry_points), ["test"]);
+
+        let outcome = entry_point_shadowing_outcome("foo", package.path()).unwrap();
+        assert_eq!(outcome.name, "entry_point_shadowing");
+        assert!(matches!(
+            outcome.status,
+            TestStatus::Warning(warning) if warning.ends_with("test, make")
+        ));
+    }
+
+    #[tokio::test]
+    async fn package_directories_are_tested_like_archives() {
+        let pkg_dir = tempfile::tempdir().unwrap();
+        // the configuration is validated before the package is read
+        assert!(matches!(
+            run_test_from_dir(pkg_dir.path(), &TestConfiguration::default()).await,
+            Err(TestError::InvalidConfiguration(_))
+        ));
+
+        let test_folder = pkg_dir.path().join("info/test");
+        fs::create_dir_all(&test_folder).unwrap();
+        fs::write(test_folder.join("run_test.py"), "import foo").unwrap();
+        let config = TestConfiguration {
+            test_type_dependencies: HashMap::from([
+                (
+                    TestType::Python,
+                    vec![MatchSpec::from_str("pytest").unwrap()],
+                ),
+                (
+                    TestType::Commands,
+                    vec![MatchSpec::from_str("bash").unwrap()],
+                ),
+            ]),
+            ..Default::default()
+        };
+        let specs = test_type_specs(&config, &TestManifest::default(), || {
+            folder_test_files(&test_folder)
+        })
+        .unwrap();
+        assert_eq!(
+            specs.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["pytest"]
+        );
+
+        // the checkpoint identifies the directory by its files
+        let digest = directory_digest(pkg_dir.path()).unwrap();
+        assert_eq!(directory_digest(pkg_dir.path()).unwrap(), digest);
+        fs::write(test_folder.join("run_test.py"), "import bar").unwrap();
+        assert_ne!(directory_digest(pkg_dir.path()).unwrap(), digest);
+    }
+
+    #[tokio::test]
+    async fn packages_are_found_in_the_subdir_of_the_target_platform() {
+        // a subdir that is not loaded by default on this host
+        let platform = if Platform::current() == Platform::LinuxAarch64 {
+            Platform::Linux64
+        } else {
+            Platform::LinuxAarch64
+        };
+        let channel = tempfile::tempdir().unwrap();
+        for (subdir, packages) in [
+            (
+                platform.to_string(),
+                format!(
+                    r#"{{"foo-1.0-h0_0.tar.bz2": {{"name": "foo", "version": "1.0", "build": "h0_0", "build_number": 0, "depends": [], "subdir": "{}"}}}}"#,
+                    platform
+                ),
+            ),
+            ("noarch".to_string(), "{}".to_string()),
+        ] {
+            fs::create_dir_all(channel.path().join(&subdir)).unwrap();
+            fs::write(
+                channel.path().join(&subdir).join("repodata.json"),
+                format!(
+                    r#"{{"info": {{"subdir": "{}"}}, "packages": {}, "packages.conda": {{}}}}"#,
+                    subdir, packages
+                ),
+            )
+            .unwrap();
+        }
+
+        let cache = tempfile::tempdir().unwrap();
+        let config = TestConfiguration {
+            target_platform: Some(platform),
+            channels: vec![channel.path().to_string_lossy().to_string()],
+            cache_dir: Some(cache.path().to_path_buf()),
+            ..Default::default()
+        };
+        let spec = MatchSpec::from_str("foo").unwrap();
+        let records = matching_channel_records(&spec, &config, &config.tool_configuration())
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].package_record.subdir, platform.to_string());
+    }
+
+    #[test]
+    fn foreign_architectures_cannot_run_on_the_host() {
+        assert!(can_run_on(Platform::Linux64, Platform::Linux64));
+        assert!(!can_run_on(Platform::LinuxAarch64, Platform::Linux64));
+        assert!(!can_run_on(Platform::Linux64, Platform::LinuxAarch64));
+        assert!(!can_run_on(Platform::Win64, Platform::Linux64));
+        // the emulations that come with the operating system
+        assert!(can_run_on(Platform::Osx64, Platform::OsxArm64));
+        assert!(!can_run_on(Platform::OsxArm64, Platform::Osx64));
+        assert!(can_run_on(Platform::Win32, Platform::Win64));
+        assert!(can_run_on(Platform::Win64, Platform::WinArm64));
+        assert!(!can_run_on(Platform::Linux32, Platform::Linux64));
+    }
+
+    /// The peak resident memory of the process since the last [`reset_peak_memory`], in kB
+    #[cfg(target_os = "linux")]
+    fn peak_memory() -> u64 {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmHWM:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            .unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn reset_peak_memory() {
+        fs::write("/proc/self/clear_refs", "5").unwrap();
+    }
+
+    /// Compares the peak memory of hashing a large file of a package after reading it into
+    /// memory ([`read_package_file`]) with streaming it ([`with_package_file`]).
+    ///
+    /// `RATTLER_BUILD_BENCH_FILE_MB=512 cargo test --release package_file_memory_benchmark -- --ignored --nocapture`
+    #[cfg(target_os = "linux")]
+    #[ignore]
+    #[test]
+    fn package_file_memory_benchmark() {
+        use std::{io::Write, time::Instant};
+
+        use sha2::Digest;
+
+        // the measurements are logged, print them to the captured output of the test
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let size_mb = std::env::var("RATTLER_BUILD_BENCH_FILE_MB")
+            .ok()
+            .and_then(|size| size.parse::<usize>().ok())
+            .unwrap_or(256);
+
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir_all(source.path().join("info")).unwrap();
+        fs::create_dir_all(source.path().join("lib")).unwrap();
+        fs::write(source.path().join("info/index.json"), "{}").unwrap();
+        let mut payload = fs::File::create(source.path().join("lib/payload.bin")).unwrap();
+        let chunk = (0..1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        for _ in 0..size_mb {
+            payload.write_all(&chunk).unwrap();
+        }
+        drop(payload);
+        let paths = vec![
+            source.path().join("info/index.json"),
+            source.path().join("lib/payload.bin"),
+        ];
+        let output = tempfile::tempdir().unwrap();
+        let archive = output.path().join("payload-1.0-0.conda");
+        rattler_package_streaming::write::write_conda_package(
+            fs::File::create(&archive).unwrap(),
+            source.path(),
+            &paths,
+            rattler_package_streaming::write::CompressionLevel::Default,
+            "payload-1.0-0",
+            None,
+        )
+        .unwrap();
+        let inner = Path::new("lib/payload.bin");
+
+        reset_peak_memory();
+        let baseline = peak_memory();
+        let started = Instant::now();
+        let contents = read_package_file(&archive, inner).unwrap();
+        let full = format!("{:x}", sha2::Sha256::digest(&contents));
+        drop(contents);
+        tracing::info!(
+            "full read of {} MB: peak {} kB above the baseline in {:?}",
+            size_mb,
+            peak_memory().saturating_sub(baseline),
+            started.elapsed()
+        );
+
+        reset_peak_memory();
+        let baseline = peak_memory();
+        let started = Instant::now();
+        let streamed = with_package_file(&archive, inner, |reader| {
+            let mut hasher = sha2::Sha256::new();
+            std::io::copy(reader, &mut hasher)?;
+            Ok(format!("{:x}", hasher.finalize()))
+        })
+        .unwrap();
+        tracing::info!(
+            "streamed read of {} MB: peak {} kB above the baseline in {:?}",
+            size_mb,
+            peak_memory().saturating_sub(baseline),
+            started.elapsed()
+        );
+        assert_eq!(full, streamed);
+    }
+
+    #[test]
+    fn package_contents_are_only_verified_on_request() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir_all(source.path().join("info")).unwrap();
+        fs::create_dir_all(source.path().join("bin")).unwrap();
+        fs::write(source.path().join("bin/hello"), "hello").unwrap();
+        fs::write(
+            source.path().join("info/paths.json"),
+            format!(
+                r#"{{"paths": [{{"_path": "bin/hello", "path_type": "hardlink", "sha256": "{}", "size_in_bytes": 5}}], "paths_version": 1}}"#,
+                "0".repeat(64)
+            ),
+        )
+        .unwrap();
+        let paths = vec![
+            source.path().join("info/paths.json"),
+            source.path().join("bin/hello"),
+        ];
+        let output = tempfile::tempdir().unwrap();
+        let archive = output.path().join("hello-1.0-0.tar.bz2");
+        rattler_package_streaming::write::write_tar_bz2_package(
+            fs::File::create(&archive).unwrap(),
+            source.path(),
+            &paths,
+            rattler_package_streaming::write::CompressionLevel::Default,
+            None,
+        )
+        .unwrap();
+
+        let mut config = TestConfiguration::default();
+        assert!(verify_package_checksum(&archive, ArchiveType::TarBz2, "", &config).is_ok());
+        config.verify_package_contents = true;
+        assert!(matches!(
+            verify_package_checksum(&archive, ArchiveType::TarBz2, "", &config),
+            Err(TestError::ChecksumMismatch { .. })
+        ));
+    }
 }
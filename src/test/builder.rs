@@ -0,0 +1,232 @@
+//! A builder for [`TestConfiguration`]
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rattler_build::test::TestConfiguration;
+//! let config = TestConfiguration::builder()
+//!     .test_prefix("/tmp/test-prefix")
+//!     .channel("conda-forge")
+//!     .keep_prefix(true)
+//!     .timeout(Duration::from_secs(600))
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! The builder only covers the commonly used options, all other options can be set on the
+//! fields of the built configuration.
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use indicatif::MultiProgress;
+use rattler_conda_types::{MatchSpec, Platform};
+use rattler_networking::AuthenticatedClient;
+
+use super::{
+    ChannelPriority, SolverBackend, TestBackend, TestConfiguration, TestEnvironmentMode, TestError,
+    TestFilter, TestObserver, TestRunner,
+};
+
+/// Builds a [`TestConfiguration`] with chainable setters. Options that are not set keep the
+/// defaults of [`TestConfiguration::default`].
+#[derive(Debug, Default)]
+pub struct TestConfigurationBuilder {
+    config: TestConfiguration,
+}
+
+impl TestConfiguration {
+    /// A builder for the configuration, see [`TestConfigurationBuilder`]
+    pub fn builder() -> TestConfigurationBuilder {
+        TestConfigurationBuilder::default()
+    }
+}
+
+impl TestConfigurationBuilder {
+    /// The directory in which the test environment is created (required)
+    pub fn test_prefix(mut self, test_prefix: impl Into<PathBuf>) -> Self {
+        self.config.test_prefix = test_prefix.into();
+        self
+    }
+
+    /// The platform for which the package is tested
+    pub fn target_platform(mut self, target_platform: Platform) -> Self {
+        self.config.target_platform = Some(target_platform);
+        self
+    }
+
+    /// Keep the test prefix after the tests (see [`TestConfiguration::keep_test_prefix`])
+    pub fn keep_prefix(mut self, keep: bool) -> Self {
+        self.config.keep_test_prefix = keep;
+        self
+    }
+
+    /// Add a channel that is used to create the test environment
+    pub fn channel(mut self, channel: impl Into<String>) -> Self {
+        self.config.channels.push(channel.into());
+        self
+    }
+
+    /// Add channels that are used to create the test environment
+    pub fn channels(mut self, channels: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.config
+            .channels
+            .extend(channels.into_iter().map(Into::into));
+        self
+    }
+
+    /// Whether the local channel with the tested package is used to create the test environment
+    pub fn include_local_channel(mut self, include: bool) -> Self {
+        self.config.include_local_channel = include;
+        self
+    }
+
+    /// How the channels are prioritized when solving the test environment
+    pub fn channel_priority(mut self, channel_priority: ChannelPriority) -> Self {
+        self.config.channel_priority = channel_priority;
+        self
+    }
+
+    /// The solver that is used to solve the test environment
+    pub fn solver(mut self, solver: SolverBackend) -> Self {
+        self.config.solver = solver;
+        self
+    }
+
+    /// The time after which a test command is killed
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// The time limit for all tests of a package
+    pub fn total_timeout(mut self, total_timeout: Duration) -> Self {
+        self.config.total_timeout = Some(total_timeout);
+        self
+    }
+
+    /// How often a failing test is retried
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.config.retries = retries;
+        self
+    }
+
+    /// Stop after the first failing test
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.config.fail_fast = fail_fast;
+        self
+    }
+
+    /// Do not access the network (see [`TestConfiguration::offline`])
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.config.offline = offline;
+        self
+    }
+
+    /// The HTTP client that is used to download the repodata and the packages
+    pub fn client(mut self, client: AuthenticatedClient) -> Self {
+        self.config.client = Some(client);
+        self
+    }
+
+    /// The cache directory (for the repodata and the packages)
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.config.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Write the output of every test into a log file in this directory
+    pub fn log_dir(mut self, log_dir: impl Into<PathBuf>) -> Self {
+        self.config.log_dir = Some(log_dir.into());
+        self
+    }
+
+    /// Add a dependency to the test environment
+    pub fn extra_dependency(mut self, spec: MatchSpec) -> Self {
+        self.config.extra_dependencies.push(spec);
+        self
+    }
+
+    /// Set an environment variable for the test commands
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.extra_env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Select which of the tests of a package are run
+    pub fn test_filter(mut self, test_filter: TestFilter) -> Self {
+        self.config.test_filter = test_filter;
+        self
+    }
+
+    /// Where the test commands are executed
+    pub fn backend(mut self, backend: TestBackend) -> Self {
+        self.config.backend = backend;
+        self
+    }
+
+    /// Whether the test environment contains the test dependencies
+    pub fn environment_mode(mut self, environment_mode: TestEnvironmentMode) -> Self {
+        self.config.environment_mode = environment_mode;
+        self
+    }
+
+    /// Add the progress bars of the tests to this instead of a new one (e.g. to render them in
+    /// the layout of an application that embeds the tests)
+    pub fn multi_progress(mut self, multi_progress: MultiProgress) -> Self {
+        self.config.multi_progress = Some(multi_progress);
+        self
+    }
+
+    /// Register a custom test (see [`TestConfiguration::register_runner`])
+    pub fn runner(mut self, runner: Box<dyn TestRunner>) -> Self {
+        self.config.register_runner(runner);
+        self
+    }
+
+    /// Notify the observer about the progress of the tests (see [`TestConfiguration::observer`])
+    pub fn observer(mut self, observer: Arc<dyn TestObserver>) -> Self {
+        self.config.observer = Some(observer);
+        self
+    }
+
+    /// Validate the options (see [`TestConfiguration::validate`]) and return the configuration
+    pub fn build(self) -> Result<TestConfiguration, TestError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configuration_is_built() {
+        let config = TestConfiguration::builder()
+            .test_prefix("/tmp/prefix")
+            .channel("conda-forge")
+            .channels(["bioconda"])
+            .keep_prefix(true)
+            .timeout(Duration::from_secs(60))
+            .env("FOO", "bar")
+            .build()
+            .unwrap();
+        assert_eq!(config.test_prefix, PathBuf::from("/tmp/prefix"));
+        assert_eq!(config.channels, ["conda-forge", "bioconda"]);
+        assert!(config.keep_test_prefix);
+        assert_eq!(config.timeout, Some(Duration::from_secs(60)));
+        assert_eq!(config.extra_env["FOO"], "bar");
+        assert!(config.include_local_channel);
+
+        assert!(matches!(
+            TestConfiguration::builder().channel("conda-forge").build(),
+            Err(TestError::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            TestConfiguration::builder()
+                .test_prefix("/tmp/prefix")
+                .include_local_channel(false)
+                .build(),
+            Err(TestError::InvalidConfiguration(_))
+        ));
+    }
+}
@@ -0,0 +1,659 @@
+//! Persist which packages have already been tested, so that an interrupted run over many
+//! packages can be resumed.
+//!
+//! Packages are identified by the SHA256 hash of the archive. The checkpoint also stores a
+//! fingerprint of the test configuration and is discarded if the configuration changes.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use rattler_conda_types::{package::ArchiveIdentifier, MatchSpec, Platform, Version};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    CommandOutput, SkipReason, TestCaseOutcome, TestConfiguration, TestEnvironmentMode, TestError,
+    TestResult, TestStatus, TestType,
+};
+
+/// The recorded status of a single test
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum CheckpointStatus {
+    Passed,
+    Failed { message: String },
+    Skipped { reason: String },
+    Warning { message: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointOutcome {
+    name: String,
+    test_type: TestType,
+    #[serde(flatten)]
+    status: CheckpointStatus,
+    duration_ms: u64,
+    exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    python_version: Option<String>,
+    #[serde(default)]
+    environment_mode: TestEnvironmentMode,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointEntry {
+    file_name: String,
+    outcomes: Vec<CheckpointOutcome>,
+}
+
+/// The packages that were tested with a given test configuration
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    config_fingerprint: String,
+    packages: BTreeMap<String, CheckpointEntry>,
+}
+
+/// The options of the configuration that influence the test outcomes. Options without a
+/// `Serialize` implementation are recorded with their `Debug` representation.
+#[derive(Serialize)]
+struct OutcomeOptions<'a> {
+    target_platform: Option<Platform>,
+    channels: &'a [String],
+    include_local_channel: bool,
+    include_package_channels: bool,
+    channel_priority: String,
+    solver: String,
+    test_minimal_activation: bool,
+    skip_activation: bool,
+    check_entry_point_shadowing: bool,
+    fail_fast: bool,
+    read_only_prefix: bool,
+    verify_side_effects: bool,
+    detect_side_effects: bool,
+    side_effect_dirs: &'a [PathBuf],
+    fail_on_side_effects: bool,
+    test_type_dependencies: BTreeMap<TestType, Vec<String>>,
+    offline: bool,
+    timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    extra_env: BTreeMap<&'a str, &'a str>,
+    expected_sha256: Option<&'a str>,
+    expected_md5: Option<&'a str>,
+    skip_checksum_verification: bool,
+    verify_package_contents: bool,
+    retries: u32,
+    allowed_exit_codes: &'a [i32],
+    virtual_packages: Vec<String>,
+    reuse_prefix: bool,
+    augment_prefix: bool,
+    path_modification: String,
+    test_filter: String,
+    runners: Vec<String>,
+    pip_check: bool,
+    linkage_check: bool,
+    linkage_allowlist: &'a [String],
+    entry_point_check: bool,
+    menuinst_check: bool,
+    prefix_check: bool,
+    metadata_check: bool,
+    uninstall_check: bool,
+    shell_override: Option<&'a Path>,
+    shell_args: &'a [String],
+    replace_default_shell_args: bool,
+    login_shell: bool,
+    fail_on_unrunnable_tests: bool,
+    dry_run: bool,
+    skip_environment_without_tests: bool,
+    python_versions: Vec<String>,
+    test_cwd: Option<&'a Path>,
+    verify_installed_files: bool,
+    extra_dependencies: Vec<String>,
+    include_run_exports: bool,
+    backend: String,
+    pre_test_script: Option<&'a str>,
+    post_test_script: Option<&'a str>,
+    test_include: &'a [String],
+    test_exclude: &'a [String],
+    check_dependencies: bool,
+    environment_mode: TestEnvironmentMode,
+    fail_on_stderr: bool,
+    repodata_patches: Option<&'a Path>,
+    verify_signature: Option<String>,
+    python_args: &'a [String],
+    interpreter_args: BTreeMap<&'a str, &'a [String]>,
+    fail_on_undefined_variables: bool,
+    lockfile: Option<&'a Path>,
+}
+
+impl<'a> OutcomeOptions<'a> {
+    fn new(config: &'a TestConfiguration) -> Self {
+        // every option is listed, so that a new option has to be sorted in here or out
+        let TestConfiguration {
+            test_prefix: _,
+            target_platform,
+            keep_test_prefix: _,
+            channels,
+            include_local_channel,
+            include_package_channels,
+            channel_priority,
+            solver,
+            test_minimal_activation,
+            skip_activation,
+            check_entry_point_shadowing,
+            log_dir: _,
+            capture_output: _,
+            compress_logs: _,
+            fail_fast,
+            read_only_prefix,
+            verify_side_effects,
+            detect_side_effects,
+            side_effect_dirs,
+            fail_on_side_effects,
+            test_type_dependencies,
+            checkpoint: _,
+            offline,
+            timeout,
+            total_timeout,
+            concurrency: _,
+            extra_env,
+            expected_sha256,
+            expected_md5,
+            skip_checksum_verification,
+            verify_package_contents,
+            multi_progress: _,
+            retries,
+            allowed_exit_codes,
+            retry_backoff: _,
+            client: _,
+            cache_dir: _,
+            environment_cache: _,
+            clear_cached_package: _,
+            virtual_packages,
+            reuse_prefix,
+            augment_prefix,
+            path_modification,
+            test_filter,
+            runners,
+            observer: _,
+            pip_check,
+            linkage_check,
+            linkage_allowlist,
+            entry_point_check,
+            menuinst_check,
+            prefix_check,
+            metadata_check,
+            uninstall_check,
+            shell_override,
+            shell_args,
+            replace_default_shell_args,
+            login_shell,
+            fail_on_unrunnable_tests,
+            dry_run,
+            skip_environment_without_tests,
+            python_versions,
+            test_cwd,
+            verify_installed_files,
+            extra_dependencies,
+            include_run_exports,
+            force_reindex: _,
+            backend,
+            unique_prefix_per_package: _,
+            pre_test_script,
+            post_test_script,
+            test_include,
+            test_exclude,
+            write_explicit_env: _,
+            json_report: _,
+            check_dependencies,
+            temp_dir: _,
+            keep_test_channel: _,
+            environment_mode,
+            fail_on_stderr,
+            quiet: _,
+            repodata_patches,
+            verify_signature,
+            python_args,
+            interpreter_args,
+            fail_on_undefined_variables,
+            lockfile,
+            print_scripts: _,
+        } = config;
+
+        let to_strings = |specs: &[MatchSpec]| -> Vec<String> {
+            specs.iter().map(ToString::to_string).collect()
+        };
+        Self {
+            target_platform: *target_platform,
+            channels,
+            include_local_channel: *include_local_channel,
+            include_package_channels: *include_package_channels,
+            channel_priority: format!("{:?}", channel_priority),
+            solver: format!("{:?}", solver),
+            test_minimal_activation: *test_minimal_activation,
+            skip_activation: *skip_activation,
+            check_entry_point_shadowing: *check_entry_point_shadowing,
+            fail_fast: *fail_fast,
+            read_only_prefix: *read_only_prefix,
+            verify_side_effects: *verify_side_effects,
+            detect_side_effects: *detect_side_effects,
+            side_effect_dirs,
+            fail_on_side_effects: *fail_on_side_effects,
+            test_type_dependencies: test_type_dependencies
+                .iter()
+                .map(|(test_type, specs)| (*test_type, to_strings(specs)))
+                .collect(),
+            offline: *offline,
+            timeout: *timeout,
+            total_timeout: *total_timeout,
+            extra_env: extra_env
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+            expected_sha256: expected_sha256.as_deref(),
+            expected_md5: expected_md5.as_deref(),
+            skip_checksum_verification: *skip_checksum_verification,
+            verify_package_contents: *verify_package_contents,
+            retries: *retries,
+            allowed_exit_codes,
+            virtual_packages: virtual_packages
+                .iter()
+                .map(|package| format!("{:?}", package))
+                .collect(),
+            reuse_prefix: *reuse_prefix,
+            augment_prefix: *augment_prefix,
+            path_modification: format!("{:?}", path_modification),
+            test_filter: format!("{:?}", test_filter),
+            runners: runners.iter().map(|runner| runner.name()).collect(),
+            pip_check: *pip_check,
+            linkage_check: *linkage_check,
+            linkage_allowlist,
+            entry_point_check: *entry_point_check,
+            menuinst_check: *menuinst_check,
+            prefix_check: *prefix_check,
+            metadata_check: *metadata_check,
+            uninstall_check: *uninstall_check,
+            shell_override: shell_override.as_deref(),
+            shell_args,
+            replace_default_shell_args: *replace_default_shell_args,
+            login_shell: *login_shell,
+            fail_on_unrunnable_tests: *fail_on_unrunnable_tests,
+            dry_run: *dry_run,
+            skip_environment_without_tests: *skip_environment_without_tests,
+            python_versions: python_versions.iter().map(ToString::to_string).collect(),
+            test_cwd: test_cwd.as_deref(),
+            verify_installed_files: *verify_installed_files,
+            extra_dependencies: to_strings(extra_dependencies),
+            include_run_exports: *include_run_exports,
+            backend: format!("{:?}", backend),
+            pre_test_script: pre_test_script.as_deref(),
+            post_test_script: post_test_script.as_deref(),
+            test_include,
+            test_exclude,
+            check_dependencies: *check_dependencies,
+            environment_mode: *environment_mode,
+            fail_on_stderr: *fail_on_stderr,
+            repodata_patches: repodata_patches.as_deref(),
+            verify_signature: verify_signature
+                .as_ref()
+                .map(|key| hex::encode(key.as_bytes())),
+            python_args,
+            interpreter_args: interpreter_args
+                .iter()
+                .map(|(interpreter, args)| (interpreter.as_str(), args.as_slice()))
+                .collect(),
+            fail_on_undefined_variables: *fail_on_undefined_variables,
+            lockfile: lockfile.as_deref(),
+        }
+    }
+}
+
+/// A fingerprint of all options of the configuration that influence the test outcomes
+fn config_fingerprint(config: &TestConfiguration) -> String {
+    let options = serde_json::to_vec(&OutcomeOptions::new(config))
+        .expect("the options of the configuration can be serialized");
+    hex::encode(Sha256::digest(options))
+}
+
+impl Checkpoint {
+    /// Load the checkpoint from disk. If the file does not exist or was written with a different
+    /// configuration, an empty checkpoint is returned.
+    pub fn load(path: &Path, config: &TestConfiguration) -> Result<Self, TestError> {
+        let config_fingerprint = config_fingerprint(config);
+        let empty = Self {
+            config_fingerprint: config_fingerprint.clone(),
+            packages: BTreeMap::new(),
+        };
+
+        if !path.exists() {
+            return Ok(empty);
+        }
+
+        // e.g. a checkpoint of an older version, or one that was truncated
+        let checkpoint: Self = match serde_json::from_str(&std::fs::read_to_string(path)?) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                tracing::warn!(
+                    "Cannot read the checkpoint {:?}, testing all packages again: {}",
+                    path,
+                    e
+                );
+                return Ok(empty);
+            }
+        };
+        if checkpoint.config_fingerprint != config_fingerprint {
+            tracing::info!(
+                "The test configuration changed, discarding the checkpoint {:?}",
+                path
+            );
+            return Ok(empty);
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// Write the checkpoint to disk. It is written to a temporary file next to it first, which
+    /// then replaces the checkpoint, so that an interrupted write does not corrupt it.
+    pub fn save(&self, path: &Path) -> Result<(), TestError> {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        std::fs::create_dir_all(parent)?;
+        let mut file = tempfile::NamedTempFile::new_in(parent)?;
+        std::io::Write::write_all(&mut file, serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Returns the result of a previous run of the package with the given hash. The captured
+    /// output of the tests is not part of the checkpoint.
+    pub fn result(&self, sha256: &str, package: &ArchiveIdentifier) -> Option<TestResult> {
+        let entry = self.packages.get(sha256)?;
+        let mut result = TestResult::new(package.clone());
+        result.outcomes = entry
+            .outcomes
+            .iter()
+            .map(|outcome| {
+                let python_version = outcome
+                    .python_version
+                    .as_deref()
+                    .and_then(|version| Version::from_str(version).ok());
+                let status = match &outcome.status {
+                    CheckpointStatus::Passed => TestStatus::Passed,
+                    CheckpointStatus::Failed { message } => {
+                        TestStatus::Failed(TestError::PreviousRun(message.clone()))
+                    }
+                    CheckpointStatus::Skipped { reason } => {
+                        TestStatus::Skipped(SkipReason::PreviousRun(reason.clone()))
+                    }
+                    CheckpointStatus::Warning { message } => TestStatus::Warning(message.clone()),
+                };
+                let mut test_case = TestCaseOutcome::new(
+                    outcome.name.clone(),
+                    outcome.test_type,
+                    status,
+                    Duration::from_millis(outcome.duration_ms),
+                    Some(CommandOutput {
+                        exit_code: outcome.exit_code,
+                        ..Default::default()
+                    }),
+                );
+                test_case.python_version = python_version;
+                test_case.environment_mode = outcome.environment_mode;
+                test_case
+            })
+            .collect();
+        result.no_tests = result.outcomes.is_empty();
+        Some(result)
+    }
+
+    /// Record the result of the package with the given hash
+    pub fn record(&mut self, sha256: &str, package_file: &Path, result: &TestResult) {
+        let outcomes = result
+            .outcomes
+            .iter()
+            .map(|outcome| CheckpointOutcome {
+                name: outcome.name.clone(),
+                test_type: outcome.test_type,
+                duration_ms: outcome.duration.as_millis() as u64,
+                exit_code: outcome.exit_code,
+                python_version: outcome.python_version.as_ref().map(ToString::to_string),
+                environment_mode: outcome.environment_mode,
+                status: match &outcome.status {
+                    TestStatus::Passed => CheckpointStatus::Passed,
+                    TestStatus::Failed(error) => CheckpointStatus::Failed {
+                        message: error.to_string(),
+                    },
+                    TestStatus::Skipped(reason) => CheckpointStatus::Skipped {
+                        reason: reason.to_string(),
+                    },
+                    TestStatus::Warning(message) => CheckpointStatus::Warning {
+                        message: message.clone(),
+                    },
+                },
+            })
+            .collect();
+
+        let file_name = package_file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.packages.insert(
+            sha256.to_string(),
+            CheckpointEntry {
+                file_name,
+                outcomes,
+            },
+        );
+    }
+}
+
+/// The location of the checkpoint and whether previously tested packages should be skipped
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointConfig {
+    /// The file in which the checkpoint is stored
+    pub path: PathBuf,
+    /// If true, packages that are already recorded in the checkpoint are not tested again
+    pub resume: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use super::*;
+    use crate::{
+        render::solver::{ChannelPriority, SolverBackend},
+        test::{TestBackend, TestFilter, TestRunner},
+    };
+
+    #[derive(Debug)]
+    struct Runner;
+
+    impl TestRunner for Runner {
+        fn name(&self) -> String {
+            "custom".to_string()
+        }
+
+        fn run(&self, _environment: &Path, _cwd: &Path) -> Result<(), TestError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn every_outcome_option_changes_the_fingerprint() {
+        let default = config_fingerprint(&TestConfiguration::default());
+        let changes: Vec<(&str, fn(&mut TestConfiguration))> = vec![
+            ("target_platform", |c| {
+                c.target_platform = Some(Platform::Win64)
+            }),
+            ("channels", |c| c.channels.push("bioconda".to_string())),
+            ("include_local_channel", |c| c.include_local_channel ^= true),
+            ("include_package_channels", |c| {
+                c.include_package_channels ^= true
+            }),
+            ("virtual_packages", |c| {
+                c.virtual_packages
+                    .push(rattler_conda_types::GenericVirtualPackage::from(
+                        rattler_virtual_packages::VirtualPackage::Unix,
+                    ))
+            }),
+            ("shell_override", |c| {
+                c.shell_override = Some(PathBuf::from("/bin/zsh"))
+            }),
+            ("test_cwd", |c| c.test_cwd = Some(PathBuf::from("/tmp"))),
+            ("path_modification", |c| {
+                c.path_modification = rattler_shell::activation::PathModificationBehavior::Replace
+            }),
+            ("runners", |c| c.runners.push(Arc::new(Runner))),
+            ("test_filter", |c| c.test_filter = TestFilter::CommandsOnly),
+            ("timeout", |c| c.timeout = Some(Duration::from_secs(1))),
+            ("extra_env", |c| {
+                c.extra_env.insert("FOO".to_string(), "bar".to_string());
+            }),
+            ("extra_dependencies", |c| {
+                c.extra_dependencies
+                    .push(MatchSpec::from_str("numpy").unwrap())
+            }),
+            ("python_versions", |c| {
+                c.python_versions.push(Version::from_str("3.12").unwrap())
+            }),
+            ("post_test_script", |c| {
+                c.post_test_script = Some("true".to_string())
+            }),
+            ("skip_checksum_verification", |c| {
+                c.skip_checksum_verification ^= true
+            }),
+            ("verify_package_contents", |c| {
+                c.verify_package_contents ^= true
+            }),
+            ("environment_mode", |c| {
+                c.environment_mode = TestEnvironmentMode::Minimal
+            }),
+            ("metadata_check", |c| c.metadata_check ^= true),
+            ("lockfile", |c| c.lockfile = Some(PathBuf::from("env.lock"))),
+            ("channel_priority", |c| {
+                c.channel_priority = ChannelPriority::Strict
+            }),
+            ("solver", |c| c.solver = SolverBackend::Libsolv),
+            ("test_minimal_activation", |c| {
+                c.test_minimal_activation ^= true
+            }),
+            ("skip_activation", |c| c.skip_activation ^= true),
+            ("check_entry_point_shadowing", |c| {
+                c.check_entry_point_shadowing ^= true
+            }),
+            ("fail_fast", |c| c.fail_fast ^= true),
+            ("read_only_prefix", |c| c.read_only_prefix ^= true),
+            ("verify_side_effects", |c| c.verify_side_effects ^= true),
+            ("detect_side_effects", |c| c.detect_side_effects ^= true),
+            ("side_effect_dirs", |c| {
+                c.side_effect_dirs.push(PathBuf::from("/tmp"))
+            }),
+            ("fail_on_side_effects", |c| c.fail_on_side_effects ^= true),
+            ("test_type_dependencies", |c| {
+                c.test_type_dependencies.insert(
+                    TestType::Python,
+                    vec![MatchSpec::from_str("pytest").unwrap()],
+                );
+            }),
+            ("offline", |c| c.offline ^= true),
+            ("total_timeout", |c| {
+                c.total_timeout = Some(Duration::from_secs(1))
+            }),
+            ("expected_sha256", |c| {
+                c.expected_sha256 = Some("0".repeat(64))
+            }),
+            ("expected_md5", |c| c.expected_md5 = Some("0".repeat(32))),
+            ("retries", |c| c.retries += 1),
+            ("allowed_exit_codes", |c| c.allowed_exit_codes.push(77)),
+            ("reuse_prefix", |c| c.reuse_prefix ^= true),
+            ("augment_prefix", |c| c.augment_prefix ^= true),
+            ("pip_check", |c| c.pip_check ^= true),
+            ("linkage_check", |c| c.linkage_check ^= true),
+            ("linkage_allowlist", |c| {
+                c.linkage_allowlist.push("libGL.so*".to_string())
+            }),
+            ("entry_point_check", |c| c.entry_point_check ^= true),
+            ("menuinst_check", |c| c.menuinst_check ^= true),
+            ("prefix_check", |c| c.prefix_check ^= true),
+            ("uninstall_check", |c| c.uninstall_check ^= true),
+            ("shell_args", |c| c.shell_args.push("-x".to_string())),
+            ("replace_default_shell_args", |c| {
+                c.replace_default_shell_args ^= true
+            }),
+            ("login_shell", |c| c.login_shell ^= true),
+            ("fail_on_unrunnable_tests", |c| {
+                c.fail_on_unrunnable_tests ^= true
+            }),
+            ("dry_run", |c| c.dry_run ^= true),
+            ("skip_environment_without_tests", |c| {
+                c.skip_environment_without_tests ^= true
+            }),
+            ("verify_installed_files", |c| {
+                c.verify_installed_files ^= true
+            }),
+            ("include_run_exports", |c| c.include_run_exports ^= true),
+            ("backend", |c| {
+                c.backend = TestBackend::Docker {
+                    image: "ubuntu".to_string(),
+                }
+            }),
+            ("pre_test_script", |c| {
+                c.pre_test_script = Some("true".to_string())
+            }),
+            ("test_include", |c| c.test_include.push("*.sh".to_string())),
+            ("test_exclude", |c| c.test_exclude.push("*.py".to_string())),
+            ("check_dependencies", |c| c.check_dependencies ^= true),
+            ("fail_on_stderr", |c| c.fail_on_stderr ^= true),
+            ("repodata_patches", |c| {
+                c.repodata_patches = Some(PathBuf::from("patches.json"))
+            }),
+            ("verify_signature", |c| {
+                c.verify_signature =
+                    Some(ed25519_dalek::SigningKey::from_bytes(&[7; 32]).verifying_key())
+            }),
+            ("python_args", |c| c.python_args.push("-X dev".to_string())),
+            ("interpreter_args", |c| {
+                c.interpreter_args
+                    .insert("perl".to_string(), vec!["-w".to_string()]);
+            }),
+            ("fail_on_undefined_variables", |c| {
+                c.fail_on_undefined_variables ^= true
+            }),
+        ];
+        for (option, change) in changes {
+            let mut config = TestConfiguration::default();
+            change(&mut config);
+            assert_ne!(config_fingerprint(&config), default, "{}", option);
+        }
+
+        // options that only change where and how the results are written keep the checkpoint
+        let mut config = TestConfiguration::default();
+        config.quiet ^= true;
+        config.concurrency += 1;
+        config.log_dir = Some(PathBuf::from("/tmp/logs"));
+        assert_eq!(config_fingerprint(&config), default);
+    }
+
+    #[test]
+    fn unreadable_checkpoints_are_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        let config = TestConfiguration::default();
+        std::fs::write(&path, "{\"config_fingerprint\": ").unwrap();
+        let checkpoint = Checkpoint::load(&path, &config).unwrap();
+        assert!(checkpoint.packages.is_empty());
+
+        checkpoint.save(&path).unwrap();
+        let checkpoint = Checkpoint::load(&path, &config).unwrap();
+        assert_eq!(checkpoint.config_fingerprint, config_fingerprint(&config));
+        // only the checkpoint is left in the directory
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,99 @@
+//! Run the test commands in a Docker container instead of on the host
+//!
+//! The test prefix, the working directory of the test and the generated test script are mounted
+//! into the container at the same paths as on the host, so that the activation script (which
+//! refers to the absolute path of the prefix) works unchanged. The command that would run the
+//! script on the host is then executed with `docker run` in the given image.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Wrap the command into `docker run`, so that it is executed in a container of the image. The
+/// given paths are mounted into the container (read-write, at the same location). The command
+/// runs as the owner of the first path (the prefix).
+pub(crate) fn docker_command(command: &Command, image: &str, mounts: &[&Path]) -> Command {
+    let mut docker = Command::new("docker");
+    docker.args(docker_args(command, image, mounts));
+    docker
+}
+
+fn docker_args(command: &Command, image: &str, mounts: &[&Path]) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec!["run".into(), "--rm".into(), "--init".into()];
+
+    // create files with the owner of the prefix, so that it can be removed after the tests
+    #[cfg(unix)]
+    if let Some(metadata) = mounts.first().and_then(|path| path.metadata().ok()) {
+        use std::os::unix::fs::MetadataExt;
+        args.push("--user".into());
+        args.push(format!("{}:{}", metadata.uid(), metadata.gid()).into());
+    }
+
+    let mut mounts = mounts
+        .iter()
+        .map(|path| path.to_path_buf())
+        .collect::<Vec<PathBuf>>();
+    mounts.sort();
+    mounts.dedup();
+    for mount in &mounts {
+        let mut volume = mount.clone().into_os_string();
+        volume.push(":");
+        volume.push(mount);
+        args.push("--volume".into());
+        args.push(volume);
+    }
+
+    if let Some(cwd) = command.get_current_dir() {
+        args.push("--workdir".into());
+        args.push(cwd.as_os_str().to_os_string());
+    }
+
+    args.push(image.into());
+    args.push(command.get_program().to_os_string());
+    args.extend(command.get_args().map(|arg| arg.to_os_string()));
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_runs_in_container() {
+        let mut command = Command::new("bash");
+        command
+            .arg("/tmp/rattler-test-123.sh")
+            .current_dir("/work/test");
+
+        let args = docker_args(
+            &command,
+            "ubuntu:22.04",
+            &[
+                Path::new("/work/test"),
+                Path::new("/tmp/rattler-test-123.sh"),
+                Path::new("/work/test"),
+            ],
+        );
+        let args = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(args[..3], ["run", "--rm", "--init"]);
+        assert_eq!(
+            args.iter().filter(|arg| *arg == "--volume").count(),
+            2,
+            "duplicate mounts are removed"
+        );
+        assert!(args.contains(&"/work/test:/work/test".to_string()));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--workdir", "/work/test"]));
+        assert_eq!(
+            args[args.len() - 3..],
+            ["ubuntu:22.04", "bash", "/tmp/rattler-test-123.sh"]
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Reuse the environments of previous tests for packages with the same dependencies
+//!
+//! The cache contains a base environment for every set of resolved records (without the tested
+//! package), in a directory that is named after the hash of the records. A test environment is
+//! cloned from its base environment: the files of packages that do not refer to their prefix are
+//! copied (reflinked where the file system supports it, so that the tests cannot modify the
+//! cache), the other packages and the tested package are linked from the package cache, so that
+//! their prefix placeholders are replaced with the test prefix.
+//!
+//! A base environment is created while holding a file lock next to it in the cache directory, so
+//! that concurrent tests, also of other processes, do not create the same environment twice.
+
+use std::{fs, path::Path};
+
+use rattler_conda_types::{
+    package::{PackageFile, PathsJson},
+    Platform, PrefixRecord, RepoDataRecord,
+};
+use sha2::{Digest, Sha256};
+
+use super::{run_blocking, TestError};
+use crate::{
+    render::solver::{find_installed_packages, install_environment, install_missing_packages},
+    tool_configuration,
+};
+
+/// The file that marks a base environment as completely installed
+const COMPLETE_MARKER: &str = ".complete";
+
+/// The hash of the records of a base environment
+fn environment_key(records: &[RepoDataRecord], platform: Platform) -> String {
+    let mut entries = records
+        .iter()
+        .map(|record| {
+            let sha256 = record
+                .package_record
+                .sha256
+                .map(|sha256| format!("{:x}", sha256))
+                .unwrap_or_default();
+            format!("{}#{}", record.url, sha256)
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(platform.as_str());
+    for entry in entries {
+        hasher.update(b"\n");
+        hasher.update(entry);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns true if the files of the installed package can be linked into another prefix: the
+/// package contains no prefix placeholders and is not a noarch Python package (whose entry
+/// points refer to the Python interpreter of the prefix)
+fn is_relocatable(record: &PrefixRecord) -> bool {
+    if record.repodata_record.package_record.noarch.is_python() {
+        return false;
+    }
+    let Some(package_dir) = &record.extracted_package_dir else {
+        return false;
+    };
+    PathsJson::from_package_directory(package_dir).is_ok_and(|paths| {
+        paths
+            .paths
+            .iter()
+            .all(|entry| entry.prefix_placeholder.is_none())
+    })
+}
+
+/// Copy a file of the base environment into the prefix. [`fs::copy`] clones the file on file
+/// systems that support it (e.g. APFS, Btrfs, XFS), so the copy is cheap there without sharing
+/// the data with the cache. Symbolic links are recreated.
+fn clone_file(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if source.is_symlink() {
+        let link = fs::read_link(source)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(link, destination)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(link, destination)?;
+        return Ok(());
+    }
+    fs::copy(source, destination)?;
+    Ok(())
+}
+
+/// Copy the relocatable packages of the base environment into the prefix and record them in its
+/// `conda-meta`. Returns the number of linked packages.
+fn clone_relocatable_packages(
+    base_prefix: &Path,
+    installed: &[PrefixRecord],
+    prefix: &Path,
+) -> Result<usize, TestError> {
+    let conda_meta = prefix.join("conda-meta");
+    fs::create_dir_all(&conda_meta)?;
+
+    let mut cloned = 0;
+    for record in installed.iter().filter(|record| is_relocatable(record)) {
+        for file in &record.files {
+            clone_file(&base_prefix.join(file), &prefix.join(file))?;
+        }
+        let package_record = &record.repodata_record.package_record;
+        record.clone().write_to_path(
+            conda_meta.join(format!(
+                "{}-{}-{}.json",
+                package_record.name.as_normalized(),
+                package_record.version,
+                package_record.build
+            )),
+            true,
+        )?;
+        cloned += 1;
+    }
+    Ok(cloned)
+}
+
+/// Wait for the exclusive lock of the base environment, which is held until the returned file is
+/// dropped. The lock is a file next to the base environment, so that it is not removed together
+/// with an incomplete environment.
+async fn lock_base_environment(base_prefix: &Path) -> Result<fs::File, TestError> {
+    let lock_path = base_prefix.with_extension("lock");
+    run_blocking(move || {
+        if let Some(cache_dir) = lock_path.parent() {
+            fs::create_dir_all(cache_dir)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&lock_path)?;
+        fs4::FileExt::lock_exclusive(&file)?;
+        Ok(file)
+    })
+    .await
+}
+
+/// Create the test environment with the records in the prefix, reusing the base environment
+/// (all records but the tested package) from the cache directory. The base environment is
+/// created first if the cache does not contain it yet.
+pub(super) async fn install_cached_environment(
+    cache_dir: &Path,
+    records: &[RepoDataRecord],
+    package_name: &str,
+    platform: Platform,
+    prefix: &Path,
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<(), TestError> {
+    let base = records
+        .iter()
+        .filter(|record| record.package_record.name.as_normalized() != package_name)
+        .cloned()
+        .collect::<Vec<_>>();
+    let base_prefix = cache_dir.join(environment_key(&base, platform));
+
+    {
+        let _lock = lock_base_environment(&base_prefix).await?;
+        if !base_prefix.join(COMPLETE_MARKER).exists() {
+            tracing::info!("Creating the cached base environment in {:?}", base_prefix);
+            if base_prefix.exists() {
+                // an incomplete environment of an interrupted run
+                fs::remove_dir_all(&base_prefix)?;
+            }
+            fs::create_dir_all(&base_prefix)?;
+            install_environment(&base, &platform, &base_prefix, tool_configuration)
+                .await
+                .map_err(TestError::TestEnvironmentSetup)?;
+            fs::write(base_prefix.join(COMPLETE_MARKER), "")?;
+        }
+    }
+
+    let installed = find_installed_packages(&base_prefix, 100)
+        .await
+        .map_err(TestError::TestEnvironmentSetup)?;
+    let cloned = clone_relocatable_packages(&base_prefix, &installed, prefix)?;
+    tracing::info!(
+        "Cloned {} of {} packages from the cached base environment {:?}",
+        cloned,
+        base.len(),
+        base_prefix
+    );
+
+    install_missing_packages(records, &platform, prefix, tool_configuration)
+        .await
+        .map_err(TestError::TestEnvironmentSetup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(file_name: &str) -> RepoDataRecord {
+        let package_record = serde_json::from_str(
+            r#"{"name": "foo", "version": "1.0", "build": "0", "build_number": 0, "subdir": "linux-64"}"#,
+        )
+        .unwrap();
+        RepoDataRecord {
+            package_record,
+            file_name: file_name.to_string(),
+            url: url::Url::parse(&format!("https://example.com/linux-64/{}", file_name)).unwrap(),
+            channel: "https://example.com/".to_string(),
+        }
+    }
+
+    #[test]
+    fn environment_key_ignores_the_order() {
+        let (a, b) = (record("a-1.0-0.conda"), record("b-1.0-0.conda"));
+        let key = environment_key(&[a.clone(), b.clone()], Platform::Linux64);
+        assert_eq!(
+            key,
+            environment_key(&[b.clone(), a.clone()], Platform::Linux64)
+        );
+        assert_ne!(key, environment_key(&[a.clone()], Platform::Linux64));
+        assert_ne!(key, environment_key(&[a, b], Platform::OsxArm64));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_are_copied_into_the_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("base/lib/libfoo.so");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, "library").unwrap();
+        std::os::unix::fs::symlink("libfoo.so", dir.path().join("base/lib/libfoo.so.1")).unwrap();
+
+        let prefix = dir.path().join("prefix");
+        clone_file(&source, &prefix.join("lib/libfoo.so")).unwrap();
+        clone_file(
+            &dir.path().join("base/lib/libfoo.so.1"),
+            &prefix.join("lib/libfoo.so.1"),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(prefix.join("lib/libfoo.so.1")).unwrap(),
+            "library"
+        );
+        assert!(prefix.join("lib/libfoo.so.1").is_symlink());
+
+        // changing the file in the prefix does not change the cached environment
+        fs::write(prefix.join("lib/libfoo.so"), "modified").unwrap();
+        assert_eq!(fs::read_to_string(&source).unwrap(), "library");
+    }
+
+    #[tokio::test]
+    async fn base_environment_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_prefix = dir.path().join("cache").join("0123abcd");
+        let lock = lock_base_environment(&base_prefix).await.unwrap();
+
+        let other = fs::File::open(dir.path().join("cache/0123abcd.lock")).unwrap();
+        assert!(fs4::FileExt::try_lock_exclusive(&other).is_err());
+        drop(lock);
+        fs4::FileExt::try_lock_exclusive(&other).unwrap();
+    }
+
+    /// Compares testing all packages of a directory with and without the environment cache
+    /// (with a cold and a warm cache). The packages need to be installable from conda-forge.
+    ///
+    /// `RATTLER_BUILD_BENCH_PACKAGES=<dir> cargo test --release environment_cache_benchmark -- --ignored --nocapture`
+    #[ignore]
+    #[tokio::test]
+    async fn environment_cache_benchmark() {
+        use crate::test::{run_tests, TestConfiguration};
+
+        // the timings are logged, print them to the captured output of the test
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+        let Ok(packages) = std::env::var("RATTLER_BUILD_BENCH_PACKAGES") else {
+            tracing::info!("RATTLER_BUILD_BENCH_PACKAGES is not set");
+            return;
+        };
+        let cache = tempfile::tempdir().unwrap();
+        for (label, environment_cache) in [
+            ("without cache", None),
+            ("cold cache", Some(cache.path().to_path_buf())),
+            ("warm cache", Some(cache.path().to_path_buf())),
+        ] {
+            let prefix = tempfile::tempdir().unwrap();
+            let config = TestConfiguration {
+                test_prefix: prefix.path().join("env"),
+                channels: vec!["conda-forge".to_string()],
+                environment_cache,
+                quiet: true,
+                ..Default::default()
+            };
+            let started = std::time::Instant::now();
+            let results = run_tests(Path::new(&packages), &config).await.unwrap();
+            let passed = results
+                .iter()
+                .filter(|(_, result)| result.as_ref().is_ok_and(|result| result.all_passed()))
+                .count();
+            tracing::info!(
+                "{}: {} packages ({} passed) in {:?}",
+                label,
+                results.len(),
+                passed,
+                started.elapsed()
+            );
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Stop the running test commands when the process is interrupted
+//!
+//! On Unix, every test command is started in its own process group, so that all processes it
+//! started can be terminated together. On Windows, every test command is assigned to a job object
+//! that kills all processes of the job once the job is closed (at the latest when this process
+//! exits).
+//!
+//! While tests are running (i.e. while an [`InterruptGuard`] exists), an interrupt (Ctrl-C or
+//! `SIGTERM`) only marks the process as interrupted: the running test commands are killed and
+//! fail with [`super::TestError::Interrupted`], so that the tests unwind normally and remove
+//! their prefixes. A second interrupt terminates the process right away. Once the last guard is
+//! dropped, the handlers that were installed before (e.g. by the application) are restored, so
+//! interrupts outside of the tests are handled as if the tests never ran.
+
+use std::{
+    process::Child,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+/// Whether the process was interrupted since the handler was installed
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The number of guards that exist and the handlers they replaced
+static HANDLER: Mutex<HandlerState> = Mutex::new(HandlerState {
+    guards: 0,
+    #[cfg(unix)]
+    previous: Vec::new(),
+});
+
+struct HandlerState {
+    guards: usize,
+    #[cfg(unix)]
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+/// Whether the process was interrupted while tests are running
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Handles interrupts of the process (see the module documentation) for as long as it exists
+pub(crate) struct InterruptGuard(());
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        let mut state = HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+        if state.guards == 0 {
+            INTERRUPTED.store(false, Ordering::SeqCst);
+            install(&mut state);
+        }
+        state.guards += 1;
+        Self(())
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        let mut state = HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+        state.guards -= 1;
+        if state.guards == 0 {
+            uninstall(&mut state);
+        }
+    }
+}
+
+#[cfg(unix)]
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // SAFETY: both functions are async-signal-safe
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn install(state: &mut HandlerState) {
+    for signal in SIGNALS {
+        // SAFETY: the action is fully initialized and the handler only uses async-signal-safe
+        // functions
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(signal, &action, &mut previous) == 0 {
+                state.previous.push((signal, previous));
+            } else {
+                tracing::warn!(
+                    "Could not install the interrupt handler for signal {}: {}",
+                    signal,
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn uninstall(state: &mut HandlerState) {
+    for (signal, previous) in state.previous.drain(..) {
+        // SAFETY: the action was returned by `sigaction` when the handler was installed
+        unsafe {
+            libc::sigaction(signal, &previous, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn on_console_event(event: u32) -> windows_sys::Win32::Foundation::BOOL {
+    use windows_sys::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+    if !matches!(event, CTRL_C_EVENT | CTRL_BREAK_EVENT) || INTERRUPTED.swap(true, Ordering::SeqCst)
+    {
+        // let the next handler (by default the one that terminates the process) handle it
+        return 0;
+    }
+    // the handler runs on its own thread, so the commands can be terminated right away
+    job::terminate();
+    1
+}
+
+#[cfg(windows)]
+fn install(_state: &mut HandlerState) {
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    // SAFETY: the handler is a valid function for the lifetime of the process
+    let added = unsafe { SetConsoleCtrlHandler(Some(on_console_event), 1) };
+    if added == 0 {
+        tracing::warn!(
+            "Could not install the interrupt handler: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(windows)]
+fn uninstall(_state: &mut HandlerState) {
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    // SAFETY: removes the handler that was added by `install`
+    unsafe {
+        SetConsoleCtrlHandler(Some(on_console_event), 0);
+    }
+}
+
+/// Kill all processes of the process group of the given process
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pid: u32) {
+    // SAFETY: `killpg` has no memory safety requirements, a group that no longer exists is
+    // reported as an error that is ignored
+    unsafe {
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+/// Keeps track of a running test command, so that it can be stopped if the process is
+/// interrupted. The command has to be started in its own process group on Unix.
+pub(crate) struct RunningChild {
+    _guard: InterruptGuard,
+}
+
+impl RunningChild {
+    pub fn new(child: &Child) -> Self {
+        let guard = InterruptGuard::new();
+        #[cfg(windows)]
+        job::assign(child);
+        #[cfg(not(windows))]
+        let _ = child;
+        Self { _guard: guard }
+    }
+}
+
+#[cfg(windows)]
+mod job {
+    use std::{os::windows::io::AsRawHandle, process::Child, sync::OnceLock};
+
+    use windows_sys::Win32::{
+        Foundation::HANDLE,
+        System::JobObjects::{
+            AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+            SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+            JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        },
+    };
+
+    /// The job object of all test commands. It is never closed explicitly, so the commands are
+    /// killed when this process exits.
+    struct Job(HANDLE);
+
+    // the handle of a job object can be used from any thread
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    static JOB: OnceLock<Option<Job>> = OnceLock::new();
+
+    fn job() -> Option<&'static Job> {
+        JOB.get_or_init(|| {
+            // SAFETY: the job object is created without a name and with default security, and
+            // the limit information is a valid, fully initialized struct of the given size
+            unsafe {
+                let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+                if handle == 0 {
+                    tracing::debug!("Could not create a job object for the test commands");
+                    return None;
+                }
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                SetInformationJobObject(
+                    handle,
+                    JobObjectExtendedLimitInformation,
+                    &info as *const _ as *const std::ffi::c_void,
+                    std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                );
+                Some(Job(handle))
+            }
+        })
+        .as_ref()
+    }
+
+    /// Assign the process to the job object of the test commands, processes that it starts
+    /// afterwards are part of the job as well
+    pub fn assign(child: &Child) {
+        if let Some(job) = job() {
+            // SAFETY: both handles are valid for the duration of the call
+            unsafe {
+                AssignProcessToJobObject(job.0, child.as_raw_handle() as HANDLE);
+            }
+        }
+    }
+
+    /// Terminate all processes of the job object
+    pub fn terminate() {
+        if let Some(job) = job() {
+            // SAFETY: the handle is valid, it is never closed
+            unsafe {
+                TerminateJobObject(job.0, 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Check that the shared libraries of a package can be loaded from the test environment
+//!
+//! The dependencies of every library that the package installs are resolved with `ldd` (Linux)
+//! or `otool -L` (macOS), or read from the import table of the DLL (Windows). A dependency that
+//! cannot be found, or that is resolved to a library outside of the prefix that is not part of
+//! the system (or explicitly allowed), is reported as a problem.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use rattler_conda_types::{
+    package::{PackageFile, PathType, PathsJson},
+    Platform,
+};
+
+use super::{prefix_bin_dirs, TestError};
+
+/// Libraries of the system that packages are allowed to link against on Linux (`glibc`)
+const LINUX_SYSTEM_LIBRARIES: &[&str] = &[
+    "linux-vdso.so*",
+    "linux-gate.so*",
+    "ld-linux*.so*",
+    "ld64.so*",
+    "libc.so*",
+    "libm.so*",
+    "libmvec.so*",
+    "libdl.so*",
+    "libpthread.so*",
+    "librt.so*",
+    "libutil.so*",
+    "libresolv.so*",
+    "libcrypt.so*",
+    "libnsl.so*",
+    "libanl.so*",
+];
+
+/// Libraries of the system that packages are allowed to link against on macOS
+const MACOS_SYSTEM_LIBRARIES: &[&str] = &["/usr/lib/**", "/System/Library/**"];
+
+/// DLLs of the system that packages are allowed to link against on Windows (in addition to the
+/// DLLs in the system directory)
+const WINDOWS_SYSTEM_LIBRARIES: &[&str] = &["api-ms-win-*", "ext-ms-*"];
+
+/// A dependency of a library that cannot be resolved inside of the test environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkageProblem {
+    /// The library (relative to the prefix) that has the dependency
+    pub library: PathBuf,
+    /// The name of the dependency (e.g. the soname)
+    pub dependency: String,
+    /// The path the dependency resolved to, `None` if it was not found
+    pub resolved: Option<PathBuf>,
+}
+
+impl fmt::Display for LinkageProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.resolved {
+            Some(resolved) => write!(
+                f,
+                "{}: {} resolves to {} outside of the prefix",
+                self.library.display(),
+                self.dependency,
+                resolved.display()
+            ),
+            None => write!(
+                f,
+                "{}: {} not found",
+                self.library.display(),
+                self.dependency
+            ),
+        }
+    }
+}
+
+/// Returns true if the file name is the name of a shared library on the platform
+fn is_library(path: &Path, platform: Platform) -> bool {
+    let Some(file_name) = path.file_name().map(|name| name.to_string_lossy().to_lowercase())
+    else {
+        return false;
+    };
+    if platform.is_windows() {
+        file_name.ends_with(".dll") || file_name.ends_with(".pyd")
+    } else if platform.is_osx() {
+        file_name.ends_with(".dylib") || file_name.ends_with(".so")
+    } else {
+        file_name.ends_with(".so") || file_name.contains(".so.")
+    }
+}
+
+fn glob_set(patterns: impl IntoIterator<Item = String>) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(&pattern)?);
+    }
+    builder.build()
+}
+
+/// Parse the output of `ldd` into the dependencies and the paths they resolve to
+fn parse_ldd_output(output: &str) -> Vec<(String, Option<PathBuf>)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, resolved) = line.split_once("=>")?;
+            let resolved = resolved.trim();
+            let resolved = if resolved.starts_with("not found") {
+                None
+            } else {
+                let path = resolved.split(" (").next().unwrap_or(resolved).trim();
+                Some(PathBuf::from(path))
+            };
+            Some((name.trim().to_string(), resolved))
+        })
+        .collect()
+}
+
+/// Parse the output of `otool -L` into the install names of the dependencies
+fn parse_otool_output(output: &str) -> Vec<String> {
+    output
+        .lines()
+        // the first line is the library itself
+        .skip(1)
+        .filter_map(|line| {
+            let name = line.trim().split(" (").next()?.trim();
+            (!name.is_empty()).then(|| name.to_string())
+        })
+        .collect()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Read the names of the imported DLLs from the import table of a PE file. Returns `None` if the
+/// file is not a valid PE file.
+fn pe_imports(data: &[u8]) -> Option<Vec<String>> {
+    let pe = read_u32(data, 0x3c)? as usize;
+    if data.get(pe..pe + 4)? != b"PE\0\0" {
+        return None;
+    }
+    let coff = pe + 4;
+    let number_of_sections = read_u16(data, coff + 2)? as usize;
+    let optional_header_size = read_u16(data, coff + 16)? as usize;
+    let optional_header = coff + 20;
+
+    // the data directories follow the standard and Windows specific fields of the header
+    let data_directories = match read_u16(data, optional_header)? {
+        0x10b => optional_header + 96,
+        0x20b => optional_header + 112,
+        _ => return None,
+    };
+    // the import table is the second data directory
+    let import_table = read_u32(data, data_directories + 8)?;
+    if import_table == 0 {
+        return Some(Vec::new());
+    }
+
+    let sections = optional_header + optional_header_size;
+    let to_offset = |rva: u32| -> Option<usize> {
+        (0..number_of_sections).find_map(|index| {
+            let section = sections + index * 40;
+            let virtual_size = read_u32(data, section + 8)?;
+            let virtual_address = read_u32(data, section + 12)?;
+            let raw_size = read_u32(data, section + 16)?;
+            let raw_offset = read_u32(data, section + 20)?;
+            let size = virtual_size.max(raw_size);
+            (rva >= virtual_address && rva - virtual_address < size)
+                .then(|| (rva - virtual_address) as usize + raw_offset as usize)
+        })
+    };
+
+    let mut imports = Vec::new();
+    let mut descriptor = to_offset(import_table)?;
+    loop {
+        let name = read_u32(data, descriptor + 12)?;
+        if name == 0 {
+            break;
+        }
+        let start = to_offset(name)?;
+        let end = start + data.get(start..)?.iter().position(|&byte| byte == 0)?;
+        imports.push(String::from_utf8_lossy(&data[start..end]).to_string());
+        descriptor += 20;
+    }
+
+    Some(imports)
+}
+
+/// Check the linkage of all libraries of the package (listed in its `info/paths.json`) that are
+/// installed into the prefix. The allowlist contains additional glob patterns of dependencies
+/// (names or resolved paths) that may be resolved outside of the prefix.
+pub(crate) fn check_linkage(
+    prefix: &Path,
+    package_folder: &Path,
+    platform: Platform,
+    allowlist: &[String],
+) -> Result<Vec<LinkageProblem>, TestError> {
+    let paths_json = PathsJson::from_path(&package_folder.join("info/paths.json"))?;
+    let libraries = paths_json
+        .paths
+        .into_iter()
+        .filter(|entry| entry.path_type == PathType::HardLink)
+        .map(|entry| entry.relative_path)
+        .filter(|path| is_library(path, platform))
+        .collect::<Vec<_>>();
+
+    let system_libraries = if platform.is_windows() {
+        WINDOWS_SYSTEM_LIBRARIES
+    } else if platform.is_osx() {
+        MACOS_SYSTEM_LIBRARIES
+    } else {
+        LINUX_SYSTEM_LIBRARIES
+    };
+    let allowed = glob_set(
+        system_libraries
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .chain(allowlist.iter().cloned())
+            .map(|pattern| {
+                if platform.is_windows() {
+                    pattern.to_lowercase()
+                } else {
+                    pattern
+                }
+            }),
+    )?;
+
+    let mut problems = Vec::new();
+    for library in libraries {
+        let path = prefix.join(&library);
+        let library_dir = path.parent().unwrap_or(prefix).to_path_buf();
+        let problem = |dependency: String, resolved: Option<PathBuf>| LinkageProblem {
+            library: library.clone(),
+            dependency,
+            resolved,
+        };
+
+        if platform.is_windows() {
+            let Some(imports) = pe_imports(&std::fs::read(&path)?) else {
+                continue;
+            };
+            let system_dir =
+                std::env::var_os("SystemRoot").map(|root| PathBuf::from(root).join("System32"));
+            for import in imports {
+                let lowercase = import.to_lowercase();
+                let found = std::iter::once(library_dir.clone())
+                    .chain(prefix_bin_dirs(prefix, &platform))
+                    .chain(system_dir.clone())
+                    .any(|dir| dir.join(&import).exists());
+                if !found && !allowed.is_match(&lowercase) {
+                    problems.push(problem(import, None));
+                }
+            }
+        } else if platform.is_osx() {
+            let output = Command::new("otool").arg("-L").arg(&path).output()?;
+            for install_name in parse_otool_output(&String::from_utf8_lossy(&output.stdout)) {
+                let file_name = Path::new(&install_name)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| install_name.clone());
+                if allowed.is_match(&install_name) || allowed.is_match(&file_name) {
+                    continue;
+                }
+
+                let resolved = match install_name.split_once('/') {
+                    Some(("@rpath" | "@loader_path" | "@executable_path", relative)) => [
+                        library_dir.join(relative),
+                        prefix.join("lib").join(relative),
+                    ]
+                    .into_iter()
+                    .find(|candidate| candidate.exists()),
+                    _ => Some(PathBuf::from(&install_name)).filter(|path| path.exists()),
+                };
+                match resolved {
+                    Some(resolved) if resolved.starts_with(prefix) => {}
+                    resolved => problems.push(problem(install_name, resolved)),
+                }
+            }
+        } else {
+            let output = Command::new("ldd").arg(&path).output()?;
+            for (soname, resolved) in parse_ldd_output(&String::from_utf8_lossy(&output.stdout)) {
+                if allowed.is_match(&soname) {
+                    continue;
+                }
+                match resolved {
+                    Some(resolved)
+                        if resolved.starts_with(prefix) || allowed.is_match(&resolved) => {}
+                    resolved => problems.push(problem(soname, resolved)),
+                }
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ldd() {
+        let output = "\tlinux-vdso.so.1 (0x00007ffd6b5f0000)\n\
+                      \tlibfoo.so.1 => /prefix/lib/libfoo.so.1 (0x00007f1c2a000000)\n\
+                      \tlibbar.so.2 => not found\n\
+                      \t/lib64/ld-linux-x86-64.so.2 (0x00007f1c2a400000)\n";
+        assert_eq!(
+            parse_ldd_output(output),
+            vec![
+                (
+                    "libfoo.so.1".to_string(),
+                    Some(PathBuf::from("/prefix/lib/libfoo.so.1"))
+                ),
+                ("libbar.so.2".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_otool() {
+        let output = "/prefix/lib/libfoo.dylib:\n\
+                      \t@rpath/libfoo.1.dylib (compatibility version 1.0.0, current version 1.2.0)\n\
+                      \t/usr/lib/libSystem.B.dylib (compatibility version 1.0.0, current version 1311.0.0)\n";
+        assert_eq!(
+            parse_otool_output(output),
+            vec![
+                "@rpath/libfoo.1.dylib".to_string(),
+                "/usr/lib/libSystem.B.dylib".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn library_file_names() {
+        assert!(is_library(
+            Path::new("lib/libfoo.so.1.2"),
+            Platform::Linux64
+        ));
+        assert!(!is_library(Path::new("bin/foo"), Platform::Linux64));
+        assert!(is_library(Path::new("lib/libfoo.dylib"), Platform::Osx64));
+        assert!(is_library(
+            Path::new("Library/bin/FOO.DLL"),
+            Platform::Win64
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+//! Create the test environment from a lockfile instead of solving it
+//!
+//! The lockfile is an explicit environment spec, as written by
+//! [`TestConfiguration::write_explicit_env`](super::TestConfiguration::write_explicit_env) or by
+//! `conda list --explicit --md5`:
+//!
+//! ```text
+//! # platform: linux-64
+//! @EXPLICIT
+//! https://conda.anaconda.org/conda-forge/linux-64/python-3.12.0-h123_0.conda#<md5>
+//! ```
+//!
+//! The lockfiles of several platforms can be concatenated: the packages that follow a
+//! `# platform: <subdir>` line belong to that platform. The records of the packages are looked
+//! up in the channels, so the channels have to contain all locked packages.
+
+use std::{path::Path, str::FromStr};
+
+use rattler_conda_types::{
+    package::ArchiveIdentifier, MatchSpec, PackageRecord, Platform, RepoDataRecord,
+};
+use url::Url;
+
+use super::{exact_match_spec, TestError};
+use crate::{render::solver::load_repodatas, tool_configuration};
+
+/// A package of the lockfile
+#[derive(Debug, PartialEq)]
+struct LockedPackage {
+    url: Url,
+    /// The hex encoded MD5 hash of the package (if it is locked)
+    md5: Option<String>,
+    /// The name of the package (from the file name)
+    name: String,
+}
+
+impl LockedPackage {
+    /// The file name of the package
+    fn file_name(&self) -> &str {
+        self.url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default()
+    }
+}
+
+/// Parse the packages of the lockfile that belong to the platform
+fn parse(contents: &str, platform: Platform) -> Result<Vec<LockedPackage>, String> {
+    let mut section_platform = None;
+    let mut packages = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if let Some(subdir) = line.strip_prefix("# platform:") {
+            let subdir = subdir.trim();
+            section_platform = Some(
+                Platform::from_str(subdir).map_err(|_| format!("unknown platform '{}'", subdir))?,
+            );
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') || line == "@EXPLICIT" {
+            continue;
+        }
+        if section_platform.is_some_and(|section| section != platform) {
+            continue;
+        }
+
+        let (url, md5) = match line.split_once('#') {
+            Some((url, md5)) => (url, Some(md5.trim().to_lowercase())),
+            None => (line, None),
+        };
+        let url = Url::parse(url).map_err(|e| format!("invalid URL '{}': {}", url, e))?;
+        let mut package = LockedPackage {
+            url,
+            md5,
+            name: String::new(),
+        };
+        package.name = ArchiveIdentifier::try_from_filename(package.file_name())
+            .ok_or_else(|| format!("'{}' is not the URL of a package", line))?
+            .name;
+        packages.push(package);
+    }
+    Ok(packages)
+}
+
+/// Returns true if one of the records satisfies the spec
+fn is_satisfied(spec: &MatchSpec, records: &[RepoDataRecord]) -> bool {
+    records
+        .iter()
+        .any(|record| spec.matches(&record.package_record))
+}
+
+/// The specs that the package depends on and that the records do not satisfy
+fn unsatisfied_dependencies(package: &PackageRecord, records: &[RepoDataRecord]) -> Vec<String> {
+    package
+        .depends
+        .iter()
+        .filter(|depends| {
+            MatchSpec::from_str(depends).is_ok_and(|spec| !is_satisfied(&spec, records))
+        })
+        .cloned()
+        .collect()
+}
+
+/// The records of the locked packages for the platform, together with the tested package (from
+/// the channels, instead of a package with the same name in the lockfile). Dependencies of the
+/// package and test requirements that the lockfile does not satisfy are reported as warnings.
+pub(super) async fn locked_records(
+    lockfile: &Path,
+    pkg: &ArchiveIdentifier,
+    platform: Platform,
+    dependencies: &[MatchSpec],
+    channels: &[String],
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<Vec<RepoDataRecord>, TestError> {
+    let invalid = |reason: String| TestError::InvalidLockfile {
+        path: lockfile.to_path_buf(),
+        reason,
+    };
+    let locked = parse(&fs_err::read_to_string(lockfile)?, platform).map_err(invalid)?;
+    if locked.is_empty() {
+        return Err(invalid(format!("it contains no packages for {}", platform)));
+    }
+
+    let package_spec = exact_match_spec(pkg)?;
+    let mut specs = vec![package_spec.clone()];
+    for package in &locked {
+        specs.push(MatchSpec::from_str(&package.name)?);
+    }
+    let available = load_repodatas(&specs, channels, platform, tool_configuration)
+        .await
+        .map_err(TestError::TestEnvironmentSetup)?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let package_record = available
+        .iter()
+        .find(|record| package_spec.matches(&record.package_record))
+        .cloned()
+        .ok_or_else(|| TestError::PackageNotFound(package_spec.to_string()))?;
+
+    let mut records = Vec::new();
+    for package in &locked {
+        if package.name == pkg.name {
+            tracing::warn!(
+                "The lockfile contains {}, the tested package is installed instead",
+                package.file_name()
+            );
+            continue;
+        }
+        let record = available
+            .iter()
+            .find(|record| record.url == package.url)
+            .or_else(|| {
+                available
+                    .iter()
+                    .find(|record| record.file_name == package.file_name())
+            })
+            .ok_or_else(|| invalid(format!("{} is not available in the channels", package.url)))?;
+        let md5 = record.package_record.md5.map(|md5| format!("{:x}", md5));
+        if package.md5.is_some() && md5.is_some() && package.md5 != md5 {
+            return Err(invalid(format!(
+                "the MD5 hash of {} does not match the channel",
+                record.file_name
+            )));
+        }
+        records.push(record.clone());
+    }
+    records.push(package_record);
+
+    let package_record = &records[records.len() - 1].package_record;
+    let unsatisfied = unsatisfied_dependencies(package_record, &records)
+        .into_iter()
+        .chain(
+            dependencies
+                .iter()
+                .filter(|spec| !is_satisfied(spec, &records))
+                .map(ToString::to_string),
+        )
+        .collect::<Vec<_>>();
+    if !unsatisfied.is_empty() {
+        tracing::warn!(
+            "The lockfile {:?} does not satisfy the requirements of {}: {}",
+            lockfile,
+            pkg.name,
+            unsatisfied.join(", ")
+        );
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockfile_is_filtered_by_platform() {
+        let contents = "\
+# platform: linux-64
+@EXPLICIT
+https://conda.anaconda.org/conda-forge/linux-64/python-3.12.0-h123_0.conda#0123456789ABCDEF0123456789abcdef
+https://conda.anaconda.org/conda-forge/noarch/tzdata-2023c-h71feb2d_0.conda
+
+# platform: osx-arm64
+@EXPLICIT
+https://conda.anaconda.org/conda-forge/osx-arm64/python-3.12.0-h456_0.conda
+";
+        let packages = parse(contents, Platform::Linux64).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "python");
+        assert_eq!(packages[0].file_name(), "python-3.12.0-h123_0.conda");
+        assert_eq!(
+            packages[0].md5.as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+        assert_eq!(packages[1].name, "tzdata");
+        assert_eq!(packages[1].md5, None);
+
+        let packages = parse(contents, Platform::OsxArm64).unwrap();
+        assert_eq!(packages.len(), 1);
+        assert!(parse(contents, Platform::Win64).unwrap().is_empty());
+
+        assert!(parse("https://example.com/not-a-package.txt\n", Platform::Linux64).is_err());
+    }
+}
@@ -0,0 +1,472 @@
+//! The optional `info/test/tests.yaml` manifest that carries additional information about the
+//! tests of a package
+//!
+//! ```yaml
+//! # tests that are defined directly in the manifest. If any of these are given, the legacy
+//! # `run_test.*` files of the package are ignored.
+//! # `${PREFIX}`, `${PKG_VERSION}` and the other variables that are set for the test commands
+//! # are expanded before the commands are run
+//! commands:
+//!   - mytool --help
+//!   - test -f ${PREFIX}/share/mytool/${PKG_VERSION}/data.txt
+//! imports:
+//!   - mypackage
+//!   # only imported when testing for one of the platforms
+//!   - module: mypackage.cuda
+//!     platforms: [linux-64]
+//! files:
+//!   - bin/mytool
+//! # additional dependencies of the test environment
+//! requires:
+//!   - pytest
+//! # check the requirements of the installed Python packages with `pip check`
+//! pip_check: true
+//! # check that all dependencies of the shared libraries of the package can be resolved
+//! linkage_check: true
+//! # run every Python entry point of the package with `--help`
+//! entry_point_check: true
+//! # check that the menu entries (`Menu/*.json`) refer to installed commands and icons
+//! menuinst_check: true
+//! # check that the prefix placeholders of the files were replaced on installation
+//! prefix_check: true
+//! # check that `info/index.json` agrees with the file name of the package
+//! metadata_check: true
+//!
+//! tests:
+//!   # options for the test, keyed by the test file name
+//!   run_test.py:
+//!     requires_gpu: true
+//!     # skipped when testing offline
+//!     requires_network: false
+//!     # files that the test is allowed to create or modify (globs, relative to the
+//!     # test folder or the prefix)
+//!     side_effects:
+//!       - "output/*.txt"
+//!     # exit codes of the test command that are accepted in addition to 0
+//!     allowed_exit_codes: [2]
+//!   run_test.sh:
+//!     # checked against the captured output (stdout and stderr) of the test
+//!     expect_output:
+//!       - contains: "mytool 1.2"
+//!       # regular expressions are matched per line (`^` and `$` match at line breaks)
+//!       - matches: "^usage: mytool"
+//!       - not_contains: "DeprecationWarning"
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::Platform;
+use serde::{Deserialize, Serialize};
+
+use super::TestError;
+
+/// The file name of the manifest inside of the `info/test` folder
+pub const MANIFEST_FILE_NAME: &str = "tests.yaml";
+
+/// Options that apply to a single test
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestOptions {
+    /// The test needs a GPU and is skipped on hosts where none is detected
+    #[serde(default)]
+    pub requires_gpu: bool,
+    /// The test needs network access and is skipped when testing offline
+    #[serde(default)]
+    pub requires_network: bool,
+    /// Glob patterns of files that the test is allowed to create, modify or remove
+    #[serde(default)]
+    pub side_effects: Vec<String>,
+    /// Exit codes of the test command that are accepted in addition to 0
+    #[serde(default)]
+    pub allowed_exit_codes: Vec<i32>,
+    /// Expectations on the captured output of the test, which fails if one of them is not met
+    #[serde(default)]
+    pub expect_output: Vec<OutputExpectation>,
+}
+
+/// An expectation on the captured output of a test. All of the given conditions have to hold.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OutputExpectation {
+    /// The output has to contain the text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    /// The output must not contain the text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_contains: Option<String>,
+    /// A part of the output has to match the regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches: Option<String>,
+    /// No part of the output may match the regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_matches: Option<String>,
+}
+
+impl OutputExpectation {
+    /// Check the output against the expectation. Returns the first condition that is not met
+    /// (e.g. `contains "mytool 1.2"`), together with the lines of the output that violate a
+    /// condition that the output must not match (all lines for the other conditions).
+    pub fn check(&self, output: &str) -> Result<Option<(String, Vec<String>)>, TestError> {
+        let all_lines = || output.lines().map(str::to_string).collect::<Vec<_>>();
+
+        if let Some(text) = &self.contains {
+            if !output.contains(text.as_str()) {
+                return Ok(Some((format!("contains {:?}", text), all_lines())));
+            }
+        }
+        if let Some(text) = &self.not_contains {
+            if output.contains(text.as_str()) {
+                let lines = output
+                    .lines()
+                    .filter(|line| line.contains(text.as_str()))
+                    .map(str::to_string)
+                    .collect();
+                return Ok(Some((format!("does not contain {:?}", text), lines)));
+            }
+        }
+        if let Some(pattern) = &self.matches {
+            if !multi_line_regex(pattern)?.is_match(output) {
+                return Ok(Some((format!("matches /{}/", pattern), all_lines())));
+            }
+        }
+        if let Some(pattern) = &self.not_matches {
+            let regex = multi_line_regex(pattern)?;
+            if regex.is_match(output) {
+                let lines = output
+                    .lines()
+                    .filter(|line| regex.is_match(line))
+                    .map(str::to_string)
+                    .collect();
+                return Ok(Some((format!("does not match /{}/", pattern), lines)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A regular expression in which `^` and `$` match at the start and end of every line
+fn multi_line_regex(pattern: &str) -> Result<regex::Regex, regex::Error> {
+    regex::RegexBuilder::new(pattern).multi_line(true).build()
+}
+
+/// A Python module that needs to be importable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Import {
+    /// The module is imported on all platforms
+    Module(String),
+    /// The module is only imported when testing for one of the platforms
+    Selected {
+        module: String,
+        platforms: Vec<Platform>,
+    },
+}
+
+impl Import {
+    /// The name of the module
+    pub fn module(&self) -> &str {
+        match self {
+            Import::Module(module) | Import::Selected { module, .. } => module,
+        }
+    }
+
+    /// Returns true if the module is imported when testing for the platform
+    pub fn applies_to(&self, platform: Platform) -> bool {
+        match self {
+            Import::Module(_) => true,
+            Import::Selected { platforms, .. } => platforms.contains(&platform),
+        }
+    }
+}
+
+/// Returns true if the name can be the name of an environment variable
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace every `${NAME}` in the command with the value of the variable. Other forms (like
+/// `$NAME` or `${NAME:-default}`) are left to the shell.
+fn expand_command(
+    command: &str,
+    variables: &HashMap<String, String>,
+    fail_on_undefined: bool,
+) -> Result<String, TestError> {
+    let mut expanded = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find("${") {
+        let Some(length) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + length];
+        expanded.push_str(&rest[..start]);
+        match variables.get(name) {
+            Some(value) => expanded.push_str(value),
+            None if fail_on_undefined && is_variable_name(name) => {
+                return Err(TestError::UndefinedVariable {
+                    name: name.to_string(),
+                    command: command.to_string(),
+                })
+            }
+            None => expanded.push_str(&rest[start..=start + length]),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The test manifest of a package
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestManifest {
+    /// Commands that are run in the test environment (as `run_test.sh` or `run_test.bat`)
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// Python modules that need to be importable (as `run_test.py`), optionally only on some
+    /// platforms
+    #[serde(default)]
+    pub imports: Vec<Import>,
+    /// Glob patterns of files that need to exist in the prefix (as `run_test.json`)
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Additional dependencies (match specs) of the test environment
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Run `pip check` in the test environment
+    #[serde(default)]
+    pub pip_check: bool,
+    /// Check the linkage of the shared libraries of the package
+    #[serde(default)]
+    pub linkage_check: bool,
+    /// Run the Python entry points of the package with `--help`
+    #[serde(default)]
+    pub entry_point_check: bool,
+    /// Check the menuinst metadata of the package
+    #[serde(default)]
+    pub menuinst_check: bool,
+    /// Check that the prefix placeholders of the package were replaced
+    #[serde(default)]
+    pub prefix_check: bool,
+    /// Check the `info/index.json` of the package against its file name
+    #[serde(default)]
+    pub metadata_check: bool,
+    /// Options for individual tests, keyed by test name
+    #[serde(default)]
+    pub tests: BTreeMap<String, TestOptions>,
+}
+
+impl TestManifest {
+    /// Read the manifest from a test folder. If the folder contains no manifest, an empty
+    /// manifest is returned.
+    pub fn from_folder(test_folder: &Path) -> Result<Self, TestError> {
+        let path = test_folder.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(&path)?;
+        Self::from_yaml(&contents)
+    }
+
+    /// Parse the manifest from its YAML contents
+    pub fn from_yaml(contents: &str) -> Result<Self, TestError> {
+        Ok(serde_yaml::from_str(contents)?)
+    }
+
+    /// Returns true if the manifest defines tests itself (instead of relying on the
+    /// `run_test.*` files of the package)
+    pub fn defines_tests(&self) -> bool {
+        !self.commands.is_empty() || !self.imports.is_empty() || !self.files.is_empty()
+    }
+
+    /// Write the tests that are defined in the manifest as `run_test.*` files for the platform
+    /// into the given directory, and return the paths of the written files. Imports that are
+    /// not selected for the platform are left out (see [`Self::skipped_imports`]).
+    pub fn write_test_files(
+        &self,
+        dir: &Path,
+        platform: Platform,
+    ) -> Result<Vec<PathBuf>, TestError> {
+        let mut test_files = Vec::new();
+
+        if !self.commands.is_empty() {
+            let path = dir.join(if platform.is_windows() {
+                "run_test.bat"
+            } else {
+                "run_test.sh"
+            });
+            let mut file = fs::File::create(&path)?;
+            for command in &self.commands {
+                writeln!(file, "{}\n", command)?;
+            }
+            test_files.push(path);
+        }
+
+        let imports = self
+            .imports
+            .iter()
+            .filter(|import| import.applies_to(platform))
+            .collect::<Vec<_>>();
+        if !imports.is_empty() {
+            let path = dir.join("run_test.py");
+            let mut file = fs::File::create(&path)?;
+            for import in imports {
+                writeln!(file, "import {}\n", import.module())?;
+            }
+            test_files.push(path);
+        }
+
+        if !self.files.is_empty() {
+            let path = dir.join("run_test.json");
+            fs::write(&path, serde_json::to_string(&self.files)?)?;
+            test_files.push(path);
+        }
+
+        Ok(test_files)
+    }
+
+    /// Expand the variables (`${NAME}`) in the commands with the variables that are set for the
+    /// test commands (e.g. `PREFIX` or `PKG_VERSION`). A variable that is not defined is left
+    /// as it is, or fails with [`TestError::UndefinedVariable`] if `fail_on_undefined` is set.
+    pub fn expand_variables(
+        &mut self,
+        variables: &HashMap<String, String>,
+        fail_on_undefined: bool,
+    ) -> Result<(), TestError> {
+        for command in &mut self.commands {
+            *command = expand_command(command, variables, fail_on_undefined)?;
+        }
+        Ok(())
+    }
+
+    /// The modules that are not imported when testing for the platform
+    pub fn skipped_imports(&self, platform: Platform) -> Vec<&str> {
+        self.imports
+            .iter()
+            .filter(|import| !import.applies_to(platform))
+            .map(Import::module)
+            .collect()
+    }
+
+    /// The options for the given test (the default options if there are none)
+    pub fn options(&self, test_name: &str) -> TestOptions {
+        self.tests.get(test_name).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_defines_tests() {
+        let manifest = TestManifest::from_yaml(
+            "commands:\n  - mytool --help\nimports:\n  - mypackage\nrequires:\n  - pytest\n",
+        )
+        .unwrap();
+        assert!(manifest.defines_tests());
+        assert_eq!(manifest.requires, vec!["pytest".to_string()]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let files = manifest
+            .write_test_files(dir.path(), Platform::Linux64)
+            .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("run_test.sh"),
+                dir.path().join("run_test.py")
+            ]
+        );
+        let imports = std::fs::read_to_string(dir.path().join("run_test.py")).unwrap();
+        assert!(imports.contains("import mypackage"));
+
+        let selected = TestManifest::from_yaml(
+            "imports:\n  - mypackage\n  - module: mypackage.cuda\n    platforms: [linux-64]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            selected.skipped_imports(Platform::Linux64),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            selected.skipped_imports(Platform::OsxArm64),
+            ["mypackage.cuda"]
+        );
+        let dir = tempfile::tempdir().unwrap();
+        selected
+            .write_test_files(dir.path(), Platform::OsxArm64)
+            .unwrap();
+        let imports = std::fs::read_to_string(dir.path().join("run_test.py")).unwrap();
+        assert!(imports.contains("import mypackage\n"));
+        assert!(!imports.contains("mypackage.cuda"));
+
+        let options_only = TestManifest::from_yaml(
+            "tests:\n  run_test.py:\n    requires_gpu: true\n  run_test.sh:\n    allowed_exit_codes: [2]\n",
+        )
+        .unwrap();
+        assert!(!options_only.defines_tests());
+        assert!(options_only.options("run_test.py").requires_gpu);
+        assert_eq!(options_only.options("run_test.sh").allowed_exit_codes, [2]);
+    }
+
+    #[test]
+    fn variables_in_commands_are_expanded() {
+        let variables = HashMap::from([
+            ("PREFIX".to_string(), "/opt/env".to_string()),
+            ("PKG_VERSION".to_string(), "1.2".to_string()),
+        ]);
+        let mut manifest = TestManifest::from_yaml(
+            "commands:\n  - test -f ${PREFIX}/lib/${PKG_VERSION}/${UNKNOWN} && echo ${x:-y} $PREFIX\n",
+        )
+        .unwrap();
+        let mut strict = manifest.clone();
+        manifest.expand_variables(&variables, false).unwrap();
+        assert_eq!(
+            manifest.commands,
+            ["test -f /opt/env/lib/1.2/${UNKNOWN} && echo ${x:-y} $PREFIX"]
+        );
+
+        assert!(matches!(
+            strict.expand_variables(&variables, true),
+            Err(TestError::UndefinedVariable { name, .. }) if name == "UNKNOWN"
+        ));
+    }
+
+    #[test]
+    fn output_expectations_are_checked() {
+        let manifest = TestManifest::from_yaml(
+            "tests:\n  run_test.sh:\n    expect_output:\n      - contains: mytool 1.2\n      - matches: '^usage: mytool'\n        not_contains: Deprecation\n",
+        )
+        .unwrap();
+        let expectations = manifest.options("run_test.sh").expect_output;
+        assert_eq!(expectations.len(), 2);
+
+        let output = "mytool 1.2\nusage: mytool [OPTIONS]\n";
+        for expectation in &expectations {
+            assert_eq!(expectation.check(output).unwrap(), None);
+        }
+
+        let (unmet, lines) = expectations[0].check("mytool 1.1\n").unwrap().unwrap();
+        assert_eq!(unmet, r#"contains "mytool 1.2""#);
+        assert_eq!(lines, ["mytool 1.1"]);
+
+        let (unmet, lines) = expectations[1]
+            .check("usage: mytool\nDeprecationWarning: old\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(unmet, r#"does not contain "Deprecation""#);
+        assert_eq!(lines, ["DeprecationWarning: old"]);
+
+        let invalid = OutputExpectation {
+            matches: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(invalid.check(""), Err(TestError::RegexError(_))));
+    }
+}
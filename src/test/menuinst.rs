@@ -0,0 +1,288 @@
+//! Check the menuinst metadata (`Menu/*.json`) of a package
+//!
+//! Packages that create shortcuts ship the description of their menu entries as JSON files in
+//! the `Menu` folder. Both the legacy format (`menu_items`, with `${PREFIX}` placeholders) and
+//! the current format (`menus`, with `{{ PREFIX }}` placeholders and platform specific
+//! overrides) are understood. Every menu needs a name, every entry a name and a command, and
+//! every icon or command that refers to a file in the prefix has to exist in the test
+//! environment.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::Platform;
+use serde_json::{Map, Value};
+
+use super::TestError;
+
+/// The keys of a legacy menu entry that contain the command of the shortcut
+const LEGACY_COMMAND_KEYS: &[&str] = &["script", "pyscript", "pywscript", "system"];
+
+/// The menu files of the package (relative to the prefix)
+fn menu_files(package_folder: &Path) -> Result<Vec<PathBuf>, TestError> {
+    let menu_dir = package_folder.join("Menu");
+    if !menu_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&menu_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            if let Some(file_name) = path.file_name() {
+                files.push(Path::new("Menu").join(file_name));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The values of the placeholders that refer to locations in the prefix
+fn placeholders(prefix: &Path, platform: Platform) -> Vec<(&'static str, String)> {
+    let path = |relative: &str| prefix.join(relative).to_string_lossy().to_string();
+    let (bin_dir, scripts_dir, python, icon_ext) = if platform.is_windows() {
+        (
+            path("Library/bin"),
+            path("Scripts"),
+            path("python.exe"),
+            "ico",
+        )
+    } else if platform.is_osx() {
+        (path("bin"), path("bin"), path("bin/python"), "icns")
+    } else {
+        (path("bin"), path("bin"), path("bin/python"), "png")
+    };
+
+    let prefix = prefix.to_string_lossy().to_string();
+    vec![
+        ("PREFIX", prefix.clone()),
+        ("BASE_PREFIX", prefix),
+        ("MENU_DIR", path("Menu")),
+        ("BIN_DIR", bin_dir),
+        ("SCRIPTS_DIR", scripts_dir.clone()),
+        ("PYTHON_SCRIPTS", scripts_dir),
+        ("PYTHON", python),
+        ("ICON_EXT", icon_ext.to_string()),
+    ]
+}
+
+/// Replace the placeholders (`{{ NAME }}` and the legacy `${NAME}`) in the value
+fn expand(value: &str, placeholders: &[(&str, String)]) -> String {
+    let mut expanded = value.to_string();
+    for (name, replacement) in placeholders {
+        for pattern in [
+            format!("{{{{ {} }}}}", name),
+            format!("{{{{{}}}}}", name),
+            format!("${{{}}}", name),
+        ] {
+            expanded = expanded.replace(&pattern, replacement);
+        }
+    }
+    expanded
+}
+
+/// The name of the platform in the `platforms` section of a menu entry
+fn menu_platform(platform: Platform) -> &'static str {
+    if platform.is_windows() {
+        "win"
+    } else if platform.is_osx() {
+        "osx"
+    } else {
+        "linux"
+    }
+}
+
+/// The entry with the overrides for the platform applied. Returns `None` if the entry is not
+/// installed on the platform.
+fn entry_for_platform(
+    entry: &Map<String, Value>,
+    platform: Platform,
+) -> Option<Map<String, Value>> {
+    let mut entry = entry.clone();
+    let Some(platforms) = entry.remove("platforms") else {
+        return Some(entry);
+    };
+    match platforms.get(menu_platform(platform))? {
+        Value::Object(overrides) => {
+            entry.extend(overrides.clone());
+            Some(entry)
+        }
+        // e.g. `"linux": null` to install the entry without overrides
+        _ => Some(entry),
+    }
+}
+
+/// Check a single menu file, and return the problems as `(entry, problem)`
+fn check_menu(
+    file: &str,
+    contents: &str,
+    prefix: &Path,
+    platform: Platform,
+) -> Vec<(String, String)> {
+    let mut problems = Vec::new();
+    let menu: Value = match serde_json::from_str(contents) {
+        Ok(menu) => menu,
+        Err(e) => return vec![(file.to_string(), format!("invalid JSON: {}", e))],
+    };
+
+    if !menu.get("menu_name").is_some_and(Value::is_string) {
+        problems.push((file.to_string(), "`menu_name` is missing".to_string()));
+    }
+    let (entries, legacy) = match (menu.get("menus"), menu.get("menu_items")) {
+        (Some(Value::Array(entries)), _) => (entries, false),
+        (None, Some(Value::Array(entries))) => (entries, true),
+        _ => {
+            problems.push((
+                file.to_string(),
+                "neither `menus` nor `menu_items` is a list of entries".to_string(),
+            ));
+            return problems;
+        }
+    };
+
+    let placeholders = placeholders(prefix, platform);
+    // only paths that point into the prefix can be checked
+    let check_path = |path: &str| -> Option<String> {
+        let expanded = PathBuf::from(expand(path, &placeholders));
+        if expanded.starts_with(prefix) && !expanded.exists() {
+            Some(format!("{} does not exist", expanded.display()))
+        } else {
+            None
+        }
+    };
+
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(entry) = entry.as_object() else {
+            problems.push((format!("{}: entry {}", file, index), "not an object".to_string()));
+            continue;
+        };
+        let name = match entry.get("name").and_then(Value::as_str) {
+            Some(name) => format!("{}: {}", file, name),
+            None => {
+                problems.push((
+                    format!("{}: entry {}", file, index),
+                    "`name` is missing".to_string(),
+                ));
+                format!("{}: entry {}", file, index)
+            }
+        };
+        let Some(entry) = entry_for_platform(entry, platform) else {
+            continue;
+        };
+
+        let command = if legacy {
+            LEGACY_COMMAND_KEYS
+                .iter()
+                .find_map(|key| entry.get(*key).and_then(Value::as_str))
+                .map(ToString::to_string)
+        } else {
+            entry
+                .get("command")
+                .and_then(Value::as_array)
+                .and_then(|command| command.first())
+                .and_then(Value::as_str)
+                .map(ToString::to_string)
+        };
+        match command {
+            Some(command) => {
+                problems.extend(check_path(&command).map(|problem| (name.clone(), problem)))
+            }
+            None => problems.push((name.clone(), "the command is missing".to_string())),
+        }
+
+        if let Some(icon) = entry.get("icon").and_then(Value::as_str) {
+            problems.extend(check_path(icon).map(|problem| (name.clone(), problem)));
+        }
+    }
+
+    problems
+}
+
+/// Check the menu files of the package (in the package folder) that are installed into the
+/// prefix. Returns the problems as `(entry, problem)`.
+pub(crate) fn check_menus(
+    prefix: &Path,
+    package_folder: &Path,
+    platform: Platform,
+) -> Result<Vec<(String, String)>, TestError> {
+    let mut problems = Vec::new();
+    for file in menu_files(package_folder)? {
+        let name = file.to_string_lossy().replace('\\', "/");
+        let contents = match fs::read_to_string(prefix.join(&file)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                problems.push((name, "not installed in the test environment".to_string()));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        problems.extend(check_menu(&name, &contents, prefix, platform));
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_entries_are_checked() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        fs::create_dir_all(prefix.join("Menu")).unwrap();
+        fs::create_dir_all(prefix.join("bin")).unwrap();
+        fs::write(prefix.join("bin/mytool"), "").unwrap();
+        fs::write(prefix.join("Menu/mytool.png"), "").unwrap();
+
+        let menu = r#"{
+            "menu_name": "My Tool",
+            "menus": [
+                {
+                    "name": "My Tool",
+                    "command": ["{{ PREFIX }}/bin/mytool", "--gui"],
+                    "icon": "{{ MENU_DIR }}/mytool.{{ ICON_EXT }}",
+                    "platforms": {"linux": {}, "win": {"command": ["{{ SCRIPTS_DIR }}/mytool.exe"]}}
+                },
+                {
+                    "name": "Broken",
+                    "command": ["{{ PREFIX }}/bin/missing"],
+                    "icon": "{{ MENU_DIR }}/missing.{{ ICON_EXT }}",
+                    "platforms": {"linux": null}
+                },
+                {
+                    "name": "Windows only",
+                    "command": ["{{ PREFIX }}/Scripts/missing.exe"],
+                    "platforms": {"win": {}}
+                }
+            ]
+        }"#;
+        let problems = check_menu("Menu/mytool.json", menu, prefix, Platform::Linux64);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems
+            .iter()
+            .all(|(entry, _)| entry == "Menu/mytool.json: Broken"));
+        assert!(problems[0].1.ends_with("bin/missing does not exist"));
+
+        let legacy = r#"{"menu_name": "Legacy", "menu_items": [{"name": "Tool", "system": "${PREFIX}/bin/mytool"}, {"icon": "x.png"}]}"#;
+        let problems = check_menu("Menu/legacy.json", legacy, prefix, Platform::Linux64);
+        assert_eq!(
+            problems,
+            vec![
+                (
+                    "Menu/legacy.json: entry 1".to_string(),
+                    "`name` is missing".to_string()
+                ),
+                (
+                    "Menu/legacy.json: entry 1".to_string(),
+                    "the command is missing".to_string()
+                ),
+            ]
+        );
+
+        let problems = check_menu("Menu/invalid.json", "{", prefix, Platform::Linux64);
+        assert!(problems[0].1.starts_with("invalid JSON"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Mark a test prefix as read-only while the tests are running
+//!
+//! Tests are not supposed to write into the installed prefix. To enforce this, all files and
+//! directories of the prefix can be made read-only for the duration of the tests. The original
+//! permissions are restored when the guard is dropped (or explicitly restored).
+//!
+//! The permissions do not stop processes that run as root (or with `CAP_DAC_OVERRIDE`), so a
+//! test that runs as root can still write into the prefix without being noticed.
+
+use std::{
+    fs::{self, Permissions},
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+/// Keeps a prefix read-only and remembers the original permissions of all entries
+pub(crate) struct ReadOnlyPrefix {
+    prefix: PathBuf,
+    exclude: Option<PathBuf>,
+    original_permissions: Vec<(PathBuf, Permissions)>,
+}
+
+#[cfg(unix)]
+fn read_only_permissions(permissions: &Permissions) -> Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    Permissions::from_mode(permissions.mode() & !0o222)
+}
+
+#[cfg(not(unix))]
+fn read_only_permissions(permissions: &Permissions) -> Permissions {
+    let mut permissions = permissions.clone();
+    permissions.set_readonly(true);
+    permissions
+}
+
+impl ReadOnlyPrefix {
+    /// Make all entries of the prefix read-only, except for the contents of the `exclude`
+    /// directory (if it is located inside of the prefix)
+    pub fn new(prefix: &Path, exclude: Option<&Path>) -> Result<Self, std::io::Error> {
+        let mut guard = Self {
+            prefix: prefix.to_path_buf(),
+            exclude: exclude.map(Path::to_path_buf),
+            original_permissions: Vec::new(),
+        };
+        guard.apply()?;
+        Ok(guard)
+    }
+
+    /// (Re-)apply the read-only permissions to all entries of the prefix
+    pub fn apply(&mut self) -> Result<(), std::io::Error> {
+        if !self.original_permissions.is_empty() {
+            return Ok(());
+        }
+
+        for entry in WalkDir::new(&self.prefix).follow_links(false) {
+            let entry = entry?;
+            // the permissions of symlinks cannot be changed without following them
+            if entry.path_is_symlink() {
+                continue;
+            }
+            if matches!(&self.exclude, Some(exclude) if entry.path().starts_with(exclude)) {
+                continue;
+            }
+            let permissions = entry.metadata()?.permissions();
+            self.original_permissions
+                .push((entry.path().to_path_buf(), permissions));
+        }
+
+        for (path, permissions) in &self.original_permissions {
+            fs::set_permissions(path, read_only_permissions(permissions))?;
+        }
+
+        Ok(())
+    }
+
+    /// Restore the original permissions of all entries of the prefix
+    pub fn restore(&mut self) -> Result<(), std::io::Error> {
+        // restore in reverse order so that directories are writable again last
+        while let Some((path, permissions)) = self.original_permissions.pop() {
+            fs::set_permissions(&path, permissions)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ReadOnlyPrefix {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            tracing::warn!(
+                "Failed to restore the permissions of {:?}: {}",
+                self.prefix,
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Detect files that were created, modified or removed by a test
+//!
+//! A snapshot of a directory records the size and modification time of every file. After the
+//! test ran, the snapshot is compared to the current state of the directory. Snapshots of
+//! directories outside of the test environment (e.g. the home directory) leave out the
+//! directories of the test and skip files that cannot be read.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use walkdir::WalkDir;
+
+/// Side effects that are always allowed, e.g. the bytecode that Python writes when importing
+const DEFAULT_ALLOWED_SIDE_EFFECTS: &[&str] = &["**/__pycache__", "**/__pycache__/**", "**/*.pyc"];
+
+/// Build a glob set from the side effects that a test declares, including the defaults
+pub(crate) fn allowed_side_effects(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in DEFAULT_ALLOWED_SIDE_EFFECTS
+        .iter()
+        .copied()
+        .chain(patterns.iter().map(String::as_str))
+    {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+/// The state of all files in a directory at a point in time
+pub(crate) struct DirectorySnapshot {
+    root: PathBuf,
+    excluded: Vec<PathBuf>,
+    skip_unreadable: bool,
+    entries: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+fn collect_entries(
+    root: &Path,
+    excluded: &[PathBuf],
+    skip_unreadable: bool,
+) -> Result<HashMap<PathBuf, (u64, Option<SystemTime>)>, std::io::Error> {
+    let mut entries = HashMap::new();
+    if !root.exists() {
+        return Ok(entries);
+    }
+
+    let walker = WalkDir::new(root)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|entry| !excluded.iter().any(|path| entry.path().starts_with(path)));
+    for entry in walker {
+        // e.g. files of other users in the home directory
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) if skip_unreadable => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) if skip_unreadable => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let relative = entry
+            .path()
+            .strip_prefix(root)
+            .expect("walkdir entries are always inside of the root")
+            .to_path_buf();
+        entries.insert(relative, (metadata.len(), metadata.modified().ok()));
+    }
+
+    Ok(entries)
+}
+
+impl DirectorySnapshot {
+    /// Take a snapshot of the given directory
+    pub fn new(root: &Path) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            root: root.to_path_buf(),
+            excluded: Vec::new(),
+            skip_unreadable: false,
+            entries: collect_entries(root, &[], false)?,
+        })
+    }
+
+    /// Take a snapshot of a directory outside of the test environment, leaving out the given
+    /// directories (e.g. the test prefix, if it is inside of the directory)
+    pub fn outside(root: &Path, excluded: &[PathBuf]) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            root: root.to_path_buf(),
+            excluded: excluded.to_vec(),
+            skip_unreadable: true,
+            entries: collect_entries(root, excluded, true)?,
+        })
+    }
+
+    /// Compare the snapshot with the current state of the directory and return the (absolute)
+    /// paths of all files that were created, modified or removed and that are not allowed.
+    pub fn unexpected_changes(&self, allowed: &GlobSet) -> Result<Vec<PathBuf>, std::io::Error> {
+        let current = collect_entries(&self.root, &self.excluded, self.skip_unreadable)?;
+
+        let mut changed = current
+            .iter()
+            .filter(|(path, state)| self.entries.get(*path) != Some(*state))
+            .map(|(path, _)| path)
+            .chain(
+                self.entries
+                    .keys()
+                    .filter(|path| !current.contains_key(*path)),
+            )
+            .filter(|path| !allowed.is_match(path))
+            .map(|path| self.root.join(path))
+            .collect::<Vec<_>>();
+        changed.sort();
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excluded_directories_are_not_checked() {
+        let home = tempfile::tempdir().unwrap();
+        let prefix = home.path().join("envs/test");
+        std::fs::create_dir_all(&prefix).unwrap();
+
+        let snapshot = DirectorySnapshot::outside(home.path(), &[prefix.clone()]).unwrap();
+        std::fs::write(prefix.join("created-by-test"), "").unwrap();
+        std::fs::write(home.path().join(".bashrc"), "").unwrap();
+
+        let allowed = allowed_side_effects(&[]).unwrap();
+        assert_eq!(
+            snapshot.unexpected_changes(&allowed).unwrap(),
+            vec![home.path().join(".bashrc")]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Verify the signature of a package before it is tested
+//!
+//! A signed package contains the ed25519 signature of its metadata in `info/signature.json`:
+//!
+//! ```json
+//! {"signature": "<hex encoded signature>"}
+//! ```
+//!
+//! The signed message is the contents of `info/index.json` followed by the contents of
+//! `info/paths.json`. As the paths record the SHA256 hash of every file of the package, the
+//! files of the payload are hashed and compared with the signed paths as well.
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::package::{ArchiveType, PackageFile, PathType, PathsJson};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{package_file_contents, TestError};
+
+/// The public key that the signature of a package is verified with
+pub type PublicKey = ed25519_dalek::VerifyingKey;
+
+/// The file that contains the signature of the package
+const SIGNATURE_FILE: &str = "info/signature.json";
+
+#[derive(Debug, Deserialize)]
+struct SignatureFile {
+    /// The hex encoded ed25519 signature
+    signature: String,
+}
+
+/// Read a file of the `info` folder, a missing file fails the verification
+fn read_info_file(
+    archive_type: ArchiveType,
+    package_file: &Path,
+    path: &str,
+    failed: &impl Fn(String) -> TestError,
+) -> Result<Vec<u8>, TestError> {
+    let contents = package_file_contents(archive_type, package_file, Path::new(path), |reader| {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        Ok(contents)
+    });
+    match contents {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(failed(format!("the package does not contain {}", path)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Hash the files of the payload and compare them with the (signed) hashes from the paths
+fn verify_payload<R: Read>(
+    mut archive: tar::Archive<R>,
+    mut expected: HashMap<PathBuf, String>,
+    failed: &impl Fn(String) -> TestError,
+) -> Result<(), TestError> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some(expected) = expected.remove(&path) else {
+            continue;
+        };
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut entry, &mut hasher)?;
+        if format!("{:x}", hasher.finalize()) != expected {
+            return Err(failed(format!(
+                "the hash of {} does not match the signed paths",
+                path.display()
+            )));
+        }
+    }
+
+    match expected.keys().next() {
+        Some(path) => Err(failed(format!(
+            "{} is missing in the package",
+            path.display()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Verify the signature of the package with the key, without extracting the package
+pub(crate) fn verify_signature(package_file: &Path, key: &PublicKey) -> Result<(), TestError> {
+    let archive_type =
+        ArchiveType::try_from(package_file).ok_or(TestError::ArchiveTypeNotSupported)?;
+    let failed = |reason: String| TestError::SignatureVerificationFailed {
+        package: package_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        reason,
+    };
+
+    let signature_file: SignatureFile = serde_json::from_slice(&read_info_file(
+        archive_type,
+        package_file,
+        SIGNATURE_FILE,
+        &failed,
+    )?)?;
+    let signature = hex::decode(signature_file.signature.trim())
+        .ok()
+        .and_then(|bytes| ed25519_dalek::Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| failed("the signature is not a hex encoded ed25519 signature".into()))?;
+
+    let mut message = read_info_file(archive_type, package_file, "info/index.json", &failed)?;
+    let paths = read_info_file(archive_type, package_file, "info/paths.json", &failed)?;
+    message.extend_from_slice(&paths);
+    key.verify_strict(&message, &signature)
+        .map_err(|_| failed("the signature does not match the key".into()))?;
+
+    let paths = PathsJson::from_str(&String::from_utf8_lossy(&paths))?;
+    let expected = paths
+        .paths
+        .iter()
+        .filter(|entry| entry.path_type == PathType::HardLink)
+        .filter_map(|entry| {
+            let sha256 = entry.sha256?;
+            Some((entry.relative_path.clone(), format!("{:x}", sha256)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let reader = std::io::BufReader::new(std::fs::File::open(package_file)?);
+    match archive_type {
+        ArchiveType::TarBz2 => verify_payload(
+            rattler_package_streaming::read::stream_tar_bz2(reader),
+            expected,
+            &failed,
+        ),
+        ArchiveType::Conda => verify_payload(
+            rattler_package_streaming::seek::stream_conda_content(reader)?,
+            expected,
+            &failed,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    fn signed_package(dir: &Path, key: &SigningKey, payload: &str) -> PathBuf {
+        let source = dir.join("source");
+        fs::create_dir_all(source.join("info")).unwrap();
+        fs::create_dir_all(source.join("bin")).unwrap();
+        let index = r#"{"name": "hello", "version": "1.0", "build": "0", "build_number": 0}"#;
+        let paths = format!(
+            r#"{{"paths": [{{"_path": "bin/hello", "path_type": "hardlink", "sha256": "{:x}", "size_in_bytes": 5}}], "paths_version": 1}}"#,
+            Sha256::digest(b"hello")
+        );
+        let signature = key.sign(format!("{}{}", index, paths).as_bytes());
+        fs::write(source.join("info/index.json"), index).unwrap();
+        fs::write(source.join("info/paths.json"), paths).unwrap();
+        fs::write(
+            source.join(SIGNATURE_FILE),
+            format!(
+                r#"{{"signature": "{}"}}"#,
+                hex::encode(signature.to_bytes())
+            ),
+        )
+        .unwrap();
+        fs::write(source.join("bin/hello"), payload).unwrap();
+
+        let files = [
+            "info/index.json",
+            "info/paths.json",
+            SIGNATURE_FILE,
+            "bin/hello",
+        ]
+        .iter()
+        .map(|path| source.join(path))
+        .collect::<Vec<_>>();
+        let package = dir.join("hello-1.0-0.tar.bz2");
+        rattler_package_streaming::write::write_tar_bz2_package(
+            fs::File::create(&package).unwrap(),
+            &source,
+            &files,
+            rattler_package_streaming::write::CompressionLevel::Default,
+            None,
+        )
+        .unwrap();
+        package
+    }
+
+    #[test]
+    fn signatures_are_verified() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let other_key = SigningKey::from_bytes(&[8; 32]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let package = signed_package(dir.path(), &key, "hello");
+        verify_signature(&package, &key.verifying_key()).unwrap();
+        assert!(matches!(
+            verify_signature(&package, &other_key.verifying_key()),
+            Err(TestError::SignatureVerificationFailed { .. })
+        ));
+
+        // the metadata is signed, but the payload was modified
+        let dir = tempfile::tempdir().unwrap();
+        let package = signed_package(dir.path(), &key, "bye!!");
+        let Err(TestError::SignatureVerificationFailed { reason, .. }) =
+            verify_signature(&package, &key.verifying_key())
+        else {
+            panic!("the modified payload was not detected");
+        };
+        assert_eq!(
+            reason,
+            "the hash of bin/hello does not match the signed paths"
+        );
+    }
+}
@@ -0,0 +1,805 @@
+//! The outcomes of running the tests of a package
+
+use std::{
+    fmt::Write,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rattler_conda_types::{package::ArchiveIdentifier, Platform, Version};
+use serde::Serialize;
+
+use super::{style, CommandOutput, TestEnvironmentMode, TestError, TestType, OUTPUT_TAIL_LINES};
+
+/// The status of a single test
+#[derive(Debug)]
+pub enum TestStatus {
+    /// The test ran successfully
+    Passed,
+    /// The test failed
+    Failed(TestError),
+    /// The test was not run, with the reason why
+    Skipped(SkipReason),
+    /// The test passed, but found something that should be looked at (e.g. an entry point that
+    /// shadows a system command)
+    Warning(String),
+}
+
+/// The reason why a test was not run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Only a dry run was done
+    DryRun,
+    /// The test does not match the test filter of the configuration
+    Filtered,
+    /// The import is not selected for the platform in the test manifest
+    NotSelected(Platform),
+    /// The test needs a GPU, and none was detected
+    NoGpu,
+    /// The test needs network access, but the tests run offline
+    Offline,
+    /// Executables of the platform cannot be run on this host
+    ForeignPlatform(Platform),
+    /// The test is a script for the shell of another platform (e.g. a `run_test.bat` on Linux)
+    OtherShell(Platform),
+    /// The test was skipped in a previous run (restored from a checkpoint), with the reason
+    PreviousRun(String),
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::DryRun => write!(f, "dry run"),
+            SkipReason::Filtered => write!(f, "filtered out"),
+            SkipReason::NotSelected(platform) => write!(f, "not imported on {}", platform),
+            SkipReason::NoGpu => write!(f, "no GPU available"),
+            SkipReason::Offline => write!(f, "requires network (offline mode)"),
+            SkipReason::ForeignPlatform(platform) => {
+                write!(f, "cannot run {} executables on this host", platform)
+            }
+            SkipReason::OtherShell(platform) => write!(f, "cannot run on {}", platform),
+            SkipReason::PreviousRun(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// The outcome of a single test case of a package
+#[derive(Debug)]
+pub struct TestCaseOutcome {
+    /// The name of the test (e.g. `run_test.py`)
+    pub name: String,
+    /// The type of the test
+    pub test_type: TestType,
+    /// The status of the test
+    pub status: TestStatus,
+    /// How long it took to run the test (zero for skipped tests)
+    pub duration: Duration,
+    /// The exit code of the test command, if a command was run and it exited normally
+    pub exit_code: Option<i32>,
+    /// The captured standard output of the test command
+    pub stdout: String,
+    /// The captured standard error of the test command
+    pub stderr: String,
+    /// The Python version of the environment in which the test was run, if the test was run
+    /// for one of the additional Python versions of the configuration
+    pub python_version: Option<Version>,
+    /// Whether the test was run in the full or in the minimal test environment
+    pub environment_mode: TestEnvironmentMode,
+    /// The log file that the output of the test command was written to (if any). If the log is
+    /// compressed, the output is only kept in the log, and `stdout` and `stderr` are empty.
+    pub log: Option<TestLog>,
+}
+
+/// A log file with the captured output of a test command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TestLog {
+    /// The path of the log file
+    pub path: PathBuf,
+    /// The size of the log file in bytes
+    pub size: u64,
+}
+
+impl TestLog {
+    /// Returns true if the log is gzip compressed (see
+    /// [`TestConfiguration::compress_logs`](super::TestConfiguration::compress_logs))
+    pub fn is_compressed(&self) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|extension| extension == "gz")
+    }
+
+    /// Read the contents of the log, a compressed log is decompressed
+    pub fn read(&self) -> std::io::Result<String> {
+        let file = fs_err::File::open(&self.path)?;
+        let mut contents = Vec::new();
+        if self.is_compressed() {
+            flate2::read::GzDecoder::new(file).read_to_end(&mut contents)?;
+        } else {
+            std::io::BufReader::new(file).read_to_end(&mut contents)?;
+        }
+        Ok(String::from_utf8_lossy(&contents).to_string())
+    }
+}
+
+impl TestCaseOutcome {
+    /// The outcome of a test that was not run
+    pub fn skipped(name: String, test_type: TestType, reason: SkipReason) -> Self {
+        Self {
+            name,
+            test_type,
+            status: TestStatus::Skipped(reason),
+            duration: Duration::ZERO,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            python_version: None,
+            environment_mode: TestEnvironmentMode::default(),
+            log: None,
+        }
+    }
+
+    /// The name of the test, together with the Python version it was run with (if any)
+    pub fn display_name(&self) -> String {
+        match &self.python_version {
+            Some(version) => format!("{} (python {})", self.name, version),
+            None => self.name.clone(),
+        }
+    }
+
+    /// The outcome of a test that was run, with the captured output of its command (if any)
+    pub fn new(
+        name: String,
+        test_type: TestType,
+        status: TestStatus,
+        duration: Duration,
+        output: Option<CommandOutput>,
+    ) -> Self {
+        let output = output.unwrap_or_default();
+        // the output of a compressed log is not kept in memory
+        let (stdout, stderr) = match &output.log {
+            Some(log) if log.is_compressed() => (String::new(), String::new()),
+            _ => (output.stdout, output.stderr),
+        };
+        Self {
+            name,
+            test_type,
+            status,
+            duration,
+            exit_code: output.exit_code,
+            stdout,
+            stderr,
+            python_version: None,
+            environment_mode: TestEnvironmentMode::default(),
+            log: output.log,
+        }
+    }
+}
+
+/// The outcomes of all test cases of a package
+#[derive(Debug)]
+pub struct TestResult {
+    /// The package that was tested
+    pub package: ArchiveIdentifier,
+    /// The outcome of every test case, in the order in which the tests were run
+    pub outcomes: Vec<TestCaseOutcome>,
+    /// The wall-clock time it took to test the package, including the creation of the test
+    /// environment (zero if it is not known, e.g. for results from a checkpoint)
+    pub total_duration: Duration,
+    /// True if the result is from a dry run, in which no tests were run
+    pub dry_run: bool,
+    /// True if the package contains no tests at all, so that nothing was tested (instead of
+    /// all tests having passed)
+    pub no_tests: bool,
+    /// The SHA256 hash of the tested package file (if it is known)
+    pub sha256: Option<String>,
+    /// The size of the tested package file in bytes (if it is known)
+    pub size: Option<u64>,
+}
+
+/// The name of a test type in the duration summary
+fn test_type_label(test_type: TestType) -> &'static str {
+    match test_type {
+        TestType::Commands => "commands",
+        TestType::Python => "imports",
+        TestType::Files => "files",
+        TestType::R => "r",
+        TestType::Interpreted => "interpreted",
+        TestType::Custom => "custom",
+        TestType::PipCheck => "pip_check",
+        TestType::Linkage => "linkage",
+        TestType::EntryPoints => "entry_points",
+        TestType::MenuInst => "menuinst",
+        TestType::TestSuite => "test_suite",
+        TestType::Metadata => "metadata",
+        TestType::InstalledFiles => "installed_files",
+        TestType::PrefixCheck => "prefix_check",
+    }
+}
+
+impl TestResult {
+    /// Create an empty result for the given package
+    pub fn new(package: ArchiveIdentifier) -> Self {
+        Self {
+            package,
+            outcomes: Vec::new(),
+            total_duration: Duration::ZERO,
+            dry_run: false,
+            no_tests: false,
+            sha256: None,
+            size: None,
+        }
+    }
+
+    /// Returns true if none of the tests failed
+    pub fn all_passed(&self) -> bool {
+        !self
+            .outcomes
+            .iter()
+            .any(|outcome| matches!(outcome.status, TestStatus::Failed(_)))
+    }
+
+    /// The total time it took to run all tests
+    pub fn duration(&self) -> Duration {
+        self.outcomes.iter().map(|outcome| outcome.duration).sum()
+    }
+
+    /// A one-line summary of how long the tests of every type took, e.g.
+    /// `imports: 1.2s, commands: 14.7s (total 16.1s)`. The total includes the creation of the
+    /// test environment if it is known.
+    pub fn duration_summary(&self) -> String {
+        let mut durations: Vec<(TestType, Duration)> = Vec::new();
+        for outcome in &self.outcomes {
+            match durations
+                .iter_mut()
+                .find(|(test_type, _)| *test_type == outcome.test_type)
+            {
+                Some((_, duration)) => *duration += outcome.duration,
+                None => durations.push((outcome.test_type, outcome.duration)),
+            }
+        }
+
+        let total = if self.total_duration.is_zero() {
+            self.duration()
+        } else {
+            self.total_duration
+        };
+        let durations = durations
+            .iter()
+            .map(|(test_type, duration)| {
+                format!(
+                    "{}: {:.1}s",
+                    test_type_label(*test_type),
+                    duration.as_secs_f64()
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{} (total {:.1}s)",
+            durations.join(", "),
+            total.as_secs_f64()
+        )
+    }
+
+    fn count(&self, predicate: impl Fn(&TestStatus) -> bool) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| predicate(&outcome.status))
+            .count()
+    }
+
+    /// Render a human readable, multi-line summary of all test outcomes. The summary is colored
+    /// if the terminal supports it (and `NO_COLOR` is not set).
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        let _ = writeln!(
+            summary,
+            "Test results for {}-{}-{}{}:",
+            self.package.name,
+            self.package.version,
+            self.package.build_string,
+            if self.dry_run { " (dry run)" } else { "" }
+        );
+
+        for outcome in &self.outcomes {
+            let _ = match &outcome.status {
+                TestStatus::Passed => writeln!(
+                    summary,
+                    " {} {} ({:.2?})",
+                    style(console::Emoji("✔", "+")).green(),
+                    outcome.display_name(),
+                    outcome.duration
+                ),
+                TestStatus::Failed(error) => writeln!(
+                    summary,
+                    " {} {} ({:.2?}): {}",
+                    style(console::Emoji("✘", "x")).red(),
+                    outcome.display_name(),
+                    outcome.duration,
+                    error
+                ),
+                TestStatus::Skipped(reason) => writeln!(
+                    summary,
+                    " {} {} (skipped: {})",
+                    style(console::Emoji("⤼", "-")).yellow(),
+                    outcome.display_name(),
+                    reason
+                ),
+                TestStatus::Warning(warning) => writeln!(
+                    summary,
+                    " {} {} ({:.2?}): {}",
+                    style(console::Emoji("⚠", "!")).yellow(),
+                    outcome.display_name(),
+                    outcome.duration,
+                    warning
+                ),
+            };
+        }
+
+        summary.push_str(&self.counts());
+        summary
+    }
+
+    /// A single line with the package and the number of passed, failed and skipped tests, e.g.
+    /// `mypkg-1.0-0: 3 passed, 0 failed, 1 skipped in 2.31s`. Tests with a warning count as
+    /// passed.
+    pub fn one_line_summary(&self) -> String {
+        format!(
+            "{}-{}-{}: {}",
+            self.package.name,
+            self.package.version,
+            self.package.build_string,
+            self.counts()
+        )
+    }
+
+    fn counts(&self) -> String {
+        if self.no_tests {
+            return style("no tests").yellow().to_string();
+        }
+        format!(
+            "{} passed, {} failed, {} skipped in {:.2?}",
+            style(self.count(|s| matches!(s, TestStatus::Passed | TestStatus::Warning(_)))).green(),
+            style(self.count(|s| matches!(s, TestStatus::Failed(_)))).red(),
+            style(self.count(|s| matches!(s, TestStatus::Skipped(_)))).yellow(),
+            self.duration(),
+        )
+    }
+
+    /// Turn the result into an error if any of the tests failed
+    pub fn into_result(self) -> Result<Self, TestError> {
+        if self.all_passed() {
+            return Ok(self);
+        }
+
+        let mut failures = self
+            .outcomes
+            .into_iter()
+            .filter_map(|outcome| match outcome.status {
+                TestStatus::Failed(error) => Some((outcome.display_name(), error)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if failures.len() == 1 {
+            Err(failures.remove(0).1)
+        } else {
+            Err(TestError::TestsFailed(failures))
+        }
+    }
+}
+
+/// Escape the special characters of XML in text and attribute values
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // control characters are not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render the results as a JUnit XML report (e.g. for GitLab or Jenkins). Every package is a
+/// `<testsuite>` and every test of the package a `<testcase>`. Failed tests carry the error, the
+/// exit code and the captured output in a `<failure>` element, skipped tests a `<skipped>`
+/// element with the reason.
+pub fn to_junit_xml(results: &[TestResult]) -> String {
+    let failures = |result: &TestResult| result.count(|s| matches!(s, TestStatus::Failed(_)));
+    let skipped = |result: &TestResult| result.count(|s| matches!(s, TestStatus::Skipped(_)));
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        results.iter().map(|r| r.outcomes.len()).sum::<usize>(),
+        results.iter().map(failures).sum::<usize>(),
+        results.iter().map(skipped).sum::<usize>(),
+        results
+            .iter()
+            .map(|r| r.duration().as_secs_f64())
+            .sum::<f64>(),
+    );
+
+    for result in results {
+        let suite = escape_xml(&format!(
+            "{}-{}-{}",
+            result.package.name, result.package.version, result.package.build_string
+        ));
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            suite,
+            result.outcomes.len(),
+            failures(result),
+            skipped(result),
+            result.duration().as_secs_f64(),
+        );
+
+        for outcome in &result.outcomes {
+            let _ = write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape_xml(&outcome.display_name()),
+                suite,
+                outcome.duration.as_secs_f64(),
+            );
+            match &outcome.status {
+                TestStatus::Passed => {
+                    let _ = writeln!(xml, "/>");
+                }
+                // JUnit has no warnings, the test passed with the warning as its output
+                TestStatus::Warning(warning) => {
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <system-out>warning: {}</system-out>\n    </testcase>",
+                        escape_xml(warning)
+                    );
+                }
+                TestStatus::Skipped(reason) => {
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <skipped message=\"{}\"/>\n    </testcase>",
+                        escape_xml(&reason.to_string())
+                    );
+                }
+                TestStatus::Failed(error) => {
+                    let exit_code = outcome
+                        .exit_code
+                        .map(|code| code.to_string())
+                        .unwrap_or_else(|| "none".to_string());
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <failure message=\"{}\">exit code: {}\n{}</failure>",
+                        escape_xml(&error.to_string()),
+                        exit_code,
+                        escape_xml(&outcome.stdout),
+                    );
+                    if !outcome.stderr.is_empty() {
+                        let _ = writeln!(
+                            xml,
+                            "      <system-err>{}</system-err>",
+                            escape_xml(&outcome.stderr)
+                        );
+                    }
+                    let _ = writeln!(xml, "    </testcase>");
+                }
+            }
+        }
+
+        let _ = writeln!(xml, "  </testsuite>");
+    }
+
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+/// The version of the schema of [`to_json_report`]. It is increased whenever a field is removed
+/// or its meaning changes (adding fields does not change the version).
+pub const JSON_REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    schema_version: u32,
+    packages: Vec<JsonPackage<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonPackage<'a> {
+    file: &'a Path,
+    name: Option<String>,
+    version: Option<String>,
+    build_string: Option<String>,
+    sha256: Option<&'a str>,
+    size: Option<u64>,
+    dry_run: bool,
+    no_tests: bool,
+    passed: bool,
+    /// set if the tests could not be run at all
+    error: Option<String>,
+    duration_ms: u64,
+    tests: Vec<JsonTestCase<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonTestCase<'a> {
+    name: &'a str,
+    test_type: &'static str,
+    status: &'static str,
+    /// the error of a failed test, the reason why a test was skipped or the warning of a test
+    message: Option<String>,
+    duration_ms: u64,
+    exit_code: Option<i32>,
+    python_version: Option<String>,
+    environment_mode: TestEnvironmentMode,
+    stdout: String,
+    stderr: String,
+    log: Option<&'a TestLog>,
+}
+
+/// The last lines of the captured output of a command
+fn output_tail(output: &str) -> String {
+    let lines = output.lines().collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..].join("\n")
+}
+
+/// Render the results as a JSON report for scripts and dashboards. Every package is an entry of
+/// `packages` (with an `error` if its tests could not be run), with the outcome of every test.
+/// The captured output of the tests is truncated to its last lines. The schema is versioned
+/// with [`JSON_REPORT_SCHEMA_VERSION`].
+pub fn to_json_report(
+    results: &[(&Path, Result<&TestResult, &TestError>)],
+) -> Result<String, serde_json::Error> {
+    let packages = results
+        .iter()
+        .map(|(file, result)| {
+            let result = match result {
+                Ok(result) => result,
+                Err(error) => {
+                    let identifier = ArchiveIdentifier::try_from_path(file);
+                    return JsonPackage {
+                        file,
+                        name: identifier.as_ref().map(|pkg| pkg.name.clone()),
+                        version: identifier.as_ref().map(|pkg| pkg.version.clone()),
+                        build_string: identifier.map(|pkg| pkg.build_string),
+                        sha256: None,
+                        size: None,
+                        dry_run: false,
+                        no_tests: false,
+                        passed: false,
+                        error: Some(error.to_string()),
+                        duration_ms: 0,
+                        tests: Vec::new(),
+                    };
+                }
+            };
+
+            let tests = result
+                .outcomes
+                .iter()
+                .map(|outcome| {
+                    let (status, message) = match &outcome.status {
+                        TestStatus::Passed => ("passed", None),
+                        TestStatus::Failed(error) => ("failed", Some(error.to_string())),
+                        TestStatus::Skipped(reason) => ("skipped", Some(reason.to_string())),
+                        TestStatus::Warning(warning) => ("warning", Some(warning.clone())),
+                    };
+                    JsonTestCase {
+                        name: &outcome.name,
+                        test_type: test_type_label(outcome.test_type),
+                        status,
+                        message,
+                        duration_ms: outcome.duration.as_millis() as u64,
+                        exit_code: outcome.exit_code,
+                        python_version: outcome.python_version.as_ref().map(ToString::to_string),
+                        environment_mode: outcome.environment_mode,
+                        stdout: output_tail(&outcome.stdout),
+                        stderr: output_tail(&outcome.stderr),
+                        log: outcome.log.as_ref(),
+                    }
+                })
+                .collect();
+
+            let duration = if result.total_duration.is_zero() {
+                result.duration()
+            } else {
+                result.total_duration
+            };
+            JsonPackage {
+                file,
+                name: Some(result.package.name.clone()),
+                version: Some(result.package.version.clone()),
+                build_string: Some(result.package.build_string.clone()),
+                sha256: result.sha256.as_deref(),
+                size: result.size,
+                dry_run: result.dry_run,
+                no_tests: result.no_tests,
+                passed: result.all_passed(),
+                error: None,
+                duration_ms: duration.as_millis() as u64,
+                tests,
+            }
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&JsonReport {
+        schema_version: JSON_REPORT_SCHEMA_VERSION,
+        packages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn junit_xml() {
+        let package =
+            ArchiveIdentifier::try_from_path(Path::new("foo-1.0-h123_0.tar.bz2")).unwrap();
+        let mut result = TestResult::new(package);
+        result.outcomes = vec![
+            TestCaseOutcome::new(
+                "run_test.sh".to_string(),
+                TestType::Commands,
+                TestStatus::Passed,
+                Duration::from_millis(1500),
+                None,
+            ),
+            TestCaseOutcome::new(
+                "run_test.py".to_string(),
+                TestType::Python,
+                TestStatus::Failed(TestError::TestFailed {
+                    test: "run_test.py".to_string(),
+                    command: "python run_test.py".to_string(),
+                    exit_code: Some(1),
+                    output_tail: String::new(),
+                }),
+                Duration::from_millis(250),
+                Some(CommandOutput {
+                    exit_code: Some(1),
+                    stdout: "ImportError: <foo>".to_string(),
+                    ..Default::default()
+                }),
+            ),
+            TestCaseOutcome::skipped(
+                "run_test.json".to_string(),
+                TestType::Files,
+                SkipReason::Filtered,
+            ),
+        ];
+
+        let xml = to_junit_xml(&[result]);
+        assert!(xml.contains(
+            "<testsuite name=\"foo-1.0-h123_0\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"1.750\">"
+        ));
+        assert!(xml.contains(
+            "<testcase name=\"run_test.sh\" classname=\"foo-1.0-h123_0\" time=\"1.500\"/>"
+        ));
+        assert!(xml.contains("exit code: 1\nImportError: &lt;foo&gt;</failure>"));
+        assert!(xml.contains("<skipped message=\"filtered out\"/>"));
+        assert!(xml.contains("message=\"test &apos;run_test.py&apos; failed (exit code 1):"));
+    }
+
+    #[test]
+    fn json_report() {
+        let file = Path::new("foo-1.0-h123_0.tar.bz2");
+        let mut result = TestResult::new(ArchiveIdentifier::try_from_path(file).unwrap());
+        result.outcomes = vec![TestCaseOutcome::new(
+            "run_test.sh".to_string(),
+            TestType::Commands,
+            TestStatus::Passed,
+            Duration::from_millis(1500),
+            Some(CommandOutput {
+                exit_code: Some(0),
+                stdout: (0..100).map(|i| format!("line {i}\n")).collect(),
+                ..Default::default()
+            }),
+        )];
+        let error = TestError::MissingPackageFileName;
+        let broken = Path::new("bar-2.0-0.conda");
+
+        let report = to_json_report(&[(file, Ok(&result)), (broken, Err(&error))]).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(report["schema_version"], JSON_REPORT_SCHEMA_VERSION);
+
+        let package = &report["packages"][0];
+        assert_eq!(package["name"], "foo");
+        assert_eq!(package["passed"], true);
+        let test = &package["tests"][0];
+        assert_eq!(test["test_type"], "commands");
+        assert_eq!(test["status"], "passed");
+        assert_eq!(test["duration_ms"], 1500);
+        assert_eq!(test["exit_code"], 0);
+        assert_eq!(test["environment_mode"], "full");
+        let stdout = test["stdout"].as_str().unwrap();
+        assert_eq!(stdout.lines().count(), 50);
+        assert!(stdout.ends_with("line 99"));
+
+        let broken = &report["packages"][1];
+        assert_eq!(broken["name"], "bar");
+        assert_eq!(broken["passed"], false);
+        assert_eq!(broken["error"], error.to_string());
+    }
+
+    #[test]
+    fn duration_summary() {
+        let package =
+            ArchiveIdentifier::try_from_path(Path::new("foo-1.0-h123_0.tar.bz2")).unwrap();
+        let mut result = TestResult::new(package);
+        for (name, test_type, millis) in [
+            ("run_test.py", TestType::Python, 1200),
+            ("run_test.sh", TestType::Commands, 14000),
+            ("pip_check", TestType::PipCheck, 300),
+            ("run_test.bat", TestType::Commands, 700),
+        ] {
+            result.outcomes.push(TestCaseOutcome::new(
+                name.to_string(),
+                test_type,
+                TestStatus::Passed,
+                Duration::from_millis(millis),
+                None,
+            ));
+        }
+        assert_eq!(
+            result.duration_summary(),
+            "imports: 1.2s, commands: 14.7s, pip_check: 0.3s (total 16.2s)"
+        );
+
+        result.total_duration = Duration::from_secs(30);
+        assert!(result.duration_summary().ends_with("(total 30.0s)"));
+        assert!(console::strip_ansi_codes(&result.one_line_summary())
+            .starts_with("foo-1.0-h123_0: 4 passed, 0 failed, 0 skipped in"));
+    }
+
+    #[test]
+    fn packages_without_tests_are_reported() {
+        let package =
+            ArchiveIdentifier::try_from_path(Path::new("foo-1.0-h123_0.tar.bz2")).unwrap();
+        let mut result = TestResult::new(package);
+        result.no_tests = true;
+        assert_eq!(
+            console::strip_ansi_codes(&result.one_line_summary()),
+            "foo-1.0-h123_0: no tests"
+        );
+        assert!(console::strip_ansi_codes(&result.summary()).ends_with("no tests"));
+    }
+
+    #[test]
+    fn warnings_are_reported() {
+        let file = Path::new("foo-1.0-h123_0.tar.bz2");
+        let mut result = TestResult::new(ArchiveIdentifier::try_from_path(file).unwrap());
+        result.outcomes = vec![TestCaseOutcome::new(
+            "entry_point_shadowing".to_string(),
+            TestType::EntryPoints,
+            TestStatus::Warning("entry points shadow well-known system commands: test".to_string()),
+            Duration::ZERO,
+            None,
+        )];
+        assert!(result.all_passed());
+        let summary = console::strip_ansi_codes(&result.summary()).to_string();
+        assert!(summary.contains("entry_point_shadowing (0.00ns): entry points shadow"));
+        assert!(summary.contains("1 passed, 0 failed, 0 skipped"));
+
+        let xml = to_junit_xml(std::slice::from_ref(&result));
+        assert!(xml.contains("failures=\"0\""));
+        assert!(xml.contains("<system-out>warning: entry points shadow"));
+
+        let report = to_json_report(&[(file, Ok(&result))]).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+        let test = &report["packages"][0]["tests"][0];
+        assert_eq!(test["status"], "warning");
+        assert_eq!(
+            test["message"],
+            "entry points shadow well-known system commands: test"
+        );
+    }
+}
@@ -25,26 +25,132 @@ pub struct Configuration {
 
     /// Whether to use bzip2
     pub use_bz2: bool,
+
+    /// The cache directory for repodata and packages (the default cache directory of rattler
+    /// if `None`)
+    pub cache_dir: Option<PathBuf>,
+
+    /// If true, no network access is allowed: only cached repodata and packages as well as local
+    /// (`file://`) channels are used
+    pub offline: bool,
+
+    /// Repodata patches (a `patch_instructions.json` file) that are applied to the repodata of
+    /// all channels before solving
+    pub repodata_patches: Option<PathBuf>,
+}
+
+impl Configuration {
+    /// The cache directory to use: the configured cache directory, or the default cache
+    /// directory of rattler
+    pub fn cache_dir(&self) -> anyhow::Result<PathBuf> {
+        match &self.cache_dir {
+            Some(cache_dir) => Ok(cache_dir.clone()),
+            None => rattler::default_cache_dir(),
+        }
+    }
+}
+
+/// The environment variables that can point to a CA bundle (a PEM file with one or more
+/// certificates) that is trusted in addition to the root certificates of the system
+const CA_BUNDLE_ENV_VARS: &[&str] = &["REQUESTS_CA_BUNDLE", "SSL_CERT_FILE", "CURL_CA_BUNDLE"];
+
+/// Create a download client that is configured from the standard environment variables: the
+/// proxies in `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` (except for the hosts in `NO_PROXY`),
+/// and the CA bundle in `REQUESTS_CA_BUNDLE`, `SSL_CERT_FILE` or `CURL_CA_BUNDLE`. The lowercase
+/// variants of the proxy variables are read as well.
+pub fn client_from_env() -> anyhow::Result<AuthenticatedClient> {
+    let mut builder = reqwest::Client::builder().no_gzip();
+
+    let env_var = |name: &str| {
+        std::env::var(name)
+            .or_else(|_| std::env::var(name.to_lowercase()))
+            .ok()
+            .filter(|value| !value.is_empty())
+    };
+    let no_proxy = reqwest::NoProxy::from_env();
+    if let Some(url) = env_var("HTTPS_PROXY") {
+        builder = builder.proxy(reqwest::Proxy::https(url)?.no_proxy(no_proxy.clone()));
+    }
+    if let Some(url) = env_var("HTTP_PROXY") {
+        builder = builder.proxy(reqwest::Proxy::http(url)?.no_proxy(no_proxy.clone()));
+    }
+    if let Some(url) = env_var("ALL_PROXY") {
+        builder = builder.proxy(reqwest::Proxy::all(url)?.no_proxy(no_proxy));
+    }
+
+    if let Some(ca_bundle) = CA_BUNDLE_ENV_VARS
+        .iter()
+        .find_map(|name| std::env::var_os(name).filter(|value| !value.is_empty()))
+    {
+        let pem = std::fs::read_to_string(&ca_bundle)?;
+        for certificate in pem_certificates(&pem) {
+            builder = builder
+                .add_root_certificate(reqwest::Certificate::from_pem(certificate.as_bytes())?);
+        }
+    }
+
+    Ok(AuthenticatedClient::from_client(
+        builder.build()?,
+        rattler_networking::AuthenticationStorage::new("rattler", &PathBuf::from("~/.rattler")),
+    ))
+}
+
+/// Split a PEM bundle into the individual certificates
+fn pem_certificates(pem: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    pem.split_inclusive(END)
+        .filter_map(|block| {
+            let start = block.find("-----BEGIN CERTIFICATE-----")?;
+            block.ends_with(END).then(|| block[start..].to_string())
+        })
+        .collect()
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
             multi_progress_indicator: indicatif::MultiProgress::new(),
-            client: AuthenticatedClient::from_client(
-                reqwest::Client::builder()
-                    .no_gzip()
-                    .build()
-                    .expect("failed to create client"),
-                rattler_networking::AuthenticationStorage::new(
-                    "rattler",
-                    &PathBuf::from("~/.rattler"),
-                ),
-            ),
+            client: client_from_env().unwrap_or_else(|e| {
+                tracing::warn!(
+                    "Could not configure the download client from the environment: {}",
+                    e
+                );
+                AuthenticatedClient::from_client(
+                    reqwest::Client::builder()
+                        .no_gzip()
+                        .build()
+                        .expect("failed to create client"),
+                    rattler_networking::AuthenticationStorage::new(
+                        "rattler",
+                        &PathBuf::from("~/.rattler"),
+                    ),
+                )
+            }),
             no_clean: false,
             no_test: false,
             use_zstd: true,
             use_bz2: true,
+            cache_dir: None,
+            offline: false,
+            repodata_patches: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::pem_certificates;
+
+    #[test]
+    fn pem_bundle_is_split() {
+        let bundle = "# first\n-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n\
+                      # second\n-----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----\n";
+        assert_eq!(
+            pem_certificates(bundle),
+            vec![
+                "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----".to_string(),
+                "-----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Windows path handling: extended-length paths to create files in deeply nested directories
+//! and the reserved device names
+
+use std::path::{Path, PathBuf};
+
+/// The maximum length of a path for the Windows APIs that do not support extended-length paths
+pub const MAX_PATH: usize = 260;
+
+/// Prefix an absolute path with `\\?\` (or `\\?\UNC\` for a network share), which lifts the
+/// MAX_PATH limit of the Windows file system APIs. Relative paths and paths that already have
+/// the prefix are returned unchanged. The prefix is only added on Windows, see
+/// [`to_extended_length`] for the conversion itself.
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    if cfg!(windows) {
+        to_extended_length(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Convert a Windows path to an extended-length path. Extended-length paths are not normalized
+/// by Windows, so forward slashes are replaced with backslashes.
+pub fn to_extended_length(path: &Path) -> PathBuf {
+    let path = path.to_string_lossy();
+    if path.starts_with(r"\\?\") {
+        return PathBuf::from(path.as_ref());
+    }
+
+    let path = path.replace('/', r"\");
+    if let Some(share) = path.strip_prefix(r"\\") {
+        return PathBuf::from(format!(r"\\?\UNC\{}", share));
+    }
+    let is_absolute =
+        path.as_bytes().get(1) == Some(&b':') && path.as_bytes().get(2) == Some(&b'\\');
+    if is_absolute {
+        PathBuf::from(format!(r"\\?\{}", path))
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+/// The device names that cannot be used as file or directory names on Windows (also with an
+/// extension, e.g. `nul.txt`)
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The first component of the path that is a reserved device name on Windows
+pub fn reserved_name(path: &Path) -> Option<String> {
+    path.components().find_map(|component| {
+        let name = component.as_os_str().to_string_lossy();
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+            .then(|| name.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_extended() {
+        let nested = format!(
+            r"C:\Users\runner\AppData\Local\Temp{}",
+            r"\nested".repeat(40)
+        );
+        assert!(nested.len() > MAX_PATH);
+        assert_eq!(
+            to_extended_length(Path::new(&nested)),
+            PathBuf::from(format!(r"\\?\{}", nested))
+        );
+        assert_eq!(
+            to_extended_length(Path::new("C:/prefix/Library/bin")),
+            PathBuf::from(r"\\?\C:\prefix\Library\bin")
+        );
+        assert_eq!(
+            to_extended_length(Path::new(r"\\server\share\prefix")),
+            PathBuf::from(r"\\?\UNC\server\share\prefix")
+        );
+        for unchanged in [r"\\?\C:\prefix", r"prefix\bin"] {
+            assert_eq!(
+                to_extended_length(Path::new(unchanged)),
+                PathBuf::from(unchanged)
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_names_are_found() {
+        assert_eq!(
+            reserved_name(Path::new("prefixes/nul.txt/env")),
+            Some("nul.txt".to_string())
+        );
+        assert_eq!(
+            reserved_name(Path::new("tmp/Com1")),
+            Some("Com1".to_string())
+        );
+        assert_eq!(reserved_name(Path::new("tmp/console/com10")), None);
+    }
+}